@@ -17,6 +17,11 @@ Thus, they require all the parameters to be known at the time the query is sent
 Function parameters cannot reference columns from the rest of your query.
 The only case when you can call a SQLPage function with a parameter that is not a constant is when it appears at the top level of a `SELECT` statement.
 For example, `SELECT sqlpage.url_encode(url) FROM t` is allowed because SQLPage can execute `SELECT url FROM t` and then apply the `url_encode` function to each value.
+
+On SQLite, the functions that do not depend on the current request
+(`url_encode`, `hash_password`, `random_string` and `version`)
+are also available as native SQLite functions, so they can be used anywhere in a query,
+for instance `SELECT CASE WHEN admin THEN sqlpage.url_encode(name) END FROM users`.
 ' as contents_md where $function IS NULL;
 
 select 'list' as component, 'SQLPage functions' as title where $function IS NULL;
@@ -351,6 +351,15 @@ select CAST(value AS integer) as id -- all values are transmitted by the browser
 from json_each($preferred_fruits); -- json_each returns a table with a "value" column for each element in the JSON array
 ```
 
+When you only need to check whether a value is among the selected ones, you can use the variable directly in an `IN` list.
+SQLPage binds each selected value separately, on all supported databases:
+
+```sql
+select name from fruits where CAST(id AS TEXT) in (:preferred_fruits);
+```
+
+In PostgreSQL, you can also write `where id = any(:preferred_fruits::int[])`.
+
 ### Example multiselect generated from a database table
 
 As an example, if you have a table of all possible options (`my_options(id int, label text)`),
@@ -49,6 +49,14 @@ When the uploaded file is larger than a few megabytes, it is not recommended to
 Instead, one can save the file to a permanent location on the server, and store the path to the file in the database.
 
 You can move the file to a permanent location using the [`sqlpage.persist_uploaded_file`](?function=persist_uploaded_file#function) function.
+
+### Multiple files
+
+When a file input has the `multiple` attribute, the user can upload several files in the same field.
+`sqlpage.uploaded_file_path`, `sqlpage.uploaded_file_name` and `sqlpage.uploaded_file_mime_type` return the information of the last file.
+[`sqlpage.uploaded_files`](?function=uploaded_files#function) describes all the files of the field,
+and [`sqlpage.persist_uploaded_files`](?function=persist_uploaded_files#function) saves all of them.
+
 ### Advanced file handling
 
 For more advanced file handling, such as uploading files to a cloud storage service,
@@ -31,6 +31,10 @@ select ''dynamic'' as component, sqlpage.run_sql(''common_header.sql'') as prope
  - **variables**: the included file will have access to the same variables (URL parameters, POST variables, etc.)
    as the calling file.
    If the included file changes the value of a variable or creates a new variable, the change will not be visible in the calling file.
+ - **`@include`**: to share variables in both directions, or to include header components such as [`cookie`](documentation.sql?component=cookie#component) or [`redirect`](documentation.sql?component=redirect#component),
+   write `-- @include ''common_header.sql''` between two statements instead.
+   The statements of the included file are inserted in place of the comment when the calling file is parsed,
+   and run exactly as if they had been written there. The path is relative to the web root.
 '
     );
 INSERT INTO sqlpage_function_parameters (
@@ -53,4 +57,10 @@ VALUES (
         'parameters',
         'Optional JSON object to pass as parameters to the included SQL file. The keys of the object will be available as variables in the included file. By default, the included file will have access to the same variables as the calling file.',
         'JSON'
+    ),(
+        'run_sql',
+        3,
+        'database',
+        'Optional name of one of the databases listed in the `databases` [configuration option](https://github.com/sqlpage/SQLPage/blob/main/configuration.md#multiple-databases). The included file runs against that database, and is parsed with its SQL dialect, instead of the database chosen by its own `-- @database` comment or its directory.',
+        'TEXT'
     );
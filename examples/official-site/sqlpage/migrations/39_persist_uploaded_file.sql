@@ -32,6 +32,38 @@ where id = (
 );
 ```
 
+#### Photo gallery
+
+A file input with the `multiple` attribute lets the user select several files at once.
+`persist_uploaded_file` only saves the last one:
+use [`sqlpage.persist_uploaded_files`](?function=persist_uploaded_files#function) to save all of them.
+
+##### `gallery_form.sql`
+
+```sql
+select ''form'' as component, ''add_photos.sql'' as action;
+select ''file'' as type, ''photos[]'' as name, TRUE as multiple, ''image/*'' as accept, ''Photos'' as label;
+```
+
+##### `add_photos.sql`
+
+```sql
+set paths = sqlpage.persist_uploaded_files(''photos[]'', ''gallery'', ''jpg,jpeg,png,webp'');
+insert into photos (path) select value from json_each($paths);
+```
+
+### Where files are stored
+
+By default, files are saved in a folder of the web root.
+The [`upload_storage`](https://github.com/sqlpage/SQLPage/blob/main/configuration.md) configuration option
+lets you store them somewhere else, without changing your SQL code:
+
+ - `database` saves them in the `sqlpage_files` table, replacing any file with the same path. A `contents` column of type `TEXT` can only store text files: use a binary column (`BLOB`, `BYTEA`, `VARBINARY(MAX)`) to store images and other binary files. This is useful when SQLPage runs on several servers, or on a server without a persistent disk.
+ - `s3` saves them in a bucket of an S3-compatible object storage service (Amazon S3, MinIO, Cloudflare R2, ...), configured with `s3_endpoint`, `s3_bucket`, `s3_region`, `s3_access_key_id` and `s3_secret_access_key`.
+   Only the files whose path starts with `s3_serve_prefix` (`uploads/` by default) are served from the bucket, so use a folder under this prefix.
+
+In all cases, the returned path can be used directly as a link or an image source:
+SQLPage serves files that are not found in the web root from the database, and then from the bucket.
 '
     );
 INSERT INTO sqlpage_function_parameters (
@@ -45,14 +77,14 @@ VALUES (
         'persist_uploaded_file',
         1,
         'file',
-        'Name of the form field containing the uploaded file. The current page must be referenced in the `action` property of a `form` component that contains a file input field.',
+        'Name of the form field containing the uploaded file. The current page must be referenced in the `action` property of a `form` component that contains a file input field. When several files were uploaded in the field, only the last one is persisted.',
         'TEXT'
     ),
     (
         'persist_uploaded_file',
         2,
         'destination_folder',
-        'Optional. Path to the folder where the file will be saved, relative to the web root (the root folder of your website files). By default, the file will be saved in the `uploads` folder.',
+        'Optional. Path to the folder where the file will be saved, relative to the web root (the root folder of your website files). By default, the file will be saved in the `uploads` folder, or in the folder set by the `uploads_directory` configuration option.',
         'TEXT'
     ),
     (
@@ -0,0 +1,45 @@
+-- Insert the stop component into the component table
+INSERT INTO
+    component (name, description, icon, introduced_in_version)
+VALUES
+    (
+        'stop',
+        'Stops the execution of the current SQL file. The statements after it are not run,
+and the page is rendered with everything that was returned before it.
+
+Add a `WHERE` clause to stop only under a condition. This makes authorization checks and "not found" branches a single line.
+
+The component has to be written literally as `''stop'' AS component` in the first column of a `SELECT`:
+it is recognized when the SQL file is parsed, and cannot be returned by the [dynamic](?component=dynamic#component) component.
+In a file included with `-- @include`, it stops the including file too.
+In a file executed with `sqlpage.run_sql`, it only stops that file.',
+        'player-stop',
+        '0.30.0'
+    );
+
+INSERT INTO example (component, description)
+VALUES (
+        'stop',
+        'Display a "not found" message and stop, when there is no product with the requested id:
+
+```sql
+select ''status_code'' as component, 404 as status where not exists (select 1 from products where id = $id);
+select ''alert'' as component, ''Product not found'' as title where not exists (select 1 from products where id = $id);
+select ''stop'' as component where not exists (select 1 from products where id = $id);
+
+select ''card'' as component;
+select name as title, description from products where id = $id;
+```
+'
+    ),
+    (
+        'stop',
+        'Only let administrators see the rest of the page:
+
+```sql
+select ''alert'' as component, ''Restricted'' as title, ''This page is reserved to administrators.'' as description
+where $role <> ''admin'';
+select ''stop'' as component where $role <> ''admin'';
+```
+'
+    );
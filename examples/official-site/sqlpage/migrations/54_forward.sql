@@ -0,0 +1,64 @@
+-- Insert the forward component into the component table
+INSERT INTO
+    component (name, description, icon, introduced_in_version)
+VALUES
+    (
+        'forward',
+        'Hands the current request to another SQL file, without a round-trip to the browser.
+
+The statements of the target file run in place of the rest of the current file,
+and the response sent to the browser is the one of the target file, including its status code and HTTP headers.
+Unlike the [redirect](?component=redirect#component) component, the target file has access to the same URL parameters,
+form fields (POST variables) and uploaded files as the current file, and the URL in the browser does not change.
+
+Like other header components, `forward` must be used before any component that displays data on the page.
+Headers and cookies set before the forward are kept.',
+        'arrow-forward-up',
+        '0.30.0'
+    );
+
+-- Insert the parameters for the forward component into the parameter table
+INSERT INTO
+    parameter (
+        component,
+        name,
+        description,
+        type,
+        top_level,
+        optional
+    )
+VALUES
+    (
+        'forward',
+        'path',
+        'The path of the SQL file that should handle the request, relative to the web root.',
+        'TEXT',
+        TRUE,
+        FALSE
+    ),
+    (
+        'forward',
+        'variables',
+        'A JSON object with additional variables to set before running the target file. They are available as `$name` in the target file, and replace the URL parameters of the same name.',
+        'JSON',
+        TRUE,
+        TRUE
+    );
+
+INSERT INTO example (component, description)
+VALUES (
+        'forward',
+        'Handle a form submission, and display the form again with an error message if the submitted data is invalid.
+The target file still receives the submitted form fields, so it can fill the form with them.
+
+```sql
+select ''forward'' as component,
+    ''edit_user.sql'' as path,
+    json_object(''error'', ''The email address is invalid'') as variables
+where :email not like ''%@%'';
+
+update users set email = :email where id = $id;
+select ''redirect'' as component, ''users.sql'' as link;
+```
+'
+    );
@@ -0,0 +1,116 @@
+-- Insert the cache component into the component table
+INSERT INTO
+    component (name, description, icon, introduced_in_version)
+VALUES
+    (
+        'cache',
+        'Stores the rendered page in memory, and serves it to the next visitors without running the rest of the SQL file, until it expires.
+
+This is useful for expensive pages, such as dashboards that compute statistics over a large table,
+that do not need to be recomputed for every visitor.
+
+The statements before the `cache` component are run on every request, so it should be placed at the top of the file.
+By default, a single version of the page is stored for all visitors, whatever their URL parameters.
+Use `vary` to store a different version of the page for each value of some parameters.
+
+The page is sent with a `Cache-Control: private` header, so that the browser of the user can cache it too,
+and it has an `ETag`, so that browsers can check if the page changed without downloading it again.
+Set `public` only for pages that are the same for all users, to let proxies and CDNs store them too.
+Only `GET` requests are cached, pages that contain errors are never cached, cookies set by the page are not sent to the other visitors,
+and each visitor gets a new [content security policy](https://github.com/sqlpage/SQLPage/blob/main/configuration.md) nonce.
+Pages that display or read the messages of the [`flash`](?component=flash) component are never cached.
+When the cache is full (see the `max_cached_responses` and `max_cached_responses_size` configuration options), the least recently used pages are removed.
+
+Use [`sqlpage.purge_cache`](functions.sql?function=purge_cache#function) to remove cached pages before they expire, for instance after the data they display changed.',
+        'database-export',
+        '0.30.0'
+    );
+
+-- Insert the parameters for the cache component into the parameter table
+INSERT INTO
+    parameter (
+        component,
+        name,
+        description,
+        type,
+        top_level,
+        optional
+    )
+VALUES
+    (
+        'cache',
+        'max_age',
+        'How long the page should be cached, in seconds.',
+        'INTEGER',
+        TRUE,
+        FALSE
+    ),
+    (
+        'cache',
+        'vary',
+        'A value, or a JSON array of values, that the page depends on. A different version of the page is cached for each distinct value.',
+        'JSON',
+        TRUE,
+        TRUE
+    ),
+    (
+        'cache',
+        'public',
+        'Allow shared caches, such as proxies and CDNs, to store the page and send it to other users. Only set this for pages that do not depend on the user: the value of `vary` is not known to shared caches.',
+        'BOOLEAN',
+        TRUE,
+        TRUE
+    );
+
+INSERT INTO example (component, description)
+VALUES (
+        'cache',
+        'Cache a dashboard for 5 minutes, with a different version for each region:
+
+```sql
+select ''cache'' as component, 300 as max_age, $region as vary;
+
+select ''chart'' as component, ''Sales'' as title;
+select day as x, sum(amount) as y from sales where region = $region group by day;
+```
+'
+    );
+
+INSERT INTO sqlpage_functions (
+        "name",
+        "introduced_in_version",
+        "icon",
+        "description_md"
+    )
+VALUES (
+        'purge_cache',
+        '0.30.0',
+        'database-x',
+        'Removes the pages stored by the [`cache`](documentation.sql?component=cache#component) component,
+so that they are computed again on the next visit. Returns the number of removed pages.
+
+### Example
+
+Remove the cached versions of the sales dashboard when a new sale is recorded:
+
+```sql
+insert into sales (day, region, amount) values (:day, :region, :amount);
+select ''redirect'' as component, ''dashboard.sql?purged='' || sqlpage.purge_cache(''/dashboard.sql'') as link;
+```
+'
+    );
+
+INSERT INTO sqlpage_function_parameters (
+        "function",
+        "index",
+        "name",
+        "description_md",
+        "type"
+    )
+VALUES (
+        'purge_cache',
+        1,
+        'path',
+        'The URL path of the page, as returned by [`sqlpage.path()`](functions.sql?function=path#function), for instance `/dashboard.sql`. All the versions of the page are removed, whatever the value of `vary`. If NULL, all the cached pages are removed.',
+        'TEXT'
+    );
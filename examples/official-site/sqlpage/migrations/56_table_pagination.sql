@@ -0,0 +1,29 @@
+INSERT INTO example(component, description, properties) VALUES
+    (
+    'table',
+    '# Server-side pagination
+
+When a table has too many rows to be displayed on a single page,
+add a `-- @paginate` comment right before the query that returns the rows of the table.
+SQLPage will only fetch the rows of the current page from the database,
+and display links to the other pages below the table.
+
+```sql
+SELECT ''table'' AS component;
+-- @paginate 20
+SELECT name, email FROM users ORDER BY name;
+```
+
+The comment contains the number of rows per page, and optionally the name of the URL parameter
+that contains the current page number, which is `page` by default: `-- @paginate 50 users_page`.
+The other URL parameters are kept in the links to the other pages,
+so pagination works together with search forms.
+
+SQLPage adds a `LIMIT` and an `OFFSET` to the query (`OFFSET ... FETCH` on SQL Server), and runs a second query
+that counts the rows on all the pages. The query must not already contain a `LIMIT`,
+and should have an `ORDER BY` clause, so that the rows are always returned in the same order.
+
+Custom components receive the page metadata in the `@pagination` variable when they are closed:
+its `page`, `page_count`, `total_rows`, `previous_link`, `next_link` and `pages` fields can be used in the footer of the template.',
+    json('[{"component":"table"}, {"name": "Ophir", "email": "ophir@example.com"}, {"name": "Linus", "email": "linus@example.com"}]')
+    );
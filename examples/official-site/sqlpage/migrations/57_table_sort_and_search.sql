@@ -0,0 +1,27 @@
+INSERT INTO example(component, description, properties) VALUES
+    (
+    'table',
+    '# Server-side sorting and searching
+
+By default, the `sort` and `search` options of the table sort and filter the rows in the browser,
+so they only see the rows that were sent to the page.
+For large tables, add a `-- @sort_and_search` comment before the query, to let the database do the work.
+
+```sql
+SELECT ''table'' AS component, TRUE AS sort, TRUE AS search;
+-- @sort_and_search
+-- @paginate 20
+SELECT name, email, created_at FROM users ORDER BY created_at DESC;
+```
+
+SQLPage wraps the query in `SELECT * FROM (...) WHERE ... ORDER BY ...`.
+Clicking on a column header reloads the page with a `sort` URL parameter containing the name of the column,
+prefixed with `-` for descending order, as in `?sort=-created_at`.
+Typing in the search box reloads the page with a `search` URL parameter,
+and only the rows in which one of the columns contains the searched text (ignoring case) are returned.
+
+The sorting and searching only apply to the columns that are named in the query, so `SELECT *` cannot be used.
+The values of the URL parameters are always bound as query parameters, and the `sort` parameter can only select
+one of the columns of the query, so they cannot be used to inject SQL.',
+    json('[{"component":"table", "sort": true, "search": true}, {"name": "Ophir", "email": "ophir@example.com"}, {"name": "Linus", "email": "linus@example.com"}]')
+    );
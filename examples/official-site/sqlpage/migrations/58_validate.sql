@@ -0,0 +1,152 @@
+-- Insert the validate component into the component table
+INSERT INTO
+    component (name, description, icon, introduced_in_version)
+VALUES
+    (
+        'validate',
+        'Checks the values submitted with a form on the server, before the rest of the SQL file is executed.
+
+The validation rules of the [`form`](documentation.sql?component=form#component) component, such as `required`, `min`, `max` or `pattern`,
+are only enforced by the browser, and can easily be bypassed by a malicious user.
+Place the `validate` component at the top of the page that receives the form,
+followed by one row per field, with the same properties as the fields of the form.
+
+When the submitted values are valid, the page is executed normally.
+When they are not, none of the statements that follow the `validate` component are executed.
+Instead, the request is sent to the page that contains the form, as with the [`forward`](documentation.sql?component=forward#component) component.
+The form is displayed again, with the values the user submitted, and an error message below each invalid field.
+Passwords and files are never sent back to the browser.
+
+On the form page, the submitted variables (`:name`) are empty, so the form is displayed as if it was opened for the first time.
+This makes it possible to use the same page to display the form and to handle it.
+
+The `validate` component must be the first statement of the file, so that nothing runs before the submission is validated.
+A file that has other statements before the `validate` component is rejected with an error.
+Its properties and fields can use the request parameters (`:name` and `$name`), but not the variables that the file `SET`s.
+The fields of the `validate` component must be written with constant values or variables, not with a query that uses `FROM` or calls functions:
+such a query is an error, unless it starts a new component.
+Only `POST` requests are validated.
+
+The following checks are supported:
+ - `required`: the field must not be empty,
+ - `type`: the value of `number`, `range`, `email`, `url` and `date` fields must have the right format,
+ - `min` and `max`: the value must be in the range. Numbers are compared as numbers, and other values as text, which works for dates and times,
+ - `minlength` and `maxlength`: the number of characters of the value,
+ - `pattern`: a regular expression that must match the entire value.
+   The same syntax as in HTML can be used, except for lookarounds (`(?=...)`, `(?!...)`) and backreferences (`\1`),
+   which are not supported on the server. A pattern that uses them makes the page fail with an error.',
+        'checklist',
+        '0.30.0'
+    );
+
+INSERT INTO
+    parameter (
+        component,
+        name,
+        description,
+        type,
+        top_level,
+        optional
+    )
+VALUES
+    (
+        'validate',
+        'form',
+        'The path of the SQL file that contains the form, relative to the root of the website, such as `users/edit.sql`. When the submitted values are invalid, the request is sent to this file. If this is not set, invalid values produce an error page.',
+        'TEXT',
+        TRUE,
+        TRUE
+    ),
+    (
+        'validate',
+        'name',
+        'The name of the field, as in the form component.',
+        'TEXT',
+        FALSE,
+        FALSE
+    ),
+    (
+        'validate',
+        'type',
+        'The type of the field, as in the form component. The values of `number`, `range`, `email`, `url` and `date` fields are checked.',
+        'TEXT',
+        FALSE,
+        TRUE
+    ),
+    (
+        'validate',
+        'required',
+        'Reject the form when this field is empty.',
+        'BOOLEAN',
+        FALSE,
+        TRUE
+    ),
+    (
+        'validate',
+        'min',
+        'The minimum value of the field.',
+        'TEXT',
+        FALSE,
+        TRUE
+    ),
+    (
+        'validate',
+        'max',
+        'The maximum value of the field.',
+        'TEXT',
+        FALSE,
+        TRUE
+    ),
+    (
+        'validate',
+        'minlength',
+        'The minimum number of characters of the field.',
+        'INTEGER',
+        FALSE,
+        TRUE
+    ),
+    (
+        'validate',
+        'maxlength',
+        'The maximum number of characters of the field.',
+        'INTEGER',
+        FALSE,
+        TRUE
+    ),
+    (
+        'validate',
+        'pattern',
+        'A regular expression that the entire value must match, as in the form component.',
+        'TEXT',
+        FALSE,
+        TRUE
+    ),
+    (
+        'validate',
+        'error_message',
+        'The message displayed below the field when its value is invalid, instead of the default English message.',
+        'TEXT',
+        FALSE,
+        TRUE
+    );
+
+INSERT INTO example (component, description)
+VALUES (
+        'validate',
+        'A page that displays a registration form, and saves the new user when the form is valid.
+When the email address is invalid, or the user is too young, the form is displayed again, with the errors.
+
+```sql
+select ''validate'' as component, ''register.sql'' as form;
+select ''email'' as name, ''email'' as type, true as required;
+select ''age'' as name, ''number'' as type, 18 as min, ''You must be an adult to register'' as error_message;
+
+insert into users (email, age) select :email, :age where :email is not null;
+select ''redirect'' as component, ''users.sql'' as link where :email is not null;
+
+select ''form'' as component, ''Register'' as title;
+select ''email'' as name, ''email'' as type, true as required;
+select ''age'' as name, ''number'' as type, 18 as min;
+```
+'
+    );
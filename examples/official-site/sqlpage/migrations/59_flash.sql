@@ -0,0 +1,101 @@
+-- Insert the flash component into the component table
+INSERT INTO
+    component (name, description, icon, introduced_in_version)
+VALUES
+    (
+        'flash',
+        'Stores a message to display on the next page the user visits, usually after a [`redirect`](documentation.sql?component=redirect#component).
+
+After handling a form submission, it is common to redirect the user to another page.
+The `flash` component lets you tell the user what happened, such as "Your changes were saved", without adding the message to the URL of the next page.
+
+The messages are displayed once, at the top of the next page that uses the [`shell`](documentation.sql?component=shell#component) component, and then removed.
+They are stored in a cookie, signed with the [`cookie_secret`](https://github.com/sqlpage/SQLPage/blob/main/configuration.md) of the server, so that users cannot forge messages.
+
+Like other header components, `flash` must be used before any component that displays data.
+To display the messages in a different way, for instance in a page that uses the `json` component, use [`sqlpage.flash_messages()`](functions.sql?function=flash_messages#function).',
+        'message-check',
+        '0.30.0'
+    );
+
+INSERT INTO
+    parameter (
+        component,
+        name,
+        description,
+        type,
+        top_level,
+        optional
+    )
+VALUES
+    (
+        'flash',
+        'title',
+        'The title of the message.',
+        'TEXT',
+        TRUE,
+        TRUE
+    ),
+    (
+        'flash',
+        'description',
+        'The text of the message.',
+        'TEXT',
+        TRUE,
+        TRUE
+    ),
+    (
+        'flash',
+        'color',
+        'The color of the message, such as "success", "danger", "warning" or "info". Defaults to "success".',
+        'COLOR',
+        TRUE,
+        TRUE
+    ),
+    (
+        'flash',
+        'icon',
+        'An icon to display next to the message.',
+        'ICON',
+        TRUE,
+        TRUE
+    );
+
+INSERT INTO example (component, description)
+VALUES (
+        'flash',
+        'Save a product, and tell the user about it on the product list:
+
+```sql
+update products set price = :price where id = $id;
+select ''flash'' as component, ''Saved'' as title, ''The new price is '' || :price as description;
+select ''redirect'' as component, ''products.sql'' as link;
+```
+'
+    );
+
+INSERT INTO sqlpage_functions (
+        "name",
+        "introduced_in_version",
+        "icon",
+        "description_md"
+    )
+VALUES (
+        'flash_messages',
+        '0.30.0',
+        'message-check',
+        'Returns the messages set by the [`flash`](documentation.sql?component=flash#component) component on the previous page, as a JSON array.
+Returns NULL when there is no message.
+
+By default, the `shell` component displays these messages at the top of the page.
+This function is useful to display them in a different way, or to return them from an API.
+Reading the messages removes them:
+on a page that calls this function, the shell does not display them, and they are not shown again on the next page.
+
+### Example
+
+```sql
+select ''json'' as component, sqlpage.flash_messages() as contents;
+```
+'
+    );
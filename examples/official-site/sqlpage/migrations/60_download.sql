@@ -0,0 +1,106 @@
+-- Insert the download component into the component table
+INSERT INTO
+    component (name, description, icon, introduced_in_version)
+VALUES
+    (
+        'download',
+        'Answers the request with a file, instead of a web page.
+
+The file can come from the database, for instance a PDF stored in a BLOB column,
+from text generated by SQL, such as a CSV export,
+or from a file of the website, such as one saved with [`sqlpage.persist_uploaded_file`](functions.sql?function=persist_uploaded_file#function).
+
+Like the [`json`](documentation.sql?component=json#component) component, `download` must be the first component of the page,
+and the rest of the SQL file is not executed.
+
+Binary columns (`BLOB` in SQLite and MySQL, `BYTEA` in PostgreSQL, `VARBINARY` in SQL Server) are returned by SQLPage as
+[data URLs](https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/Data_URLs), which can be passed directly to the `contents` property.
+Files from the website are streamed from the disk, so they can be large.',
+        'download',
+        '0.30.0'
+    );
+
+INSERT INTO
+    parameter (
+        component,
+        name,
+        description,
+        type,
+        top_level,
+        optional
+    )
+VALUES
+    (
+        'download',
+        'contents',
+        'The contents of the file: a BLOB column, a data URL such as `data:application/pdf;base64,JVBERi0x...`, or text.',
+        'TEXT',
+        TRUE,
+        TRUE
+    ),
+    (
+        'download',
+        'file',
+        'The path of a file to send, as returned by `sqlpage.persist_uploaded_file`. Only files of the `uploads_directory` [configuration option](https://github.com/sqlpage/SQLPage/blob/main/configuration.md), `uploads` by default, can be sent. They are read from where `upload_storage` says uploaded files are stored: the web root, the `sqlpage_files` table, or the S3 bucket. Use either `contents` or `file`.',
+        'TEXT',
+        TRUE,
+        TRUE
+    ),
+    (
+        'download',
+        'filename',
+        'The name under which the browser saves the file.',
+        'TEXT',
+        TRUE,
+        TRUE
+    ),
+    (
+        'download',
+        'content_type',
+        'The MIME type of the file, such as `application/pdf`. By default, it is taken from the data URL, or guessed from the extension of the file name.',
+        'TEXT',
+        TRUE,
+        TRUE
+    ),
+    (
+        'download',
+        'inline',
+        'Display the file in the browser, for instance in its PDF viewer, instead of saving it. Defaults to false.',
+        'BOOLEAN',
+        TRUE,
+        TRUE
+    );
+
+INSERT INTO example (component, description)
+VALUES
+    (
+        'download',
+        'Download a document stored in a BLOB column of the database:
+
+```sql
+select ''download'' as component, name as filename, contents
+from documents where id = $id;
+```
+'
+    ),
+    (
+        'download',
+        'Export a table as a CSV file, generated in SQL:
+
+```sql
+select ''download'' as component, ''users.csv'' as filename,
+    ''name,email'' || char(10) || group_concat(name || '','' || email, char(10)) as contents
+from users;
+```
+'
+    ),
+    (
+        'download',
+        'Open a PDF that was uploaded with `sqlpage.persist_uploaded_file` in the browser:
+
+```sql
+select ''download'' as component, path as file, true as inline
+from invoices where id = $id;
+```
+'
+    );
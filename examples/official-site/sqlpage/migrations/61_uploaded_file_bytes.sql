@@ -0,0 +1,58 @@
+INSERT INTO sqlpage_functions (
+        "name",
+        "introduced_in_version",
+        "icon",
+        "description_md"
+    )
+VALUES (
+        'uploaded_file_bytes',
+        '0.30.0',
+        'file-database',
+        'Returns the contents of an uploaded file, as binary data.
+Returns NULL when no file was uploaded in the given field.
+
+The contents are sent to the database as a binary parameter, so they can be stored in a binary column
+(`BLOB` in SQLite and MySQL, `BYTEA` in PostgreSQL, `VARBINARY(MAX)` in SQL Server) without any conversion.
+Unlike a [data URL](?function=read_file_as_data_url#function), the stored file is not larger than the original,
+and does not need to be decoded when it is read.
+
+### Example: storing uploaded pictures in the database
+
+```sql
+insert into pictures (title, mime_type, content)
+values (
+    :title,
+    sqlpage.uploaded_file_mime_type(''picture''),
+    sqlpage.uploaded_file_bytes(''picture'')
+);
+```
+
+The stored pictures can then be sent back to the browser with the [`download`](documentation.sql?component=download#component) component:
+
+```sql
+select ''download'' as component, content as contents, mime_type as content_type
+from pictures where id = $id;
+```
+
+### Limitations
+
+The binary contents can only be used directly as a value sent to the database,
+as in the example above. They cannot be passed to other `sqlpage.` functions,
+which work with text.
+'
+    );
+
+INSERT INTO sqlpage_function_parameters (
+        "function",
+        "index",
+        "name",
+        "description_md",
+        "type"
+    )
+VALUES (
+        'uploaded_file_bytes',
+        1,
+        'name',
+        'Name of the file input field in the form.',
+        'TEXT'
+    );
@@ -0,0 +1,97 @@
+INSERT INTO sqlpage_functions (
+        "name",
+        "introduced_in_version",
+        "icon",
+        "description_md"
+    )
+VALUES (
+        'uploaded_files',
+        '0.30.0',
+        'files',
+        'Returns a JSON array that describes all the files uploaded in a form field with the `multiple` attribute.
+Returns NULL when no file was uploaded in the field.
+
+Each element of the array is an object with the following properties:
+ - `name`: the name of the file on the computer of the user, as returned by [`sqlpage.uploaded_file_name`](?function=uploaded_file_name#function),
+ - `path`: the path of the temporary file on the server, as returned by [`sqlpage.uploaded_file_path`](?function=uploaded_file_path#function),
+ - `mime_type`: the type of the file, as returned by [`sqlpage.uploaded_file_mime_type`](?function=uploaded_file_mime_type#function).
+
+The files are in the order in which they were uploaded, which is also the order of the paths returned by
+[`sqlpage.persist_uploaded_files`](?function=persist_uploaded_files#function).
+
+### Example: list the uploaded files
+
+```sql
+select ''list'' as component, ''Uploaded files'' as title;
+select value->>''name'' as title, value->>''mime_type'' as description
+from json_each(sqlpage.uploaded_files(''documents[]''));
+```
+'
+    ),
+    (
+        'persist_uploaded_files',
+        '0.30.0',
+        'device-floppy',
+        'Persists all the files uploaded in a form field with the `multiple` attribute,
+and returns a JSON array with their paths, in the order in which they were uploaded.
+Returns NULL when no file was uploaded in the field.
+
+The files are saved like with [`sqlpage.persist_uploaded_file`](?function=persist_uploaded_file#function).
+All the files are checked before any of them is saved:
+if one of them does not have an allowed extension, none of them is saved, and the page shows an error.
+
+### Example: photo gallery
+
+#### `gallery_form.sql`
+
+```sql
+select ''form'' as component, ''add_photos.sql'' as action;
+select ''file'' as type, ''photos[]'' as name, TRUE as multiple, ''image/*'' as accept, ''Photos'' as label;
+```
+
+#### `add_photos.sql`
+
+```sql
+insert into photos (path)
+select value from json_each(sqlpage.persist_uploaded_files(''photos[]'', ''uploads/gallery'', ''jpg,jpeg,png,webp''));
+
+select ''redirect'' as component, ''gallery.sql'' as link;
+```
+'
+    );
+
+INSERT INTO sqlpage_function_parameters (
+        "function",
+        "index",
+        "name",
+        "description_md",
+        "type"
+    )
+VALUES (
+        'uploaded_files',
+        1,
+        'name',
+        'Name of the file input field in the form, including the `[]` if the field name has them.',
+        'TEXT'
+    ),
+    (
+        'persist_uploaded_files',
+        1,
+        'file',
+        'Name of the file input field in the form, including the `[]` if the field name has them.',
+        'TEXT'
+    ),
+    (
+        'persist_uploaded_files',
+        2,
+        'destination_folder',
+        'Optional. Path to the folder where the files will be saved, relative to the web root. By default, the files are saved in the `uploads` folder.',
+        'TEXT'
+    ),
+    (
+        'persist_uploaded_files',
+        3,
+        'allowed_extensions',
+        'Optional. Comma-separated list of allowed file extensions, with the same default as in `sqlpage.persist_uploaded_file`.',
+        'TEXT'
+    );
@@ -27,6 +27,12 @@ function sqlpage_card() {
 function sqlpage_table(){
     // Tables
     for (const r of document.querySelectorAll("[data-pre-init=table]")) {
+        const server_side = r.querySelector("[data-sort-and-search]");
+        if (server_side) {
+            sqlpage_server_side_table(r, server_side.dataset);
+            r.removeAttribute("data-pre-init");
+            continue;
+        }
         new List(r, {
             valueNames: [...r.getElementsByTagName("th")].map(t => t.textContent),
             searchDelay: 100,
@@ -37,6 +43,29 @@ function sqlpage_table(){
     }
 }
 
+// Tables whose query is marked with -- @sort_and_search are sorted and filtered by the server:
+// sorting and searching reload the page with new URL parameters
+function sqlpage_server_side_table(r, { sort, search, pageVariable }) {
+    function reload(name, value) {
+        const url = new URL(window.location.href);
+        if (value) url.searchParams.set(name, value);
+        else url.searchParams.delete(name);
+        if (pageVariable) url.searchParams.delete(pageVariable);
+        window.location.href = url.toString();
+    }
+    for (const button of r.querySelectorAll("button.sort")) {
+        const column = button.dataset.sort;
+        if (sort === column) button.classList.add("asc");
+        if (sort === "-" + column) button.classList.add("desc");
+        button.addEventListener("click", () => reload("sort", sort === column ? "-" + column : column));
+    }
+    const input = r.querySelector("input.search");
+    if (input) {
+        input.value = search;
+        input.addEventListener("change", () => reload("search", input.value));
+    }
+}
+
 function sqlpage_select_dropdown(){
   const selects = document.querySelectorAll("[data-pre-init=select-dropdown]");
   if (!selects.length) return;
@@ -4,6 +4,7 @@ use config::Config;
 use percent_encoding::AsciiSet;
 use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::{Path, PathBuf};
 
@@ -108,6 +109,28 @@ impl AppConfig {
                 "Database connection acquire timeout must be positive"
             ));
         }
+        for (directory, name) in &self.database_directories {
+            if !self.databases.contains_key(name) {
+                return Err(anyhow::anyhow!(
+                    "The directory {directory:?} should use the database named {name:?}, but it is not in the \"databases\" configuration option"
+                ));
+            }
+        }
+        if self.max_cached_files == 0 {
+            return Err(anyhow::anyhow!(
+                "Maximum number of cached files must be positive"
+            ));
+        }
+        if self.database_files_poll_interval_seconds <= 0.0 {
+            return Err(anyhow::anyhow!(
+                "Database files poll interval must be positive"
+            ));
+        }
+        if let Some(max_query_duration) = self.max_query_duration_seconds {
+            if max_query_duration <= 0.0 {
+                return Err(anyhow::anyhow!("Maximum query duration must be positive"));
+            }
+        }
         if let Some(max_connections) = self.max_database_pool_connections {
             if max_connections == 0 {
                 return Err(anyhow::anyhow!(
@@ -145,12 +168,25 @@ pub fn load_from_env() -> anyhow::Result<AppConfig> {
 }
 
 #[derive(Debug, Deserialize, PartialEq, Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct AppConfig {
     #[serde(default = "default_database_url")]
     pub database_url: String,
     /// A separate field for the database password. If set, this will override any password specified in the `database_url`.
     #[serde(default)]
     pub database_password: Option<String>,
+    /// URLs of read-only replicas of the main database.
+    /// Statements that do not modify data are sent to one of the replicas, in turn.
+    #[serde(default)]
+    pub database_replica_urls: Vec<String>,
+    /// Additional databases, by name. A SQL file runs against one of them instead of the main database
+    /// when it starts with a `-- @database name` comment.
+    #[serde(default)]
+    pub databases: HashMap<String, String>,
+    /// Directories of the web root whose SQL files run against one of the `databases`, by name,
+    /// unless they start with their own `-- @database` comment.
+    #[serde(default)]
+    pub database_directories: HashMap<String, String>,
     pub max_database_pool_connections: Option<u32>,
     pub database_connection_idle_timeout_seconds: Option<f64>,
     pub database_connection_max_lifetime_seconds: Option<f64>,
@@ -158,6 +194,11 @@ pub struct AppConfig {
     #[serde(default)]
     pub sqlite_extensions: Vec<String>,
 
+    /// Whether to add regular expression, unicode normalization and accent-insensitive comparison
+    /// functions to `SQLite` databases.
+    #[serde(default = "default_sqlite_text_functions")]
+    pub sqlite_text_functions: bool,
+
     #[serde(default, deserialize_with = "deserialize_socket_addr")]
     pub listen_on: Option<SocketAddr>,
     pub port: Option<u16>,
@@ -174,6 +215,10 @@ pub struct AppConfig {
     #[serde(default = "default_database_connection_acquire_timeout_seconds")]
     pub database_connection_acquire_timeout_seconds: f64,
 
+    /// Maximum number of seconds a single SQL statement is allowed to run before it is cancelled.
+    /// By default, statements can run indefinitely.
+    pub max_query_duration_seconds: Option<f64>,
+
     /// The directory where the .sql files are located. Defaults to the current directory.
     #[serde(default = "default_web_root")]
     pub web_root: PathBuf,
@@ -192,6 +237,41 @@ pub struct AppConfig {
     #[serde(default = "default_max_file_size")]
     pub max_uploaded_file_size: usize,
 
+    /// Where `sqlpage.persist_uploaded_file` saves files: in the web root, in the `sqlpage_files` table,
+    /// or in an S3-compatible bucket. Files that are not found in the web root are served from there.
+    #[serde(default)]
+    pub upload_storage: UploadStorage,
+
+    /// Folder where `sqlpage.persist_uploaded_file` saves files by default.
+    /// The `download` component only sends files from this folder.
+    #[serde(default = "default_uploads_directory")]
+    pub uploads_directory: String,
+
+    /// URL of an S3-compatible object storage service, such as `https://s3.eu-west-1.amazonaws.com`,
+    /// or `http://localhost:9000` for a local `MinIO` server.
+    pub s3_endpoint: Option<String>,
+
+    /// Name of the bucket where uploaded files are stored when `upload_storage` is `s3`.
+    pub s3_bucket: Option<String>,
+
+    /// Only the static files whose path starts with this prefix are looked up in the bucket,
+    /// so that other missing files do not cost a request to the bucket.
+    /// Defaults to `uploads/`, the default folder of `sqlpage.persist_uploaded_file`.
+    #[serde(default = "default_s3_serve_prefix")]
+    pub s3_serve_prefix: String,
+
+    /// Region of the S3 bucket. Defaults to `us-east-1`, which is also what `MinIO` expects by default.
+    #[serde(default = "default_s3_region")]
+    pub s3_region: String,
+
+    /// Access key used to sign the requests to the bucket.
+    /// Defaults to the `AWS_ACCESS_KEY_ID` environment variable.
+    pub s3_access_key_id: Option<String>,
+
+    /// Secret key used to sign the requests to the bucket.
+    /// Defaults to the `AWS_SECRET_ACCESS_KEY` environment variable.
+    pub s3_secret_access_key: Option<String>,
+
     /// A domain name to use for the HTTPS server. If this is set, the server will perform all the necessary
     /// steps to set up an HTTPS server automatically. All you need to do is point your domain name to the
     /// server's IP address.
@@ -232,14 +312,57 @@ pub struct AppConfig {
     #[serde(default = "default_max_pending_rows")]
     pub max_pending_rows: usize,
 
+    /// Maximum number of parsed SQL files, and of templates, kept in memory.
+    /// The least recently used ones are evicted when there are more.
+    #[serde(default = "default_max_cached_files")]
+    pub max_cached_files: usize,
+
+    /// Maximum total size, in bytes, of the sources of the SQL files, and of the templates, kept in memory.
+    #[serde(default = "default_max_cached_files_size")]
+    pub max_cached_files_size: usize,
+
+    /// Maximum number of pages stored by the `cache` component.
+    /// The least recently used ones are evicted when there are more.
+    #[serde(default = "default_max_cached_responses")]
+    pub max_cached_responses: usize,
+
+    /// Maximum total size, in bytes, of the pages stored by the `cache` component.
+    #[serde(default = "default_max_cached_responses_size")]
+    pub max_cached_responses_size: usize,
+
+    /// Parse all the SQL files and templates when the server starts, instead of when they are first used,
+    /// and warn about the ones that contain errors, or refuse to start.
+    #[serde(default)]
+    pub precompile_files: Precompilation,
+
+    /// Watch the web root and the templates directory for changes, instead of checking
+    /// the modification time of cached files when they are used.
+    #[serde(default)]
+    pub watch_files: bool,
+
+    /// When `watch_files` is enabled, how often to check whether the files
+    /// stored in the `sqlpage_files` table changed.
+    #[serde(default = "default_database_files_poll_interval_seconds")]
+    pub database_files_poll_interval_seconds: f64,
+
     /// Whether to compress the http response body when the client supports it.
     #[serde(default = "default_compress_responses")]
     pub compress_responses: bool,
 
+    /// Whether to serve the precompressed `.br` version of a static file when the client supports it.
+    /// When disabled, only the `.gz` version is used.
+    #[serde(default = "default_compress_brotli")]
+    pub compress_brotli: bool,
+
     /// Content-Security-Policy header to send to the client.
     /// If not set, a default policy allowing scripts from the same origin is used and from jsdelivr.net
     pub content_security_policy: Option<String>,
 
+    /// The secret used to sign the cookies that `SQLPage` sets itself, such as the ones of the `flash` component.
+    /// A random secret is generated at startup when it is not set.
+    #[serde(default)]
+    pub cookie_secret: Option<String>,
+
     /// Whether `sqlpage.fetch` should load trusted certificates from the operating system's certificate store
     /// By default, it loads Mozilla's root certificates that are embedded in the `SQLPage` binary, or the ones pointed to by the
     /// `SSL_CERT_FILE` and `SSL_CERT_DIR` environment variables.
@@ -322,6 +445,7 @@ fn env_config() -> config::Environment {
         .try_parsing(true)
         .list_separator(" ")
         .with_list_parse_key("sqlite_extensions")
+        .with_list_parse_key("database_replica_urls")
 }
 
 fn deserialize_socket_addr<'de, D: Deserializer<'de>>(
@@ -477,6 +601,46 @@ fn default_compress_responses() -> bool {
     true
 }
 
+fn default_sqlite_text_functions() -> bool {
+    true
+}
+
+fn default_max_cached_files() -> usize {
+    10_000
+}
+
+fn default_max_cached_files_size() -> usize {
+    100 * 1024 * 1024
+}
+
+fn default_max_cached_responses() -> usize {
+    1000
+}
+
+fn default_max_cached_responses_size() -> usize {
+    50 * 1024 * 1024
+}
+
+fn default_database_files_poll_interval_seconds() -> f64 {
+    5.0
+}
+
+fn default_compress_brotli() -> bool {
+    true
+}
+
+fn default_uploads_directory() -> String {
+    "uploads".to_string()
+}
+
+fn default_s3_serve_prefix() -> String {
+    "uploads/".to_string()
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
 fn default_system_root_ca_certificates() -> bool {
     std::env::var("SSL_CERT_FILE").is_ok_and(|x| !x.is_empty())
         || std::env::var("SSL_CERT_DIR").is_ok_and(|x| !x.is_empty())
@@ -495,6 +659,32 @@ impl DevOrProd {
     }
 }
 
+/// Where uploaded files are persisted
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadStorage {
+    /// In a folder of the web root
+    #[default]
+    Local,
+    /// In the `sqlpage_files` table
+    Database,
+    /// In an S3-compatible bucket
+    S3,
+}
+
+/// What to do with the SQL files and templates when the server starts
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Precompilation {
+    /// Parse files when they are first used
+    #[default]
+    Off,
+    /// Parse all files, and log the errors they contain
+    Warn,
+    /// Parse all files, and refuse to start if one of them contains an error
+    Abort,
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::AppConfig;
@@ -1,3 +1,4 @@
+use crate::webserver::error_with_status::is_not_found;
 use crate::webserver::ErrorWithStatus;
 use crate::AppState;
 use actix_web::http::StatusCode;
@@ -11,28 +12,41 @@ use std::sync::atomic::{
     Ordering::{Acquire, Release},
 };
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use tokio::sync::RwLock;
 
 /// The maximum time in milliseconds that a file can be cached before its freshness is checked
 /// (in production mode)
 const MAX_STALE_CACHE_MS: u64 = 150;
 
+/// How long a file that does not exist is remembered as missing (in production mode),
+/// so that looking for `404.sql` handlers does not hit the disk or the database on every request
+const NOT_FOUND_CACHE_MS: u64 = 1000;
+
 #[derive(Default)]
 struct Cached<T> {
     last_checked_at: AtomicU64,
+    /// Used to evict the least recently used files when the cache is full
+    last_used_at: AtomicU64,
+    /// Size of the source of the file, in bytes
+    size: usize,
     content: Arc<T>,
 }
 
 impl<T> Cached<T> {
-    fn new(content: T) -> Self {
+    fn new(content: T, size: usize) -> Self {
         let s = Self {
             last_checked_at: AtomicU64::new(0),
+            last_used_at: AtomicU64::new(Self::elapsed()),
+            size,
             content: Arc::new(content),
         };
         s.update_check_time();
         s
     }
+    fn mark_used(&self) {
+        self.last_used_at.store(Self::elapsed(), Release);
+    }
     fn last_check_time(&self) -> DateTime<Utc> {
         self.last_checked_at
             .load(Acquire)
@@ -58,20 +72,161 @@ impl<T> Cached<T> {
             .saturating_add(MAX_STALE_CACHE_MS)
             < Self::elapsed()
     }
+    /// Whether the entry was last checked more than `interval` ago
+    fn checked_before(&self, interval: Duration) -> bool {
+        let intervals = interval.as_millis() / u128::from(MAX_STALE_CACHE_MS);
+        let intervals = u64::try_from(intervals).unwrap_or(u64::MAX);
+        self.last_checked_at.load(Acquire).saturating_add(intervals) < Self::elapsed()
+    }
     /// Creates a new cached entry with the same content but a new check time set to now
     fn make_fresh(&self) -> Self {
         Self {
             last_checked_at: AtomicU64::from(Self::elapsed()),
+            last_used_at: AtomicU64::from(Self::elapsed()),
+            size: self.size,
             content: Arc::clone(&self.content),
         }
     }
 }
 
+struct Entries<T> {
+    files: HashMap<PathBuf, Cached<T>>,
+    /// Paths that were recently looked up but not found, with the time of the lookup
+    not_found: HashMap<PathBuf, u64>,
+    /// Total size of the sources of the cached files, in bytes
+    size: usize,
+    /// Incremented by every invalidation, so that the files that were being loaded
+    /// while one of them changed are not cached
+    invalidations: u64,
+}
+
+impl<T> Default for Entries<T> {
+    fn default() -> Self {
+        Self {
+            files: HashMap::new(),
+            not_found: HashMap::new(),
+            size: 0,
+            invalidations: 0,
+        }
+    }
+}
+
+impl<T> Entries<T> {
+    fn insert(&mut self, path: PathBuf, value: Cached<T>, max_files: usize, max_size: usize) {
+        self.not_found.remove(&path);
+        self.size += value.size;
+        if let Some(old) = self.files.insert(path, value) {
+            self.size -= old.size;
+        }
+        if self.files.len() > max_files || self.size > max_size {
+            self.evict(max_files, max_size);
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(old) = self.files.remove(path) {
+            self.size -= old.size;
+        }
+    }
+
+    /// Caches a file that was loaded when the cache had seen `invalidations` invalidations.
+    /// A file that was loaded before an invalidation may be stale, and is not cached.
+    fn insert_loaded(
+        &mut self,
+        invalidations: u64,
+        path: PathBuf,
+        value: Cached<T>,
+        max_files: usize,
+        max_size: usize,
+    ) {
+        if self.invalidations == invalidations {
+            self.insert(path, value, max_files, max_size);
+        } else {
+            log::debug!("{path:?} may have changed while it was loaded, not caching it");
+        }
+    }
+
+    /// Removes the least recently used files, until the cache is 10% under its limits,
+    /// so that evictions do not happen on every insertion
+    fn evict(&mut self, max_files: usize, max_size: usize) {
+        let (target_files, target_size) = (max_files - max_files / 10, max_size - max_size / 10);
+        let mut by_last_use: Vec<(u64, PathBuf)> = self
+            .files
+            .iter()
+            .map(|(path, cached)| (cached.last_used_at.load(Acquire), path.clone()))
+            .collect();
+        by_last_use.sort_unstable_by_key(|(last_used_at, _)| *last_used_at);
+        for (_, path) in by_last_use {
+            if self.files.len() <= target_files && self.size <= target_size {
+                break;
+            }
+            log::trace!("Evicting {path:?} from the full cache");
+            self.remove(&path);
+        }
+    }
+
+    /// Removes a file that could not be loaded, and remembers that it is missing if asked to
+    fn remove_failed(&mut self, path: &Path, remember_not_found: bool, max_files: usize) {
+        self.remove(path);
+        if remember_not_found {
+            self.insert_not_found(PathBuf::from(path), max_files);
+        }
+    }
+
+    fn insert_not_found(&mut self, path: PathBuf, max_files: usize) {
+        let now = Cached::<T>::elapsed();
+        if self.not_found.len() >= max_files {
+            self.not_found
+                .retain(|_, &mut checked_at| !Self::not_found_expired(checked_at, now));
+            if self.not_found.len() >= max_files {
+                self.not_found.clear();
+            }
+        }
+        self.not_found.insert(path, now);
+    }
+
+    fn recently_not_found(&self, path: &Path) -> bool {
+        self.not_found
+            .get(path)
+            .is_some_and(|&checked_at| !Self::not_found_expired(checked_at, Cached::<T>::elapsed()))
+    }
+
+    fn not_found_expired(checked_at: u64, now: u64) -> bool {
+        checked_at + NOT_FOUND_CACHE_MS / MAX_STALE_CACHE_MS < now
+    }
+}
+
 pub struct FileCache<T: AsyncFromStrWithState> {
-    cache: Arc<RwLock<HashMap<PathBuf, Cached<T>>>>,
+    cache: Arc<RwLock<Entries<T>>>,
     /// Files that are loaded at the beginning of the program,
     /// and used as fallback when there is no match for the request in the file system
     static_files: HashMap<PathBuf, Cached<T>>,
+    /// Whether a file watcher removes the entries of the files that change,
+    /// so that local files don't have to be checked when they are used
+    watched: bool,
+}
+
+/// Removes entries from a cache when their files change, from the thread of the file watcher
+pub(crate) struct CacheInvalidator<T>(Arc<RwLock<Entries<T>>>);
+
+impl<T: AsyncFromStrWithState> CacheInvalidator<T> {
+    /// Removes the cached file at the given path, and the ones that depend on it
+    pub fn invalidate(&self, path: &Path) {
+        let mut entries = self.0.blocking_write();
+        entries.invalidations += 1;
+        entries.not_found.remove(path);
+        let mut removed_size = 0;
+        entries.files.retain(|cached_path, cached| {
+            let changed =
+                cached_path == path || cached.content.dependencies().iter().any(|d| d == path);
+            if changed {
+                log::debug!("{path:?} changed, removing {cached_path:?} from the cache");
+                removed_size += cached.size;
+            }
+            !changed
+        });
+        entries.size -= removed_size;
+    }
 }
 
 impl<T: AsyncFromStrWithState> Default for FileCache<T> {
@@ -86,13 +241,35 @@ impl<T: AsyncFromStrWithState> FileCache<T> {
         Self {
             cache: Arc::default(),
             static_files: HashMap::new(),
+            watched: false,
         }
     }
 
+    /// Lets a file watcher remove entries from the cache when their files change
+    pub(crate) fn invalidator(&self) -> CacheInvalidator<T> {
+        CacheInvalidator(Arc::clone(&self.cache))
+    }
+
+    /// Stops checking local files for modifications when they are used,
+    /// once a file watcher removes their entries when they change
+    pub(crate) fn set_watched(&mut self) {
+        self.watched = true;
+    }
+
+    fn needs_check(&self, app_state: &AppState, cached: &Cached<T>) -> bool {
+        if self.watched {
+            // Only the files stored in the database have to be polled
+            let poll_interval = app_state.config.database_files_poll_interval_seconds;
+            return app_state.file_system.has_database_files()
+                && cached.checked_before(Duration::from_secs_f64(poll_interval));
+        }
+        !app_state.config.environment.is_prod() || cached.needs_check()
+    }
+
     /// Adds a static file to the cache so that it will never be looked up from the disk
     pub fn add_static(&mut self, path: PathBuf, contents: T) {
         log::trace!("Adding static file {path:?} to the cache.");
-        self.static_files.insert(path, Cached::new(contents));
+        self.static_files.insert(path, Cached::new(contents, 0));
     }
 
     /// Gets a file from the cache, or loads it from the file system if it's not there
@@ -111,25 +288,29 @@ impl<T: AsyncFromStrWithState> FileCache<T> {
         privileged: bool,
     ) -> anyhow::Result<Arc<T>> {
         log::trace!("Attempting to get from cache {:?}", path);
-        if let Some(cached) = self.cache.read().await.get(path) {
-            if app_state.config.environment.is_prod() && !cached.needs_check() {
-                log::trace!("Cache answer without filesystem lookup for {:?}", path);
-                return Ok(Arc::clone(&cached.content));
-            }
-            match app_state
-                .file_system
-                .modified_since(app_state, path, cached.last_check_time(), privileged)
-                .await
-            {
-                Ok(false) => {
-                    log::trace!("Cache answer with filesystem metadata read for {:?}", path);
-                    cached.update_check_time();
+        let invalidations = {
+            let entries = self.cache.read().await;
+            if let Some(cached) = entries.files.get(path) {
+                cached.mark_used();
+                if !self.needs_check(app_state, cached) {
+                    log::trace!("Cache answer without filesystem lookup for {:?}", path);
                     return Ok(Arc::clone(&cached.content));
                 }
-                Ok(true) => log::trace!("{path:?} was changed, updating cache..."),
-                Err(e) => log::trace!("Cannot read metadata of {path:?}, re-loading it: {e:#}"),
+                match modified_since(app_state, path, cached, privileged).await {
+                    Ok(false) => {
+                        log::trace!("Cache answer with filesystem metadata read for {:?}", path);
+                        cached.update_check_time();
+                        return Ok(Arc::clone(&cached.content));
+                    }
+                    Ok(true) => log::trace!("{path:?} was changed, updating cache..."),
+                    Err(e) => log::trace!("Cannot read metadata of {path:?}, re-loading it: {e:#}"),
+                }
+            } else if entries.recently_not_found(path) {
+                log::trace!("{path:?} was recently not found, not looking it up again");
+                return Err(not_found_error(path));
             }
-        }
+            entries.invalidations
+        };
         // Read lock is released
         log::trace!("Loading and parsing {:?}", path);
         let file_contents = app_state
@@ -139,16 +320,11 @@ impl<T: AsyncFromStrWithState> FileCache<T> {
 
         let parsed = match file_contents {
             Ok(contents) => {
-                let value = T::from_str_with_state(app_state, &contents).await?;
-                Ok(Cached::new(value))
+                let value = T::from_str_with_state(app_state, &contents, path).await?;
+                Ok(Cached::new(value, contents.len()))
             }
             // If a file is not found, we try to load it from the static files
-            Err(e)
-                if e.downcast_ref()
-                    == Some(&ErrorWithStatus {
-                        status: StatusCode::NOT_FOUND,
-                    }) =>
-            {
+            Err(e) if is_not_found(&e) => {
                 if let Some(static_file) = self.static_files.get(path) {
                     log::trace!("File {path:?} not found, loading it from static files instead.");
                     let cached: Cached<T> = static_file.make_fresh();
@@ -164,7 +340,14 @@ impl<T: AsyncFromStrWithState> FileCache<T> {
             Ok(value) => {
                 let new_val = Arc::clone(&value.content);
                 log::trace!("Writing to cache {:?}", path);
-                self.cache.write().await.insert(PathBuf::from(path), value);
+                let config = &app_state.config;
+                self.cache.write().await.insert_loaded(
+                    invalidations,
+                    PathBuf::from(path),
+                    value,
+                    config.max_cached_files,
+                    config.max_cached_files_size,
+                );
                 log::trace!("Done writing to cache {:?}", path);
                 log::trace!("{:?} loaded in cache", path);
                 Ok(new_val)
@@ -174,7 +357,14 @@ impl<T: AsyncFromStrWithState> FileCache<T> {
                     "Evicting {path:?} from the cache because the following error occurred: {e}"
                 );
                 log::trace!("Removing from cache {:?}", path);
-                self.cache.write().await.remove(path);
+                // In development, new files must be visible immediately.
+                // When files are watched, creating a file removes it from the missing files.
+                let remember_not_found =
+                    (app_state.config.environment.is_prod() || self.watched) && is_not_found(&e);
+                let mut entries = self.cache.write().await;
+                let remember_not_found =
+                    remember_not_found && entries.invalidations == invalidations;
+                entries.remove_failed(path, remember_not_found, app_state.config.max_cached_files);
                 log::trace!("Done removing from cache {:?}", path);
                 Err(e)
             }
@@ -182,7 +372,97 @@ impl<T: AsyncFromStrWithState> FileCache<T> {
     }
 }
 
+fn not_found_error(path: &Path) -> anyhow::Error {
+    anyhow::Error::from(ErrorWithStatus {
+        status: StatusCode::NOT_FOUND,
+    })
+    .context(format!("Couldn't load {} into cache", path.display()))
+}
+
+/// Whether a cached file, or one of the files it depends on, changed since it was last checked
+async fn modified_since<T: AsyncFromStrWithState>(
+    app_state: &AppState,
+    path: &Path,
+    cached: &Cached<T>,
+    privileged: bool,
+) -> anyhow::Result<bool> {
+    let since = cached.last_check_time();
+    let file_system = &app_state.file_system;
+    if file_system
+        .modified_since(app_state, path, since, privileged)
+        .await?
+    {
+        return Ok(true);
+    }
+    for dependency in cached.content.dependencies() {
+        if file_system
+            .modified_since(app_state, dependency, since, true)
+            .await?
+        {
+            log::trace!("{dependency:?}, used by {path:?}, was changed");
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
 #[async_trait(? Send)]
 pub trait AsyncFromStrWithState: Sized {
-    async fn from_str_with_state(app_state: &AppState, source: &str) -> anyhow::Result<Self>;
+    async fn from_str_with_state(
+        app_state: &AppState,
+        source: &str,
+        source_path: &Path,
+    ) -> anyhow::Result<Self>;
+
+    /// Other files that were read to build this value.
+    /// The cached value is reloaded when any of them changes.
+    fn dependencies(&self) -> &[PathBuf] {
+        &[]
+    }
+}
+
+#[test]
+fn test_evicts_least_recently_used() {
+    let mut entries = Entries::default();
+    for (i, name) in ["a", "b", "c"].into_iter().enumerate() {
+        let cached = Cached::new((), 10);
+        cached.last_used_at.store(i as u64, Release);
+        entries.insert(PathBuf::from(name), cached, 3, 100);
+    }
+    entries.files[Path::new("a")].last_used_at.store(3, Release);
+    entries.insert_not_found(PathBuf::from("d"), 3);
+    assert!(entries.recently_not_found(Path::new("d")));
+    entries.insert(PathBuf::from("d"), Cached::new((), 75), 3, 100);
+    assert!(!entries.recently_not_found(Path::new("d")));
+    // The cache is now too large: the least recently used files are evicted
+    let mut remaining: Vec<_> = entries.files.keys().collect();
+    remaining.sort();
+    assert_eq!(remaining, [Path::new("a"), Path::new("d")]);
+    assert_eq!(entries.size, 85);
+}
+
+#[test]
+fn test_does_not_cache_files_loaded_before_an_invalidation() {
+    use crate::webserver::database::ParsedSqlFile;
+    let cache: FileCache<ParsedSqlFile> = FileCache::new();
+    let invalidations = cache.cache.blocking_read().invalidations;
+    cache.invalidator().invalidate(Path::new("a"));
+    let mut entries = cache.cache.blocking_write();
+    entries.insert_loaded(
+        invalidations,
+        PathBuf::from("a"),
+        Cached::new(ParsedSqlFile::default(), 1),
+        10,
+        100,
+    );
+    assert!(entries.files.is_empty());
+    let invalidations = entries.invalidations;
+    entries.insert_loaded(
+        invalidations,
+        PathBuf::from("a"),
+        Cached::new(ParsedSqlFile::default(), 1),
+        10,
+        100,
+    );
+    assert_eq!(entries.files.len(), 1);
 }
@@ -0,0 +1,96 @@
+//! Watches the web root and the templates directory, and removes the files that change from the caches,
+//! so that cached files don't have to be checked for modifications on every request.
+
+use crate::app_config::AppConfig;
+use crate::file_cache::CacheInvalidator;
+use crate::templates::SplitTemplate;
+use crate::webserver::database::ParsedSqlFile;
+use crate::TEMPLATES_DIR;
+use anyhow::Context;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+
+/// Stops watching when dropped
+pub(crate) struct FileWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+    pub fn start(
+        config: &AppConfig,
+        sql_files: CacheInvalidator<ParsedSqlFile>,
+        templates: CacheInvalidator<SplitTemplate>,
+    ) -> anyhow::Result<Self> {
+        // Events are reported with the paths that are watched
+        let web_root = config
+            .web_root
+            .canonicalize()
+            .with_context(|| format!("Unable to watch {}", config.web_root.display()))?;
+        let templates_dir = config
+            .configuration_directory
+            .join("templates")
+            .canonicalize()
+            .ok();
+        let roots = WatchedRoots {
+            web_root: web_root.clone(),
+            templates_dir: templates_dir.clone(),
+        };
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                    for key in event.paths.iter().flat_map(|path| roots.cache_keys(path)) {
+                        sql_files.invalidate(&key);
+                        templates.invalidate(&key);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => log::error!("Error while watching files: {e}"),
+            })?;
+        watcher.watch(&web_root, RecursiveMode::Recursive)?;
+        if let Some(templates_dir) = templates_dir.filter(|dir| !dir.starts_with(&web_root)) {
+            watcher.watch(&templates_dir, RecursiveMode::Recursive)?;
+        }
+        log::info!("Watching {web_root:?} for changes");
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+struct WatchedRoots {
+    web_root: PathBuf,
+    templates_dir: Option<PathBuf>,
+}
+
+impl WatchedRoots {
+    /// The paths under which a changed file may be cached
+    fn cache_keys(&self, changed: &Path) -> Vec<PathBuf> {
+        let mut keys = Vec::with_capacity(2);
+        if let Some(Ok(template)) = self
+            .templates_dir
+            .as_ref()
+            .map(|dir| changed.strip_prefix(dir))
+        {
+            keys.push(Path::new(TEMPLATES_DIR).join(template));
+        }
+        if let Ok(relative) = changed.strip_prefix(&self.web_root) {
+            keys.push(relative.to_path_buf());
+        }
+        keys
+    }
+}
+
+#[test]
+fn test_cache_keys() {
+    let roots = WatchedRoots {
+        web_root: PathBuf::from("/var/www"),
+        templates_dir: Some(PathBuf::from("/etc/sqlpage/templates")),
+    };
+    assert_eq!(
+        roots.cache_keys(Path::new("/var/www/admin/index.sql")),
+        [PathBuf::from("admin/index.sql")]
+    );
+    assert_eq!(
+        roots.cache_keys(Path::new("/etc/sqlpage/templates/card.handlebars")),
+        [PathBuf::from("sqlpage/templates/card.handlebars")]
+    );
+    assert!(roots.cache_keys(Path::new("/tmp/other.sql")).is_empty());
+}
@@ -1,17 +1,27 @@
+use crate::app_config::{AppConfig, UploadStorage};
+use crate::object_storage::ObjectStorage;
 use crate::webserver::ErrorWithStatus;
 use crate::webserver::{make_placeholder, Database};
 use crate::{AppState, TEMPLATES_DIR};
 use anyhow::Context;
-use chrono::{DateTime, Utc};
-use sqlx::any::{AnyKind, AnyStatement, AnyTypeInfo};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::any::{AnyKind, AnyRow, AnyStatement, AnyTypeInfo};
 use sqlx::postgres::types::PgTimeTz;
-use sqlx::{Postgres, Statement, Type};
+use sqlx::{Either, Executor, Postgres, Row, Statement, Type, TypeInfo};
 use std::io::ErrorKind;
 use std::path::{Component, Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Uploads are written to the database in chunks of this size, so that they are never entirely in memory
+const DATABASE_WRITE_CHUNK_SIZE: u64 = 1024 * 1024;
 
 pub(crate) struct FileSystem {
     local_root: PathBuf,
     db_fs_queries: Option<DbFsQueries>,
+    /// Where `sqlpage.persist_uploaded_file` saves files
+    upload_storage: UploadStorage,
+    /// The bucket from which files that are neither local nor in the database are served
+    object_storage: Option<ObjectStorage>,
 }
 
 impl FileSystem {
@@ -29,7 +39,90 @@ impl FileSystem {
                     None
                 }
             },
+            upload_storage: UploadStorage::Local,
+            object_storage: None,
+        }
+    }
+
+    /// Configures where uploaded files are persisted, and the bucket files can be served from
+    pub fn with_upload_storage(mut self, config: &AppConfig) -> anyhow::Result<Self> {
+        self.object_storage = ObjectStorage::from_config(config)?;
+        if config.upload_storage == UploadStorage::S3 && self.object_storage.is_none() {
+            anyhow::bail!("upload_storage is set to s3, but s3_bucket is not set");
+        }
+        self.upload_storage = config.upload_storage;
+        Ok(self)
+    }
+
+    /// Whether files can be stored in the `sqlpage_files` table
+    pub fn has_database_files(&self) -> bool {
+        self.db_fs_queries.is_some()
+    }
+
+    /// The paths of all the files stored in the `sqlpage_files` table
+    pub async fn list_database_files(&self, app_state: &AppState) -> anyhow::Result<Vec<PathBuf>> {
+        if self.db_fs_queries.is_none() {
+            return Ok(Vec::new());
+        }
+        let paths: Vec<String> = sqlx::query_scalar("SELECT path FROM sqlpage_files")
+            .fetch_all(&app_state.db.connection)
+            .await
+            .with_context(|| "Unable to list the files of the sqlpage_files table")?;
+        Ok(paths.into_iter().map(PathBuf::from).collect())
+    }
+
+    pub fn object_storage(&self) -> Option<&ObjectStorage> {
+        self.object_storage.as_ref()
+    }
+
+    /// Saves the file at `source` to the configured upload storage,
+    /// at the given path relative to the web root.
+    /// The file is streamed, and never entirely loaded in memory.
+    pub async fn persist(
+        &self,
+        app_state: &AppState,
+        path: &Path,
+        source: &Path,
+    ) -> anyhow::Result<()> {
+        match self.upload_storage {
+            UploadStorage::Local => {
+                let target = self.local_root.join(path);
+                if let Some(folder) = target.parent() {
+                    tokio::fs::create_dir_all(folder)
+                        .await
+                        .with_context(|| format!("unable to create folder {}", folder.display()))?;
+                }
+                tokio::fs::copy(source, &target).await.with_context(|| {
+                    format!(
+                        "unable to copy {} to {}",
+                        source.display(),
+                        target.display()
+                    )
+                })?;
+            }
+            UploadStorage::Database => {
+                let db_fs = self.db_fs_queries.as_ref().with_context(|| {
+                    "upload_storage is set to database, but the sqlpage_files table does not exist"
+                })?;
+                let contents = tokio::fs::File::open(source)
+                    .await
+                    .with_context(|| format!("unable to open {}", source.display()))?;
+                // The large futures are boxed, because this can be called from nested run_sql calls
+                Box::pin(db_fs.write_file(app_state, path, contents)).await?;
+            }
+            UploadStorage::S3 => {
+                let storage = self
+                    .object_storage
+                    .as_ref()
+                    .with_context(|| "upload_storage is set to s3, but s3_bucket is not set")?;
+                let contents = tokio::fs::File::open(source)
+                    .await
+                    .with_context(|| format!("unable to open {}", source.display()))?;
+                let key = path.display().to_string();
+                Box::pin(storage.put(&app_state.config, &key, contents)).await?;
+            }
         }
+        Ok(())
     }
 
     pub async fn modified_since(
@@ -49,6 +142,10 @@ impl FileSystem {
                     .file_modified_since_in_db(app_state, path, since)
                     .await
             }
+            (Err(e), None) if e.kind() == ErrorKind::NotFound => Err(ErrorWithStatus {
+                status: actix_web::http::StatusCode::NOT_FOUND,
+            }
+            .into()),
             (Err(e), _) => {
                 Err(e).with_context(|| format!("Unable to read local file metadata for {path:?}"))
             }
@@ -92,6 +189,59 @@ impl FileSystem {
         }
     }
 
+    /// Reads a file from the `sqlpage_files` table, and returns when it was last modified, if known.
+    pub async fn open_database_file_with_date(
+        &self,
+        app_state: &AppState,
+        path: &Path,
+    ) -> anyhow::Result<(OpenedFile, Option<DateTime<Utc>>)> {
+        let db_fs = self.database_files()?;
+        let (contents, last_modified) = db_fs.read_file_with_date(app_state, path).await?;
+        Ok((OpenedFile::InMemory(contents), last_modified))
+    }
+
+    /// The size in bytes and the modification time of a file of the `sqlpage_files` table,
+    /// without reading its contents
+    pub async fn database_file_metadata(
+        &self,
+        app_state: &AppState,
+        path: &Path,
+    ) -> anyhow::Result<(u64, Option<DateTime<Utc>>)> {
+        self.database_files()?.file_metadata(app_state, path).await
+    }
+
+    fn database_files(&self) -> anyhow::Result<&DbFsQueries> {
+        self.db_fs_queries.as_ref().ok_or_else(|| {
+            ErrorWithStatus {
+                status: actix_web::http::StatusCode::NOT_FOUND,
+            }
+            .into()
+        })
+    }
+
+    /// Opens a file of the web root only if it exists on the local disk, without looking in the database.
+    pub async fn open_local_file_with_date(
+        &self,
+        app_state: &AppState,
+        path: &Path,
+    ) -> anyhow::Result<Option<(OpenedFile, Option<DateTime<Utc>>)>> {
+        let local_path = self.safe_local_path(app_state, path, false)?;
+        match tokio::fs::File::open(&local_path).await {
+            Ok(file) => {
+                let metadata = file.metadata().await.with_context(|| {
+                    format!("Unable to read the metadata of {}", path.display())
+                })?;
+                let last_modified = metadata.modified().ok().map(DateTime::<Utc>::from);
+                let len = metadata.len();
+                Ok(Some((OpenedFile::Local { file, len }, last_modified)))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                Err(e).with_context(|| format!("Unable to open local file {}", path.display()))
+            }
+        }
+    }
+
     fn safe_local_path(
         &self,
         app_state: &AppState,
@@ -131,6 +281,35 @@ impl FileSystem {
     }
 }
 
+pub(crate) enum OpenedFile {
+    Local { file: tokio::fs::File, len: u64 },
+    InMemory(Vec<u8>),
+}
+
+impl OpenedFile {
+    pub fn len(&self) -> u64 {
+        match self {
+            OpenedFile::Local { len, .. } => *len,
+            OpenedFile::InMemory(contents) => contents.len() as u64,
+        }
+    }
+}
+
+/// The contents column can be binary, or text as in the documented schema
+fn file_contents(row: &AnyRow) -> Result<Vec<u8>, sqlx::Error> {
+    row.try_get::<Vec<u8>, _>(0)
+        .or_else(|_| row.try_get::<String, _>(0).map(String::into_bytes))
+}
+
+/// TIMESTAMP columns are decoded as naive dates by some databases
+fn last_modified(row: &AnyRow, index: usize) -> Option<DateTime<Utc>> {
+    row.try_get::<DateTime<Utc>, _>(index).ok().or_else(|| {
+        row.try_get::<NaiveDateTime, _>(index)
+            .ok()
+            .map(|date| date.and_utc())
+    })
+}
+
 async fn file_modified_since_local(path: &Path, since: DateTime<Utc>) -> tokio::io::Result<bool> {
     tokio::fs::metadata(path)
         .await
@@ -141,6 +320,7 @@ async fn file_modified_since_local(path: &Path, since: DateTime<Utc>) -> tokio::
 pub(crate) struct DbFsQueries {
     was_modified: AnyStatement<'static>,
     read_file: AnyStatement<'static>,
+    file_metadata: AnyStatement<'static>,
 }
 
 impl DbFsQueries {
@@ -150,6 +330,7 @@ impl DbFsQueries {
         Ok(Self {
             was_modified: Self::make_was_modified_query(db, db_kind).await?,
             read_file: Self::make_read_file_query(db, db_kind).await?,
+            file_metadata: Self::make_file_metadata_query(db, db_kind).await?,
         })
     }
 
@@ -174,13 +355,30 @@ impl DbFsQueries {
         db_kind: AnyKind,
     ) -> anyhow::Result<AnyStatement<'static>> {
         let was_modified_query = format!(
-            "SELECT contents from sqlpage_files WHERE path = {} LIMIT 1",
+            "SELECT contents, last_modified from sqlpage_files WHERE path = {} LIMIT 1",
             make_placeholder(db_kind, 1),
         );
         let param_types: &[AnyTypeInfo; 1] = &[<str as Type<Postgres>>::type_info().into()];
         db.prepare_with(&was_modified_query, param_types).await
     }
 
+    async fn make_file_metadata_query(
+        db: &Database,
+        db_kind: AnyKind,
+    ) -> anyhow::Result<AnyStatement<'static>> {
+        let length_in_bytes = match db_kind {
+            AnyKind::Mssql => "DATALENGTH(contents)",
+            AnyKind::Sqlite => "length(CAST(contents AS BLOB))",
+            AnyKind::Postgres | AnyKind::MySql => "octet_length(contents)",
+        };
+        let file_metadata_query = format!(
+            "SELECT {length_in_bytes}, last_modified from sqlpage_files WHERE path = {} LIMIT 1",
+            make_placeholder(db_kind, 1),
+        );
+        let param_types: &[AnyTypeInfo; 1] = &[<str as Type<Postgres>>::type_info().into()];
+        db.prepare_with(&file_metadata_query, param_types).await
+    }
+
     async fn file_modified_since_in_db(
         &self,
         app_state: &AppState,
@@ -191,34 +389,188 @@ impl DbFsQueries {
             .query_as::<(bool,)>()
             .bind(since)
             .bind(path.display().to_string())
-            .fetch_one(&app_state.db.connection)
+            .fetch_optional(&app_state.db.connection)
             .await
-            .map(|(modified,)| modified)
+            .map_err(anyhow::Error::from)
+            .and_then(|modified| {
+                let (modified,) = modified.ok_or(ErrorWithStatus {
+                    status: actix_web::http::StatusCode::NOT_FOUND,
+                })?;
+                Ok(modified)
+            })
             .with_context(|| {
                 format!("Unable to check when {path:?} was last modified in the database")
             })
     }
 
     async fn read_file(&self, app_state: &AppState, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let (contents, _) = self.read_file_with_date(app_state, path).await?;
+        Ok(contents)
+    }
+
+    async fn read_file_with_date(
+        &self,
+        app_state: &AppState,
+        path: &Path,
+    ) -> anyhow::Result<(Vec<u8>, Option<DateTime<Utc>>)> {
         log::debug!("Reading file {} from the database", path.display());
-        self.read_file
-            .query_as::<(Vec<u8>,)>()
+        let row = self
+            .read_file
+            .query()
             .bind(path.display().to_string())
             .fetch_optional(&app_state.db.connection)
             .await
-            .map_err(anyhow::Error::from)
-            .and_then(|modified| {
-                if let Some((modified,)) = modified {
-                    Ok(modified)
-                } else {
-                    Err(ErrorWithStatus {
-                        status: actix_web::http::StatusCode::NOT_FOUND,
-                    }
-                    .into())
-                }
-            })
-            .with_context(|| format!("Unable to read {path:?} from the database"))
+            .with_context(|| format!("Unable to read {} from the database", path.display()))?
+            .ok_or(ErrorWithStatus {
+                status: actix_web::http::StatusCode::NOT_FOUND,
+            })?;
+        let contents = file_contents(&row)
+            .with_context(|| format!("Unable to read the contents of {}", path.display()))?;
+        Ok((contents, last_modified(&row, 1)))
     }
+
+    async fn file_metadata(
+        &self,
+        app_state: &AppState,
+        path: &Path,
+    ) -> anyhow::Result<(u64, Option<DateTime<Utc>>)> {
+        log::debug!(
+            "Reading the metadata of {} from the database",
+            path.display()
+        );
+        let row = self
+            .file_metadata
+            .query()
+            .bind(path.display().to_string())
+            .fetch_optional(&app_state.db.connection)
+            .await
+            .with_context(|| {
+                format!(
+                    "Unable to read the metadata of {} from the database",
+                    path.display()
+                )
+            })?
+            .ok_or(ErrorWithStatus {
+                status: actix_web::http::StatusCode::NOT_FOUND,
+            })?;
+        // The integer type of the length depends on the database
+        let len = row
+            .try_get::<i64, _>(0)
+            .or_else(|_| row.try_get::<i32, _>(0).map(i64::from))
+            .with_context(|| format!("Unable to read the size of {}", path.display()))?;
+        Ok((u64::try_from(len)?, last_modified(&row, 1)))
+    }
+
+    /// Creates or replaces a file in the database.
+    /// The contents are sent in chunks, appended to each other in a single transaction.
+    /// They are sent as text when the `contents` column is `TEXT`, as in the documented schema,
+    /// and as binary data when they are not valid UTF-8, or when the column is `BYTEA`.
+    async fn write_file(
+        &self,
+        app_state: &AppState,
+        path: &Path,
+        mut contents: impl AsyncRead + Unpin,
+    ) -> anyhow::Result<()> {
+        log::debug!("Writing file {} to the database", path.display());
+        let context = || format!("Unable to write {} to the database", path.display());
+        let (upsert, append) = write_file_queries(app_state.db.connection.any_kind());
+        let path_str = path.display().to_string();
+        let mut transaction = app_state
+            .db
+            .connection
+            .begin()
+            .await
+            .with_context(context)?;
+        let binary_column = match (&mut *transaction)
+            .prepare(&upsert)
+            .await
+            .with_context(context)?
+            .parameters()
+        {
+            // Only PostgreSQL tells us the type of the contents column
+            Some(Either::Left(types)) => types.first().is_some_and(|t| t.name() == "BYTEA"),
+            _ => false,
+        };
+        let mut pending = Vec::new();
+        let mut query = upsert.as_str();
+        loop {
+            let read = (&mut contents)
+                .take(DATABASE_WRITE_CHUNK_SIZE)
+                .read_to_end(&mut pending)
+                .await
+                .with_context(context)?;
+            let at_end = (read as u64) < DATABASE_WRITE_CHUNK_SIZE;
+            let statement = sqlx::query(query);
+            let statement = match next_chunk(&mut pending, binary_column, at_end) {
+                FileChunk::Text(text) => statement.bind(text),
+                FileChunk::Binary(bytes) => statement.bind(bytes),
+            };
+            statement
+                .bind(path_str.as_str())
+                .execute(&mut *transaction)
+                .await
+                .with_context(context)?;
+            if at_end {
+                break;
+            }
+            query = append.as_str();
+        }
+        transaction.commit().await.with_context(context)?;
+        Ok(())
+    }
+}
+
+/// The queries that create or replace a file, and that append a chunk to its contents.
+/// Both take the contents, and then the path of the file,
+/// in the order of their placeholders, since some databases use positional `?` placeholders.
+fn write_file_queries(db_kind: AnyKind) -> (String, String) {
+    let contents = make_placeholder(db_kind, 1);
+    let path = make_placeholder(db_kind, 2);
+    let upsert = match db_kind {
+        AnyKind::Sqlite | AnyKind::Postgres => format!(
+            "INSERT INTO sqlpage_files(contents, path, last_modified) VALUES ({contents}, {path}, CURRENT_TIMESTAMP) \
+            ON CONFLICT(path) DO UPDATE SET contents = excluded.contents, last_modified = excluded.last_modified"
+        ),
+        AnyKind::MySql => format!(
+            "INSERT INTO sqlpage_files(contents, path, last_modified) VALUES ({contents}, {path}, CURRENT_TIMESTAMP) \
+            ON DUPLICATE KEY UPDATE contents = VALUES(contents), last_modified = VALUES(last_modified)"
+        ),
+        AnyKind::Mssql => format!(
+            "MERGE INTO sqlpage_files WITH (HOLDLOCK) AS target \
+            USING (SELECT {contents} AS contents, {path} AS path) AS source ON target.path = source.path \
+            WHEN MATCHED THEN UPDATE SET contents = source.contents, last_modified = CURRENT_TIMESTAMP \
+            WHEN NOT MATCHED THEN INSERT (path, contents, last_modified) VALUES (source.path, source.contents, CURRENT_TIMESTAMP);"
+        ),
+    };
+    let concatenation = match db_kind {
+        AnyKind::MySql => format!("CONCAT(contents, {contents})"),
+        AnyKind::Mssql => format!("contents + {contents}"),
+        AnyKind::Sqlite | AnyKind::Postgres => format!("contents || {contents}"),
+    };
+    let append = format!("UPDATE sqlpage_files SET contents = {concatenation} WHERE path = {path}");
+    (upsert, append)
+}
+
+#[derive(Debug, PartialEq)]
+enum FileChunk {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Takes the next chunk to write from the bytes read so far.
+/// A character that is cut at the end of the chunk is left in `pending`, to be sent with the next chunk.
+fn next_chunk(pending: &mut Vec<u8>, binary_column: bool, at_end: bool) -> FileChunk {
+    if binary_column {
+        return FileChunk::Binary(std::mem::take(pending));
+    }
+    let valid_up_to = match std::str::from_utf8(pending) {
+        Ok(_) => pending.len(),
+        Err(e) if e.error_len().is_none() && !at_end => e.valid_up_to(),
+        Err(_) => return FileChunk::Binary(std::mem::take(pending)),
+    };
+    let rest = pending.split_off(valid_up_to);
+    let text = std::mem::replace(pending, rest);
+    FileChunk::Text(String::from_utf8(text).expect("checked to be valid UTF-8"))
 }
 
 #[actix_web::test]
@@ -246,5 +598,79 @@ async fn test_sql_file_read_utf8() -> anyhow::Result<()> {
         .read_to_string(&state, "unit test file.txt".as_ref(), false)
         .await?;
     assert_eq!(actual, "Héllö world! 😀");
+    let (len, _) = fs
+        .database_file_metadata(&state, "unit test file.txt".as_ref())
+        .await?;
+    assert_eq!(len, actual.len() as u64);
     Ok(())
 }
+
+#[actix_web::test]
+async fn test_persist_to_database() -> anyhow::Result<()> {
+    use crate::app_config;
+    use sqlx::Executor;
+    let mut config = app_config::tests::test_config();
+    config.upload_storage = UploadStorage::Database;
+    let state = AppState::init(&config).await?;
+    state
+        .db
+        .connection
+        .execute(
+            r"
+        CREATE TABLE sqlpage_files(
+          path VARCHAR(255) NOT NULL PRIMARY KEY,
+          contents TEXT,
+          last_modified TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+    ",
+        )
+        .await?;
+    let fs = FileSystem::init("/", &state.db)
+        .await
+        .with_upload_storage(&config)?;
+    let source = std::env::temp_dir().join("sqlpage_test_persist_to_database.txt");
+    tokio::fs::write(&source, b"uploaded \xFF").await?;
+    let path = Path::new("uploads/persisted.txt");
+    fs.persist(&state, path, &source).await?;
+    assert_eq!(fs.read_file(&state, path, false).await?, b"uploaded \xFF");
+    // Persisting a file again replaces it
+    tokio::fs::write(&source, "é".repeat(1024 * 1024)).await?;
+    fs.persist(&state, path, &source).await?;
+    tokio::fs::remove_file(&source).await?;
+    // Compared without assert_eq, to avoid printing megabytes when it fails
+    let contents = fs.read_to_string(&state, path, false).await?;
+    assert!(
+        contents == "é".repeat(1024 * 1024),
+        "{} bytes",
+        contents.len()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_next_chunk() {
+    let mut pending = "aé".as_bytes().to_vec();
+    pending.pop();
+    // The first byte of 'é' waits for the next chunk
+    assert_eq!(
+        next_chunk(&mut pending, false, false),
+        FileChunk::Text("a".into())
+    );
+    assert_eq!(pending, [0xC3]);
+    pending.push(0xA9);
+    assert_eq!(
+        next_chunk(&mut pending, false, true),
+        FileChunk::Text("é".into())
+    );
+    assert!(pending.is_empty());
+    pending.extend_from_slice(b"\xFF\xFE");
+    assert_eq!(
+        next_chunk(&mut pending, false, false),
+        FileChunk::Binary(b"\xFF\xFE".to_vec())
+    );
+    pending.extend_from_slice(b"text");
+    assert_eq!(
+        next_chunk(&mut pending, true, true),
+        FileChunk::Binary(b"text".to_vec())
+    );
+}
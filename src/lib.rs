@@ -6,7 +6,10 @@ extern crate core;
 pub mod app_config;
 pub mod dynamic_component;
 pub mod file_cache;
+mod file_watcher;
 pub mod filesystem;
+pub mod object_storage;
+mod precompile;
 pub mod render;
 pub mod template_helpers;
 pub mod templates;
@@ -14,9 +17,12 @@ pub mod utils;
 pub mod webserver;
 
 use crate::app_config::AppConfig;
+use crate::file_watcher::FileWatcher;
 use crate::filesystem::FileSystem;
 use crate::webserver::database::ParsedSqlFile;
+use crate::webserver::response_cache::ResponseCache;
 use file_cache::FileCache;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use templates::AllTemplates;
 use webserver::Database;
@@ -30,9 +36,19 @@ pub const ON_CONNECT_FILE: &str = "on_connect.sql";
 
 pub struct AppState {
     pub db: Database,
+    /// The additional named databases that SQL files can choose to run against
+    databases: HashMap<String, Database>,
     all_templates: AllTemplates,
     sql_file_cache: FileCache<ParsedSqlFile>,
     file_system: FileSystem,
+    /// Pages rendered with the `cache` component
+    response_cache: ResponseCache,
+    /// The key that signs the cookies set by `SQLPage`, such as the flash messages
+    cookie_key: Vec<u8>,
+    /// Warns once that `cookie_key` was generated randomly, the first time a cookie is signed with it
+    random_cookie_key_warning: std::sync::Once,
+    /// Removes the files that change from the caches, when `watch_files` is enabled
+    _file_watcher: Option<FileWatcher>,
     config: AppConfig,
 }
 
@@ -42,21 +58,71 @@ impl AppState {
         Self::init_with_db(config, db).await
     }
     pub async fn init_with_db(config: &AppConfig, db: Database) -> anyhow::Result<Self> {
-        let all_templates = AllTemplates::init(config)?;
+        let databases = Database::init_named(config).await?;
+        let mut all_templates = AllTemplates::init(config)?;
         let mut sql_file_cache = FileCache::new();
-        let file_system = FileSystem::init(&config.web_root, &db).await;
+        let file_system = FileSystem::init(&config.web_root, &db)
+            .await
+            .with_upload_storage(config)?;
         sql_file_cache.add_static(
             PathBuf::from("index.sql"),
             ParsedSqlFile::new(&db, include_str!("../index.sql")),
         );
-        Ok(AppState {
+        let mut file_watcher = None;
+        if config.watch_files {
+            let sql_files = sql_file_cache.invalidator();
+            match FileWatcher::start(config, sql_files, all_templates.invalidator()) {
+                Ok(watcher) => {
+                    sql_file_cache.set_watched();
+                    all_templates.set_watched();
+                    file_watcher = Some(watcher);
+                }
+                Err(e) => log::warn!(
+                    "Unable to watch files for changes, they will be checked when they are used: {e:#}"
+                ),
+            }
+        }
+        let app_state = AppState {
             db,
+            databases,
             all_templates,
             sql_file_cache,
             file_system,
+            response_cache: ResponseCache::new(
+                config.max_cached_responses,
+                config.max_cached_responses_size,
+            ),
+            cookie_key: cookie_key(config),
+            random_cookie_key_warning: std::sync::Once::new(),
+            _file_watcher: file_watcher,
             config: config.clone(),
-        })
+        };
+        precompile::precompile_files(&app_state).await?;
+        Ok(app_state)
     }
+
+    /// The key that signs new cookies
+    pub(crate) fn cookie_signing_key(&self) -> &[u8] {
+        if self.config.cookie_secret.is_none() {
+            self.random_cookie_key_warning.call_once(|| {
+                log::warn!(
+                    "The cookie_secret configuration option is not set. A random secret was generated: \
+                    the messages of the flash component that were set before a restart, \
+                    or by another SQLPage instance behind the same load balancer, will be ignored."
+                );
+            });
+        }
+        &self.cookie_key
+    }
+}
+
+fn cookie_key(config: &AppConfig) -> Vec<u8> {
+    if let Some(secret) = &config.cookie_secret {
+        return secret.as_bytes().to_vec();
+    }
+    let mut key = vec![0; 32];
+    rand::RngCore::fill_bytes(&mut password_hash::rand_core::OsRng, &mut key);
+    key
 }
 
 impl std::fmt::Debug for AppState {
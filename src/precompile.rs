@@ -0,0 +1,131 @@
+//! Parses all the SQL files and templates when the server starts, when `precompile_files` is enabled,
+//! so that broken pages are reported at startup instead of on their first request,
+//! and pages don't have to be parsed while a visitor is waiting for them.
+
+use crate::app_config::Precompilation;
+use crate::{AppState, TEMPLATES_DIR};
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+
+pub(crate) async fn precompile_files(app_state: &AppState) -> anyhow::Result<()> {
+    let mode = app_state.config.precompile_files;
+    if mode == Precompilation::Off {
+        return Ok(());
+    }
+    let start = Instant::now();
+    let (sql_files, templates) = list_files(app_state).await?;
+    let mut errors = Vec::new();
+    for path in &sql_files {
+        // Loaded like when they are requested, so that files in sqlpage/ are not cached
+        match app_state
+            .sql_file_cache
+            .get_with_privilege(app_state, path, false)
+            .await
+        {
+            Ok(parsed) => {
+                if let Some(err) = parsed.first_error() {
+                    errors.push(format!("{}: {err:#}", path.display()));
+                }
+            }
+            Err(err) => errors.push(format!("{}: {err:#}", path.display())),
+        }
+    }
+    for name in &templates {
+        if let Err(err) = app_state.all_templates.get_template(app_state, name).await {
+            errors.push(format!("{err:#}"));
+        }
+    }
+    log::info!(
+        "Precompiled {} SQL files and {} templates in {:?}",
+        sql_files.len(),
+        templates.len(),
+        start.elapsed()
+    );
+    if errors.is_empty() {
+        return Ok(());
+    }
+    if mode == Precompilation::Abort {
+        anyhow::bail!(
+            "{} files contain errors. Fix them, or set precompile_files to \"warn\" to start anyway:\n{}",
+            errors.len(),
+            errors.join("\n")
+        );
+    }
+    for error in errors {
+        log::warn!("{error}");
+    }
+    Ok(())
+}
+
+/// The SQL files of the web root, and the names of the templates,
+/// from the local file system and from the `sqlpage_files` table
+async fn list_files(app_state: &AppState) -> anyhow::Result<(BTreeSet<PathBuf>, BTreeSet<String>)> {
+    let config = &app_state.config;
+    let mut sql_files = BTreeSet::new();
+    let mut templates = BTreeSet::new();
+    for path in list_local_files(&config.web_root).await? {
+        if has_extension(&path, "sql") && !is_in_sqlpage_dir(&path) {
+            sql_files.insert(path);
+        }
+    }
+    let templates_dir = config.configuration_directory.join("templates");
+    for path in list_local_files(&templates_dir).await? {
+        if has_extension(&path, "handlebars") {
+            templates.insert(template_name(&path));
+        }
+    }
+    for path in app_state.file_system.list_database_files(app_state).await? {
+        if let Ok(template) = path.strip_prefix(TEMPLATES_DIR) {
+            if has_extension(template, "handlebars") {
+                templates.insert(template_name(template));
+            }
+        } else if has_extension(&path, "sql") && !is_in_sqlpage_dir(&path) {
+            sql_files.insert(path);
+        }
+    }
+    Ok((sql_files, templates))
+}
+
+/// The files of a directory and its subdirectories, relative to it.
+/// Hidden files and directories, such as `.git`, are skipped.
+async fn list_local_files(root: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if !root.is_dir() {
+        return Ok(files);
+    }
+    let mut directories = vec![PathBuf::new()];
+    while let Some(directory) = directories.pop() {
+        let full_directory = root.join(&directory);
+        let mut entries = tokio::fs::read_dir(&full_directory)
+            .await
+            .with_context(|| format!("Unable to list the files of {}", full_directory.display()))?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = directory.join(entry.file_name());
+            if entry.file_type().await?.is_dir() {
+                directories.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// Files in sqlpage/ cannot be requested
+fn is_in_sqlpage_dir(path: &Path) -> bool {
+    matches!(path.components().next(), Some(Component::Normal(dir)) if dir.eq_ignore_ascii_case("sqlpage"))
+}
+
+fn template_name(path: &Path) -> String {
+    path.with_extension("").to_string_lossy().replace('\\', "/")
+}
@@ -1,5 +1,8 @@
 use crate::templates::SplitTemplate;
+use crate::webserver::download::Download;
+use crate::webserver::form_validation::FormValidation;
 use crate::webserver::http::RequestContext;
+use crate::webserver::response_cache::{cache_key, CacheSettings};
 use crate::webserver::ErrorWithStatus;
 use crate::AppState;
 use actix_web::cookie::time::format_description::well_known::Rfc3339;
@@ -23,6 +26,8 @@ pub enum PageContext<W: std::io::Write> {
     Body {
         http_response: HttpResponseBuilder,
         renderer: RenderContext<W>,
+        /// How the rendered page should be cached
+        cache: Option<CacheSettings>,
     },
 
     /// The response is ready, and should be sent as is. No further statements should be executed
@@ -36,6 +41,11 @@ pub struct HeaderContext<W: std::io::Write> {
     pub writer: W,
     response: HttpResponseBuilder,
     has_status: bool,
+    cache: Option<CacheSettings>,
+    /// The messages of the `flash` component, to display on the next page
+    flash_messages: Vec<JsonValue>,
+    /// The signed cookie that contains `flash_messages`
+    flash_cookie: Option<String>,
 }
 
 impl<'a, W: std::io::Write> HeaderContext<W> {
@@ -51,25 +61,36 @@ impl<'a, W: std::io::Write> HeaderContext<W> {
             writer,
             response,
             has_status: false,
+            cache: None,
+            flash_messages: Vec::new(),
+            flash_cookie: None,
         }
     }
     pub async fn handle_row(self, data: JsonValue) -> anyhow::Result<PageContext<W>> {
         log::debug!("Handling header row: {data}");
+        // The larger futures are boxed to keep this one small: pages are rendered recursively by run_sql
         match get_object_str(&data, "component") {
             Some("status_code") => self.status_code(&data).map(PageContext::Header),
             Some("http_header") => self.add_http_header(&data).map(PageContext::Header),
             Some("redirect") => self.redirect(&data).map(PageContext::Close),
             Some("json") => self.json(&data).map(PageContext::Close),
+            Some("download") => Box::pin(self.download(&data)).await.map(PageContext::Close),
             Some("cookie") => self.add_cookie(&data).map(PageContext::Header),
-            Some("authentication") => self.authentication(data).await,
-            _ => self.start_body(data).await,
+            Some("authentication") => Box::pin(self.authentication(data)).await,
+            Some("forward") => self.forward(&data).map(PageContext::Header),
+            Some("cache") => self.cache(&data),
+            Some("validate") => self.validate(&data).map(PageContext::Header),
+            Some("flash") => self.flash(data).map(PageContext::Header),
+            _ => Box::pin(self.start_body(data)).await,
         }
     }
 
-    pub async fn handle_error(self, err: anyhow::Error) -> anyhow::Result<PageContext<W>> {
+    pub async fn handle_error(mut self, err: anyhow::Error) -> anyhow::Result<PageContext<W>> {
         if self.app_state.config.environment.is_prod() {
             return Err(err);
         }
+        // Error pages are never cached
+        self.cache = None;
         log::debug!("Handling header error: {err}");
         let data = json!({
             "component": "error",
@@ -182,10 +203,102 @@ impl<'a, W: std::io::Write> HeaderContext<W> {
         let link = get_object_str(data, "link")
             .with_context(|| "The redirect component requires a 'link' property")?;
         self.response.insert_header((header::LOCATION, link));
+        self.send_flash_cookie();
         let response = self.response.body(());
         Ok(response)
     }
 
+    /// The statements of the target file are executed next, in place of the rest of the current file.
+    /// The headers set until now are kept.
+    fn forward(self, data: &JsonValue) -> anyhow::Result<Self> {
+        let path = get_object_str(data, "path")
+            .with_context(|| "The forward component requires a 'path' property")?;
+        log::debug!("Forwarding the request to {path}");
+        Ok(self)
+    }
+
+    /// Stores a message in a cookie, to display it on the next page the user visits
+    fn flash(mut self, mut data: JsonValue) -> anyhow::Result<Self> {
+        let message = data
+            .as_object_mut()
+            .filter(|m| m.contains_key("title") || m.contains_key("description"))
+            .with_context(|| "The flash component requires a 'title' or a 'description'")?;
+        message.remove("component");
+        self.flash_messages.push(data);
+        self.flash_cookie = Some(crate::webserver::flash::encode(
+            &self.flash_messages,
+            self.app_state.cookie_signing_key(),
+        )?);
+        Ok(self)
+    }
+
+    /// Remembers the errors of a rejected form submission, to display them in the form
+    fn validate(mut self, data: &JsonValue) -> anyhow::Result<Self> {
+        let object = |key: &str| -> anyhow::Result<serde_json::Map<String, JsonValue>> {
+            match data.get(key) {
+                Some(JsonValue::Object(map)) => Ok(map.clone()),
+                Some(JsonValue::String(json)) => serde_json::from_str(json)
+                    .with_context(|| format!("Invalid JSON in the '{key}' property of the validate component: {json}")),
+                None | Some(JsonValue::Null) => Ok(serde_json::Map::new()),
+                Some(other) => bail!("The '{key}' property of the validate component must be a JSON object, not {other}"),
+            }
+        };
+        self.request_context.form_validation = Some(FormValidation {
+            errors: object("errors")?,
+            values: object("values")?,
+        });
+        Ok(self)
+    }
+
+    /// Answers with the cached page if it was rendered recently, or marks the page to be cached
+    fn cache(mut self, data: &JsonValue) -> anyhow::Result<PageContext<W>> {
+        let max_age = data
+            .get("max_age")
+            .and_then(|v| v.as_u64().or_else(|| v.as_str()?.parse().ok()))
+            .with_context(|| "The cache component requires a 'max_age' property, in seconds")?;
+        let max_age = std::time::Duration::from_secs(max_age);
+        if self.has_flash_messages() {
+            log::debug!("Not caching the page, because it has flash messages");
+            self.response
+                .insert_header((header::CACHE_CONTROL, "no-store"));
+            return Ok(PageContext::Header(self));
+        }
+        // The page may depend on the user: only their browser may store it, unless it is marked as public
+        let public = matches!(data.get("public"), Some(JsonValue::Bool(true)))
+            || data.get("public").and_then(JsonValue::as_i64) == Some(1);
+        self.response.insert_header((
+            header::CACHE_CONTROL,
+            CacheSettings::cache_control(public, max_age),
+        ));
+        let Some(path) = &self.request_context.cache_path else {
+            return Ok(PageContext::Header(self));
+        };
+        let key = cache_key(path, data.get("vary").unwrap_or(&JsonValue::Null));
+        if let Some(cached) = self.app_state.response_cache.get(&key) {
+            log::debug!("Serving {key:?} from the cache");
+            let if_none_match = self.request_context.if_none_match.as_deref();
+            let csp = &self.request_context.content_security_policy;
+            return Ok(PageContext::Close(cached.response(if_none_match, csp)));
+        }
+        let etag = CacheSettings::new_etag();
+        self.response.insert_header((header::ETAG, etag.as_str()));
+        self.cache = Some(CacheSettings {
+            key,
+            max_age,
+            public,
+            etag,
+            nonce: self.request_context.content_security_policy.nonce,
+        });
+        Ok(PageContext::Header(self))
+    }
+
+    /// Answers to the HTTP request with a file
+    async fn download(mut self, data: &JsonValue) -> anyhow::Result<HttpResponse> {
+        let download = Download::from_properties(&self.app_state, data).await?;
+        self.send_flash_cookie();
+        Ok(download.respond(self.response))
+    }
+
     /// Answers to the HTTP request with a single json object
     fn json(mut self, data: &JsonValue) -> anyhow::Result<HttpResponse> {
         let contents = data
@@ -198,6 +311,7 @@ impl<'a, W: std::io::Write> HeaderContext<W> {
         };
         self.response
             .insert_header((header::CONTENT_TYPE, "application/json"));
+        self.send_flash_cookie();
         Ok(self.response.body(json_response))
     }
 
@@ -231,20 +345,68 @@ impl<'a, W: std::io::Write> HeaderContext<W> {
         Ok(PageContext::Close(http_response))
     }
 
-    async fn start_body(self, data: JsonValue) -> anyhow::Result<PageContext<W>> {
+    /// Whether the page displays, reads or sets messages of the `flash` component.
+    /// Such a page is specific to the user who sees it, and is never cached.
+    fn has_flash_messages(&self) -> bool {
+        !self.request_context.flash_messages.is_empty()
+            || self.request_context.flash_messages_read
+            || self.flash_cookie.is_some()
+    }
+
+    async fn start_body(mut self, data: JsonValue) -> anyhow::Result<PageContext<W>> {
+        if self.has_flash_messages() && self.cache.take().is_some() {
+            log::debug!("Not caching the page, because it has flash messages");
+            self.response
+                .insert_header((header::CACHE_CONTROL, "no-store"));
+        }
         let renderer = RenderContext::new(self.app_state, self.request_context, self.writer, data)
             .await
             .with_context(|| "Failed to create a render context from the header context.")?;
-        let http_response = self.response;
+        let mut http_response = self.response;
+        set_flash_cookie(
+            &mut http_response,
+            self.flash_cookie,
+            renderer.displayed_flash_messages || renderer.request_context.flash_messages_read,
+        );
         Ok(PageContext::Body {
             renderer,
             http_response,
+            cache: self.cache,
         })
     }
 
     pub fn close(mut self) -> HttpResponse {
+        self.send_flash_cookie();
         self.response.finish()
     }
+
+    /// Sends the new flash messages, or removes the ones the page read, when there is no page body
+    fn send_flash_cookie(&mut self) {
+        let flash_messages_read = self.request_context.flash_messages_read;
+        set_flash_cookie(
+            &mut self.response,
+            self.flash_cookie.take(),
+            flash_messages_read,
+        );
+    }
+}
+
+/// Sends the messages of the `flash` component to the browser,
+/// or removes the ones that were displayed or read on the current page
+fn set_flash_cookie(response: &mut HttpResponseBuilder, value: Option<String>, displayed: bool) {
+    use crate::webserver::flash::FLASH_COOKIE;
+    let mut cookie = actix_web::cookie::Cookie::build(FLASH_COOKIE, value.unwrap_or_default())
+        .path("/")
+        .http_only(true)
+        .same_site(actix_web::cookie::SameSite::Lax)
+        .finish();
+    if cookie.value().is_empty() {
+        if !displayed {
+            return;
+        }
+        cookie.make_removal();
+    }
+    response.append_header((header::SET_COOKIE, cookie.encoded().to_string()));
 }
 
 async fn verify_password_async(
@@ -291,6 +453,8 @@ pub struct RenderContext<W: std::io::Write> {
     shell_renderer: SplitTemplateRenderer,
     current_statement: usize,
     request_context: RequestContext,
+    /// Whether the messages of the `flash` component set on the previous page were displayed
+    pub displayed_flash_messages: bool,
 }
 
 const DEFAULT_COMPONENT: &str = "table";
@@ -326,11 +490,22 @@ impl<W: std::io::Write> RenderContext<W> {
         }
         let mut rows_iter = initial_rows.into_iter().map(Cow::into_owned);
 
-        let shell_row = rows_iter
+        let mut shell_row = rows_iter
             .next()
             .expect("shell row should exist at this point");
+        let shell_component = get_object_str(&shell_row, "component")
+            .expect("shell should exist")
+            .to_owned();
+        let displayed_flash_messages = shell_component != FRAGMENT_SHELL_COMPONENT
+            && !request_context.flash_messages.is_empty();
+        if displayed_flash_messages {
+            if let Some(shell) = shell_row.as_object_mut() {
+                let messages = JsonValue::Array(request_context.flash_messages.clone());
+                shell.entry("flash_messages").or_insert(messages);
+            }
+        }
         let mut shell_renderer = Self::create_renderer(
-            get_object_str(&shell_row, "component").expect("shell should exist"),
+            &shell_component,
             Arc::clone(&app_state),
             0,
             request_context.content_security_policy.nonce,
@@ -347,6 +522,7 @@ impl<W: std::io::Write> RenderContext<W> {
             shell_renderer,
             current_statement: 1,
             request_context,
+            displayed_flash_messages,
         };
 
         for row in rows_iter {
@@ -376,7 +552,8 @@ impl<W: std::io::Write> RenderContext<W> {
                 _,
                 Some(
                     component_name @ ("status_code" | "http_header" | "redirect" | "json"
-                    | "cookie" | "authentication"),
+                    | "cookie" | "authentication" | "forward" | "cache"
+                    | "validate"),
                 ),
             ) => {
                 bail!("The {component_name} component cannot be used after data has already been sent to the client's browser. \
@@ -396,6 +573,10 @@ impl<W: std::io::Write> RenderContext<W> {
             (_, Some(new_component)) => {
                 self.open_component_with_data(new_component, &data).await?;
             }
+            (Some("form"), None) if self.request_context.form_validation.is_some() => {
+                let data = self.with_form_validation(data);
+                self.render_current_template_with_data(&data).await?;
+            }
             (Some(_current_component), None) => {
                 self.render_current_template_with_data(&data).await?;
             }
@@ -403,6 +584,26 @@ impl<W: std::io::Write> RenderContext<W> {
         Ok(())
     }
 
+    /// Shows the values and the errors of a rejected form submission in a field of the form
+    fn with_form_validation<'d>(&self, data: &'d JsonValue) -> Cow<'d, JsonValue> {
+        match (&self.request_context.form_validation, data) {
+            (Some(validation), JsonValue::Object(field)) => {
+                let mut field = field.clone();
+                validation.apply_to_field(&mut field);
+                Cow::Owned(JsonValue::Object(field))
+            }
+            _ => Cow::Borrowed(data),
+        }
+    }
+
+    /// Gives variables to the current component, such as `@pagination`,
+    /// which it receives when it is closed
+    pub fn set_component_variables(&mut self, variables: serde_json::Map<String, JsonValue>) {
+        if let Some(component) = self.current_component.as_mut() {
+            component.variables.extend(variables);
+        }
+    }
+
     #[allow(clippy::unused_async)]
     pub async fn finish_query(&mut self) -> anyhow::Result<()> {
         log::debug!("-> Query {} finished", self.current_statement);
@@ -474,6 +675,7 @@ impl<W: std::io::Write> RenderContext<W> {
             .get_template(&app_state, component)
             .await?;
         Ok(SplitTemplateRenderer::new(
+            component,
             split_template,
             app_state,
             component_index,
@@ -546,6 +748,7 @@ impl<W: std::io::Write> handlebars::Output for HandlebarWriterOutput<W> {
 }
 
 pub struct SplitTemplateRenderer {
+    component: String,
     split_template: Arc<SplitTemplate>,
     local_vars: Option<handlebars::LocalVars>,
     ctx: Context,
@@ -553,16 +756,19 @@ pub struct SplitTemplateRenderer {
     row_index: usize,
     component_index: usize,
     nonce: JsonValue,
+    variables: serde_json::Map<String, JsonValue>,
 }
 
 impl SplitTemplateRenderer {
     fn new(
+        component: &str,
         split_template: Arc<SplitTemplate>,
         app_state: Arc<AppState>,
         component_index: usize,
         nonce: u64,
     ) -> Self {
         Self {
+            component: component.to_owned(),
             split_template,
             local_vars: None,
             app_state,
@@ -570,14 +776,11 @@ impl SplitTemplateRenderer {
             ctx: Context::null(),
             component_index,
             nonce: nonce.into(),
+            variables: serde_json::Map::new(),
         }
     }
     fn name(&self) -> &str {
-        self.split_template
-            .list_content
-            .name
-            .as_deref()
-            .unwrap_or_default()
+        &self.component
     }
 
     fn render_start<W: std::io::Write>(
@@ -611,6 +814,7 @@ impl SplitTemplateRenderer {
             .block_mut()
             .map(|blk| std::mem::take(blk.local_variables_mut()));
         self.row_index = 0;
+        self.variables.clear();
         Ok(())
     }
 
@@ -661,6 +865,9 @@ impl SplitTemplateRenderer {
             local_vars.put("row_index", self.row_index.into());
             local_vars.put("component_index", self.component_index.into());
             local_vars.put("csp_nonce", self.nonce.clone());
+            for (name, value) in std::mem::take(&mut self.variables) {
+                local_vars.put(&name, value);
+            }
             log::trace!("Rendering the after_list template with the following local variables: {local_vars:?}");
             *render_context
                 .block_mut()
@@ -696,7 +903,7 @@ mod tests {
         let mut output = Vec::new();
         let config = app_config::tests::test_config();
         let app_state = Arc::new(AppState::init(&config).await.unwrap());
-        let mut rdr = SplitTemplateRenderer::new(Arc::new(split), app_state, 0, 0);
+        let mut rdr = SplitTemplateRenderer::new("test", Arc::new(split), app_state, 0, 0);
         rdr.render_start(&mut output, json!({"name": "SQL"}))?;
         rdr.render_item(&mut output, json!({"x": 1}))?;
         rdr.render_item(&mut output, json!({"x": 2}))?;
@@ -717,7 +924,7 @@ mod tests {
         let mut output = Vec::new();
         let config = app_config::tests::test_config();
         let app_state = Arc::new(AppState::init(&config).await.unwrap());
-        let mut rdr = SplitTemplateRenderer::new(Arc::new(split), app_state, 0, 0);
+        let mut rdr = SplitTemplateRenderer::new("test", Arc::new(split), app_state, 0, 0);
         rdr.render_start(&mut output, json!(null))?;
         rdr.render_item(&mut output, json!({"x": 1}))?;
         rdr.render_item(&mut output, json!({"x": 2}))?;
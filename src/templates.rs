@@ -1,11 +1,12 @@
 use crate::app_config::AppConfig;
 use crate::file_cache::AsyncFromStrWithState;
+use crate::file_cache::CacheInvalidator;
 use crate::template_helpers::register_all_helpers;
 use crate::{AppState, FileCache, TEMPLATES_DIR};
 use async_trait::async_trait;
 use handlebars::{template::TemplateElement, Handlebars, Template};
 use include_dir::{include_dir, Dir};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 pub struct SplitTemplate {
@@ -52,7 +53,11 @@ pub fn split_template(mut original: Template) -> SplitTemplate {
 
 #[async_trait(? Send)]
 impl AsyncFromStrWithState for SplitTemplate {
-    async fn from_str_with_state(_app_state: &AppState, source: &str) -> anyhow::Result<Self> {
+    async fn from_str_with_state(
+        _app_state: &AppState,
+        source: &str,
+        _source_path: &Path,
+    ) -> anyhow::Result<Self> {
         let tpl = Template::compile_with_name(source, "SQLPage component".to_string())?;
         Ok(split_template(tpl))
     }
@@ -105,6 +110,14 @@ impl AllTemplates {
         Ok(())
     }
 
+    pub(crate) fn invalidator(&self) -> CacheInvalidator<SplitTemplate> {
+        self.split_templates.invalidator()
+    }
+
+    pub(crate) fn set_watched(&mut self) {
+        self.split_templates.set_watched();
+    }
+
     pub async fn get_template(
         &self,
         app_state: &AppState,
@@ -0,0 +1,41 @@
+//! Content negotiation for precompressed static files.
+//! Other responses are compressed by actix's `Compress` middleware,
+//! which leaves the responses that already have a `Content-Encoding` untouched.
+
+use crate::app_config::AppConfig;
+use actix_web::dev::ResponseHead;
+use actix_web::http::header::{
+    self, AcceptEncoding, ContentEncoding, Encoding, Header, HeaderValue,
+};
+
+/// The encodings the client accepts, in the order we prefer them
+pub(crate) fn accepted_encodings(
+    request: &impl actix_web::HttpMessage,
+    config: &AppConfig,
+) -> Vec<ContentEncoding> {
+    let Ok(accept_encoding) = AcceptEncoding::parse(request) else {
+        return Vec::new();
+    };
+    let mut supported = Vec::with_capacity(2);
+    if config.compress_brotli {
+        supported.push(ContentEncoding::Brotli);
+    }
+    supported.push(ContentEncoding::Gzip);
+    supported.retain(|&encoding| {
+        let encoding = Encoding::Known(encoding);
+        accept_encoding.negotiate([&encoding].into_iter()) == Some(encoding)
+    });
+    supported
+}
+
+/// Tells caches that the response depends on the `Accept-Encoding` header of the request
+pub(crate) fn add_vary_accept_encoding(head: &mut ResponseHead) {
+    let already_present = head
+        .headers
+        .get_all(header::VARY)
+        .any(|v| v.as_bytes().eq_ignore_ascii_case(b"accept-encoding"));
+    if !already_present {
+        head.headers
+            .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+}
@@ -1,22 +1,88 @@
-use std::{mem::take, time::Duration};
+use std::{collections::HashMap, mem::take, sync::atomic::AtomicUsize, time::Duration};
 
-use super::Database;
+use super::sqlpage_functions::sqlite_native::native_sqlite_functions;
+use super::{sqlite_text_functions, Database};
 use crate::{app_config::AppConfig, ON_CONNECT_FILE};
 use anyhow::Context;
 use sqlx::{
-    any::{Any, AnyConnectOptions, AnyKind},
+    any::{Any, AnyConnectOptions, AnyKind, AnyPool},
     pool::PoolOptions,
     sqlite::{Function, SqliteFunctionCtx},
     ConnectOptions, Executor,
 };
 
+/// Describes one of the database connection pools to open
+struct PoolConfig<'a> {
+    database_url: &'a str,
+    password: Option<&'a str>,
+    /// Name of the configuration option in which the URL was set
+    config_option: &'a str,
+    /// Whether to run `sqlpage/on_connect.sql` on new connections
+    run_on_connect_file: bool,
+}
+
 impl Database {
     pub async fn init(config: &AppConfig) -> anyhow::Result<Self> {
-        let database_url = &config.database_url;
+        let main_pool_config = |database_url, config_option| PoolConfig {
+            database_url,
+            password: config.database_password.as_deref(),
+            config_option,
+            run_on_connect_file: true,
+        };
+        let connection = Self::connect_pool(
+            config,
+            main_pool_config(&config.database_url, "database_url"),
+        )
+        .await?;
+        let mut replicas = Vec::with_capacity(config.database_replica_urls.len());
+        for replica_url in &config.database_replica_urls {
+            let pool_config = main_pool_config(replica_url, "database_replica_urls");
+            replicas.push(Self::connect_pool(config, pool_config).await?);
+        }
+        Ok(Database {
+            connection,
+            replicas,
+            next_replica: AtomicUsize::new(0),
+        })
+    }
+
+    /// Connects to the additional databases listed in the `databases` configuration option.
+    /// They do not use the password, replicas, and `on_connect.sql` file of the main database.
+    pub async fn init_named(config: &AppConfig) -> anyhow::Result<HashMap<String, Self>> {
+        let mut databases = HashMap::with_capacity(config.databases.len());
+        for (name, database_url) in &config.databases {
+            log::info!("Connecting to the {name:?} database");
+            let pool_config = PoolConfig {
+                database_url,
+                password: None,
+                config_option: "databases",
+                run_on_connect_file: false,
+            };
+            let connection = Self::connect_pool(config, pool_config).await?;
+            let database = Database {
+                connection,
+                replicas: Vec::new(),
+                next_replica: AtomicUsize::new(0),
+            };
+            databases.insert(name.clone(), database);
+        }
+        Ok(databases)
+    }
+
+    async fn connect_pool(
+        config: &AppConfig,
+        pool_config: PoolConfig<'_>,
+    ) -> anyhow::Result<AnyPool> {
+        let PoolConfig {
+            database_url,
+            password,
+            config_option,
+            run_on_connect_file,
+        } = pool_config;
         let mut connect_options: AnyConnectOptions = database_url
             .parse()
-            .with_context(|| format!("\"{database_url}\" is not a valid database URL. Please change the \"database_url\" option in the configuration file."))?;
-        if let Some(password) = &config.database_password {
+            .with_context(|| format!("\"{database_url}\" is not a valid database URL. Please change the \"{config_option}\" option in the configuration file."))?;
+        if let Some(password) = password {
             set_database_password(&mut connect_options, password);
         }
         connect_options.log_statements(log::LevelFilter::Trace);
@@ -33,9 +99,14 @@ impl Database {
         log::info!("Connecting to database: {database_url}");
         let mut retries = config.database_connection_retries;
         let connection = loop {
-            match Self::create_pool_options(config, connect_options.kind())
-                .connect_with(connect_options.clone())
-                .await
+            match Self::create_pool_options(
+                config,
+                database_url,
+                connect_options.kind(),
+                run_on_connect_file,
+            )
+            .connect_with(connect_options.clone())
+            .await
             {
                 Ok(c) => break c,
                 Err(e) => {
@@ -50,10 +121,15 @@ impl Database {
             }
         };
         log::debug!("Initialized database pool: {connection:#?}");
-        Ok(Database { connection })
+        Ok(connection)
     }
 
-    fn create_pool_options(config: &AppConfig, db_kind: AnyKind) -> PoolOptions<Any> {
+    fn create_pool_options(
+        config: &AppConfig,
+        database_url: &str,
+        db_kind: AnyKind,
+        run_on_connect_file: bool,
+    ) -> PoolOptions<Any> {
         let mut pool_options = PoolOptions::new()
             .max_connections(if let Some(max) = config.max_database_pool_connections {
                 max
@@ -63,7 +139,7 @@ impl Database {
                     AnyKind::Postgres => 50,
                     AnyKind::MySql => 75,
                     AnyKind::Sqlite => {
-                        if config.database_url.contains(":memory:") {
+                        if database_url.contains(":memory:") {
                             // Create no more than a single in-memory database connection
                             1
                         } else {
@@ -94,7 +170,9 @@ impl Database {
             .acquire_timeout(Duration::from_secs_f64(
                 config.database_connection_acquire_timeout_seconds,
             ));
-        pool_options = add_on_connection_handler(config, pool_options);
+        if run_on_connect_file {
+            pool_options = add_on_connection_handler(config, pool_options);
+        }
         pool_options
     }
 }
@@ -138,6 +216,12 @@ fn set_custom_connect_options(options: &mut AnyConnectOptions, config: &AppConfi
             .collation("NOCASE", |a, b| a.to_lowercase().cmp(&b.to_lowercase()))
             .function(make_sqlite_fun("upper", str::to_uppercase))
             .function(make_sqlite_fun("lower", str::to_lowercase));
+        for function in native_sqlite_functions() {
+            *sqlite_options = std::mem::take(sqlite_options).function(function);
+        }
+        if config.sqlite_text_functions {
+            *sqlite_options = sqlite_text_functions::register(std::mem::take(sqlite_options));
+        }
     }
 }
 
@@ -4,23 +4,38 @@ use futures_util::StreamExt;
 use serde_json::Value;
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::future::Future;
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{error::Elapsed, Instant};
 
 use super::csv_import::run_csv_import;
 use super::sql::{
-    DelayedFunctionCall, ParsedSqlFile, ParsedStatement, SimpleSelectValue, StmtWithParams,
+    cast_placeholder_from_text, has_numbered_placeholders, make_placeholder, DelayedFunctionCall,
+    ParsedSqlFile, ParsedStatement, SetVariables, SimpleSelectValue, StmtWithParams, TableQuery,
 };
+use super::statement_cancellation::StatementCanceller;
+use super::table_query::{clamp_page_number, table_variables};
 use crate::dynamic_component::parse_dynamic_rows;
 use crate::utils::add_value_to_map;
-use crate::webserver::database::sql_to_json::row_to_string;
+use crate::webserver::database::sql_to_json::{row_to_json, sql_to_json};
+use crate::webserver::form_validation::validate_fields;
 use crate::webserver::http::SingleOrVec;
 use crate::webserver::http_request_info::RequestInfo;
+use crate::AppState;
 
-use super::syntax_tree::{extract_req_param, StmtParam};
+use super::parameter_types::{ParamType, ParamValue};
+use super::syntax_tree::{
+    extract_req_param, extract_req_param_list, extract_req_param_value, request_variable, StmtParam,
+};
 use super::{error_highlighting::display_db_error, Database, DbItem};
-use sqlx::any::{AnyArguments, AnyQueryResult, AnyRow, AnyStatement, AnyTypeInfo};
+use sqlx::any::{
+    AnyArguments, AnyKind, AnyPool, AnyQueryResult, AnyRow, AnyStatement, AnyTypeInfo,
+};
 use sqlx::pool::PoolConnection;
-use sqlx::{Any, Arguments, Column, Either, Executor, Row as _, Statement, ValueRef};
+use sqlx::{Any, Column, Either, Executor, Row as _, Statement, TypeInfo, ValueRef};
 
 pub type DbConn = Option<PoolConnection<sqlx::Any>>;
 
@@ -43,50 +58,224 @@ pub fn stream_query_results_with_conn<'a>(
     request: &'a mut RequestInfo,
     db_connection: &'a mut DbConn,
 ) -> impl Stream<Item = DbItem> + 'a {
+    // The larger futures are boxed, because this stream is nested in itself by run_sql
     async_stream::try_stream! {
-        for res in &sql_file.statements {
+        let app_state = Arc::clone(&request.app_state);
+        let db = file_database(&app_state, sql_file)?;
+        let mut connections = FileConnections::new(db_connection);
+        let mut forward = None;
+        'statements: for res in &sql_file.statements {
             match res {
                 ParsedStatement::CsvImport(csv_import) => {
-                    let connection = take_connection(&request.app_state.db, db_connection).await?;
+                    let connection = Box::pin(connections.acquire(db, sql_file, false)).await?;
                     log::debug!("Executing CSV import: {:?}", csv_import);
                     run_csv_import(connection, csv_import, request).await?;
                 },
-                ParsedStatement::StmtWithParams(stmt) => {
-                    let query = bind_parameters(stmt, request, db_connection).await?;
-                    let connection = take_connection(&request.app_state.db, db_connection).await?;
+                ParsedStatement::StmtWithParams(stmt)
+                | ParsedStatement::TableQuery(TableQuery { query: stmt, .. }) => {
+                    let table_variables = match res {
+                        ParsedStatement::TableQuery(table) => Some(Box::pin(table_query_variables(&mut connections, db, sql_file, request, table)).await?),
+                        _ => None,
+                    };
+                    let db_kind = db.connection.any_kind();
+                    let sql = expand_list_parameters(stmt, db_kind, request);
+                    let mut query = evaluate_parameters(stmt, &sql, db_kind, request, connections.main).await?;
+                    let (connection, deadline) = Box::pin(connections.acquire_with_deadline(db, sql_file, stmt.read_only, request)).await?;
+                    query.infer_types(connection).await;
+                    let query = query.bind()?;
                     log::trace!("Executing query {:?}", query.sql);
-                    let mut stream = connection.fetch_many(query);
-                    while let Some(elem) = stream.next().await {
-                        let is_err = elem.is_err();
-                        let mut query_result = parse_single_sql_result(&stmt.query, elem);
-                        apply_delayed_functions(request, &stmt.delayed_functions, &mut query_result).await?;
-                        apply_json_columns(&mut query_result, &stmt.json_columns);
-                        for i in parse_dynamic_rows(query_result) {
-                            yield i;
+                    let mut timed_out = false;
+                    {
+                        let mut stream = connection.fetch_many(query);
+                        loop {
+                            let Ok(next) = before_deadline(deadline.as_ref(), stream.next()).await else {
+                                timed_out = true;
+                                break;
+                            };
+                            let Some(elem) = next else { break };
+                            let is_err = elem.is_err();
+                            let mut query_result = parse_single_sql_result(&sql, elem);
+                            apply_delayed_functions(request, &stmt.delayed_functions, &mut query_result).await?;
+                            apply_json_columns(&mut query_result, &stmt.json_columns);
+                            for i in parse_dynamic_rows(query_result) {
+                                forward = forward_target(&i);
+                                yield i;
+                                if forward.is_some() {
+                                    break 'statements;
+                                }
+                            }
+                            if is_err {
+                                break;
+                            }
                         }
-                        if is_err {
-                            break;
+                    }
+                    if timed_out {
+                        if let Some(deadline) = deadline {
+                            deadline.cancel();
                         }
+                        connections.discard(db, sql_file, stmt.read_only);
+                        Err(query_timeout_error(request, &stmt.query))?;
+                    }
+                    if let Some(variables) = table_variables {
+                        yield DbItem::ComponentVariables(variables);
                     }
                 },
-                ParsedStatement::SetVariable { variable, value} => {
-                    execute_set_variable_query(db_connection, request, variable, value).await
+                ParsedStatement::SetVariable { variables, value} => {
+                    Box::pin(execute_set_variable_query(&mut connections, db, sql_file, request, variables, value)).await
                     .with_context(||
-                        format!("Failed to set the {variable} variable to {value:?}")
+                        format!("Failed to set the {variables} variable to {value:?}")
                     )?;
                 },
                 ParsedStatement::StaticSimpleSelect(value) => {
-                    for i in parse_dynamic_rows(DbItem::Row(exec_static_simple_select(value, request, db_connection).await?)) {
+                    for i in parse_dynamic_rows(DbItem::Row(exec_static_simple_select(value, request, connections.main).await?)) {
+                        forward = forward_target(&i);
                         yield i;
+                        if forward.is_some() {
+                            break 'statements;
+                        }
+                    }
+                }
+                ParsedStatement::Validate { properties, fields } => {
+                    let Some((errors, target)) = validate_form(properties, fields, request, connections.main).await? else { continue };
+                    yield errors;
+                    forward = Some(target);
+                    break;
+                }
+                ParsedStatement::Stop(condition) => {
+                    if Box::pin(is_stopped(&mut connections, db, sql_file, request, condition.as_ref())).await? {
+                        log::debug!("Stopping the execution of the file at a stop component");
+                        break;
                     }
                 }
                 ParsedStatement::Error(e) => yield DbItem::Error(clone_anyhow_err(e)),
             }
         }
+        if let Some(target) = forward {
+            let target_file = load_forward_target(request, target).await?;
+            let mut target_results = stream_query_results_boxed(&target_file, request, connections.main);
+            while let Some(item) = target_results.next().await {
+                yield item;
+            }
+        }
     }
     .map(|res| res.unwrap_or_else(DbItem::Error))
 }
 
+/// The file and the extra variables of a `forward` component
+struct ForwardTarget {
+    path: String,
+    variables: Option<Value>,
+}
+
+fn forward_target(item: &DbItem) -> Option<ForwardTarget> {
+    let DbItem::Row(Value::Object(row)) = item else {
+        return None;
+    };
+    if row.get("component").and_then(Value::as_str) != Some("forward") {
+        return None;
+    }
+    Some(ForwardTarget {
+        path: row.get("path").and_then(Value::as_str)?.to_owned(),
+        variables: row.get("variables").cloned(),
+    })
+}
+
+/// Checks the submitted form against the fields of a `validate` component.
+/// When the form is invalid, returns a row with the errors and the submitted values,
+/// and the form page the request is sent back to.
+async fn validate_form(
+    properties: &[(String, SimpleSelectValue)],
+    fields: &[Vec<(String, SimpleSelectValue)>],
+    request: &mut RequestInfo,
+    db_connection: &mut DbConn,
+) -> anyhow::Result<Option<(DbItem, ForwardTarget)>> {
+    if request.method != actix_web::http::Method::POST || request.validation_failed {
+        return Ok(None);
+    }
+    let properties = exec_static_simple_select(properties, request, db_connection).await?;
+    let mut field_rows = Vec::with_capacity(fields.len());
+    for field in fields {
+        field_rows.push(exec_static_simple_select(field, request, db_connection).await?);
+    }
+    let errors = validate_fields(
+        &field_rows,
+        &request.post_variables,
+        &request.uploaded_files,
+    )?;
+    if errors.is_empty() {
+        return Ok(None);
+    }
+    let Some(form) = properties.get("form").and_then(Value::as_str) else {
+        let errors = errors
+            .iter()
+            .map(|(name, message)| format!("{name}: {}", message.as_str().unwrap_or_default()))
+            .collect::<Vec<_>>();
+        anyhow::bail!(
+            "The submitted form is invalid. {}\n\
+            Set the 'form' property of the validate component to display the errors in the form instead.",
+            errors.join(" ")
+        );
+    };
+    log::debug!("Invalid form submission, sending the user back to {form}: {errors:?}");
+    // The form page sees a request without a form submission, and the submitted values are displayed in the form
+    request.validation_failed = true;
+    request.uploaded_files = Rc::default();
+    let values = serde_json::to_value(std::mem::take(&mut request.post_variables))?;
+    let row = serde_json::json!({
+        "component": "validate",
+        "errors": errors,
+        "values": values,
+    });
+    let target = ForwardTarget {
+        path: form.to_owned(),
+        variables: None,
+    };
+    Ok(Some((DbItem::Row(row), target)))
+}
+
+/// Loads the file a request is forwarded to, and adds the extra variables to the request
+async fn load_forward_target(
+    request: &mut RequestInfo,
+    ForwardTarget { path, variables }: ForwardTarget,
+) -> anyhow::Result<Arc<ParsedSqlFile>> {
+    request.clone_depth += 1;
+    if request.clone_depth > 8 {
+        anyhow::bail!(
+            "Too many nested forwards. Forwarding to {path:?} would exceed the limit of 8 levels. \
+            Make sure that your SQL file does not forward the request to itself, directly or through a chain of other files."
+        );
+    }
+    let variables = match variables {
+        Some(Value::String(json)) => serde_json::from_str(&json).with_context(|| {
+            format!("Invalid JSON in the variables of the forward component: {json}")
+        })?,
+        Some(variables) => variables,
+        None => Value::Null,
+    };
+    match variables {
+        Value::Object(variables) => {
+            for (name, value) in variables {
+                if let Some(value) = json_to_variable(value) {
+                    request.get_variables.insert(name, value);
+                } else {
+                    request.get_variables.remove(&name);
+                }
+            }
+        }
+        Value::Null => {}
+        other => anyhow::bail!(
+            "The variables of the forward component must be a JSON object, not {other}"
+        ),
+    }
+    log::debug!("Forwarding the request to {path:?}");
+    let app_state = Arc::clone(&request.app_state);
+    app_state
+        .sql_file_cache
+        .get_with_privilege(&app_state, std::path::Path::new(&path), true)
+        .await
+        .with_context(|| format!("Unable to forward the request to {path:?}"))
+}
+
 pub fn stop_at_first_error(
     results_stream: impl Stream<Item = DbItem>,
 ) -> impl Stream<Item = DbItem> {
@@ -125,6 +314,9 @@ async fn extract_req_param_as_json(
     request: &RequestInfo,
     db_connection: &mut DbConn,
 ) -> anyhow::Result<serde_json::Value> {
+    if let Some(SingleOrVec::Json(value)) = request_variable(param, request) {
+        return Ok(value.clone());
+    }
     if let Some(val) = extract_req_param(param, request, db_connection).await? {
         Ok(serde_json::Value::String(val.into_owned()))
     } else {
@@ -146,34 +338,179 @@ pub fn stream_query_results_boxed<'a>(
     ))
 }
 
-async fn execute_set_variable_query<'a>(
-    db_connection: &'a mut DbConn,
-    request: &'a mut RequestInfo,
-    variable: &StmtParam,
+async fn execute_set_variable_query(
+    connections: &mut FileConnections<'_>,
+    db: &Database,
+    sql_file: &ParsedSqlFile,
+    request: &mut RequestInfo,
+    variables: &SetVariables,
     statement: &StmtWithParams,
 ) -> anyhow::Result<()> {
-    let query = bind_parameters(statement, request, db_connection).await?;
-    let connection = take_connection(&request.app_state.db, db_connection).await?;
-    log::debug!(
-        "Executing query to set the {variable:?} variable: {:?}",
-        query.sql
-    );
-    let value: Option<String> = connection
-        .fetch_optional(query)
-        .await?
-        .as_ref()
-        .and_then(row_to_string);
-    let (vars, name) = vars_and_name(request, variable)?;
-    if let Some(value) = value {
-        log::debug!("Setting variable {name} to {value:?}");
-        vars.insert(name.to_owned(), SingleOrVec::Single(value));
-    } else {
-        log::debug!("Removing variable {name}");
-        vars.remove(name);
+    let all_rows = matches!(variables, SetVariables::Rows(_));
+    let rows = fetch_rows(connections, db, sql_file, request, statement, all_rows).await?;
+    let first_row = rows.first();
+    let assignments = match variables {
+        SetVariables::Single(variable) => {
+            vec![(
+                variable,
+                first_row.and_then(|row| column_to_variable(row, 0)),
+            )]
+        }
+        SetVariables::Columns(variables) => {
+            if let Some(row) = first_row {
+                let column_count = row.columns().len();
+                if column_count != variables.len() {
+                    anyhow::bail!(
+                        "{} variables are set, but the query returned {column_count} columns",
+                        variables.len()
+                    );
+                }
+            }
+            variables
+                .iter()
+                .enumerate()
+                .map(|(i, variable)| {
+                    (
+                        variable,
+                        first_row.and_then(|row| column_to_variable(row, i)),
+                    )
+                })
+                .collect()
+        }
+        SetVariables::Rows(variable) => {
+            let rows = rows.iter().map(row_to_set_value).collect();
+            vec![(variable, Some(SingleOrVec::Json(Value::Array(rows))))]
+        }
+    };
+    for (variable, value) in assignments {
+        let (vars, name) = vars_and_name(request, variable)?;
+        if let Some(value) = value {
+            log::debug!("Setting variable {name} to {value:?}");
+            vars.insert(name.to_owned(), value);
+        } else {
+            log::debug!("Removing variable {name}");
+            vars.remove(name);
+        }
     }
     Ok(())
 }
 
+/// A `stop` component stops the execution of the file when its query returns a row
+async fn is_stopped(
+    connections: &mut FileConnections<'_>,
+    db: &Database,
+    sql_file: &ParsedSqlFile,
+    request: &RequestInfo,
+    condition: Option<&StmtWithParams>,
+) -> anyhow::Result<bool> {
+    Ok(match condition {
+        None => true,
+        Some(stmt) => !fetch_rows(connections, db, sql_file, request, stmt, false)
+            .await?
+            .is_empty(),
+    })
+}
+
+/// Counts the rows of a paginated query, and returns the variables given to the component that displays it
+async fn table_query_variables(
+    connections: &mut FileConnections<'_>,
+    db: &Database,
+    sql_file: &ParsedSqlFile,
+    request: &mut RequestInfo,
+    table: &TableQuery,
+) -> anyhow::Result<serde_json::Map<String, Value>> {
+    let total_rows = match &table.pagination {
+        Some(pagination) => {
+            let total_rows =
+                count_rows(connections, db, sql_file, request, &pagination.count).await?;
+            clamp_page_number(request, pagination, total_rows);
+            Some(total_rows)
+        }
+        None => None,
+    };
+    Ok(table_variables(table, total_rows, request))
+}
+
+/// Runs the query that counts the rows on all the pages of a paginated query
+async fn count_rows(
+    connections: &mut FileConnections<'_>,
+    db: &Database,
+    sql_file: &ParsedSqlFile,
+    request: &RequestInfo,
+    count: &StmtWithParams,
+) -> anyhow::Result<u64> {
+    let rows = fetch_rows(connections, db, sql_file, request, count, false).await?;
+    let total = rows
+        .first()
+        .and_then(|row| Some(sql_to_json(row, row.columns().first()?)));
+    match &total {
+        Some(Value::Number(n)) => n.as_u64(),
+        Some(Value::String(s)) => s.parse().ok(),
+        _ => None,
+    }
+    .with_context(|| format!("Unable to count the rows of the paginated query: {total:?}"))
+}
+
+/// Runs a statement whose results are used by `SQLPage` itself instead of being rendered.
+/// Only the first row is fetched, unless `all_rows` is set.
+async fn fetch_rows(
+    connections: &mut FileConnections<'_>,
+    db: &Database,
+    sql_file: &ParsedSqlFile,
+    request: &RequestInfo,
+    statement: &StmtWithParams,
+    all_rows: bool,
+) -> anyhow::Result<Vec<AnyRow>> {
+    let db_kind = db.connection.any_kind();
+    let sql = expand_list_parameters(statement, db_kind, request);
+    let mut query =
+        evaluate_parameters(statement, &sql, db_kind, request, connections.main).await?;
+    let (connection, deadline) = connections
+        .acquire_with_deadline(db, sql_file, statement.read_only, request)
+        .await?;
+    query.infer_types(connection).await;
+    let query = query.bind()?;
+    log::debug!("Executing query {:?}", query.sql);
+    let fetched = if all_rows {
+        before_deadline(deadline.as_ref(), connection.fetch_all(query)).await
+    } else {
+        before_deadline(deadline.as_ref(), connection.fetch_optional(query))
+            .await
+            .map(|row| row.map(|row| row.into_iter().collect()))
+    };
+    let Ok(rows) = fetched else {
+        if let Some(deadline) = deadline {
+            deadline.cancel();
+        }
+        connections.discard(db, sql_file, statement.read_only);
+        return Err(query_timeout_error(request, &statement.query));
+    };
+    Ok(rows?)
+}
+
+fn column_to_variable(row: &AnyRow, index: usize) -> Option<SingleOrVec> {
+    let column = row.columns().get(index)?;
+    json_to_variable(sql_to_json(row, column))
+}
+
+/// Strings are stored as they are, and other values keep their JSON type.
+/// NULL removes the variable.
+fn json_to_variable(value: Value) -> Option<SingleOrVec> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(SingleOrVec::Single(s)),
+        other => Some(SingleOrVec::Json(other)),
+    }
+}
+
+/// Rows with a single column become a single value, and the others become objects
+fn row_to_set_value(row: &AnyRow) -> Value {
+    match row.columns() {
+        [column] => sql_to_json(row, column),
+        _ => row_to_json(row),
+    }
+}
+
 fn vars_and_name<'a, 'b>(
     request: &'a mut RequestInfo,
     variable: &'b StmtParam,
@@ -202,19 +539,190 @@ async fn take_connection<'a, 'b>(
     if let Some(c) = conn {
         return Ok(c);
     }
-    match db.connection.acquire().await {
+    acquire_connection(&db.connection, conn).await
+}
+
+async fn acquire_connection<'b>(
+    pool: &AnyPool,
+    conn: &'b mut DbConn,
+) -> anyhow::Result<&'b mut PoolConnection<sqlx::Any>> {
+    match pool.acquire().await {
         Ok(c) => {
             log::debug!("Acquired a database connection");
             *conn = Some(c);
             Ok(conn.as_mut().unwrap())
         }
         Err(e) => {
-            let err_msg = format!("Unable to acquire a database connection to execute the SQL file. All of the {} {:?} connections are busy.", db.connection.size(), db.connection.any_kind());
+            let err_msg = format!("Unable to acquire a database connection to execute the SQL file. All of the {} {:?} connections are busy.", pool.size(), pool.any_kind());
             Err(anyhow::Error::new(e).context(err_msg))
         }
     }
 }
 
+/// The database a SQL file runs against: the main one, or the one named in its `-- @database` comment.
+fn file_database<'a>(
+    app_state: &'a AppState,
+    sql_file: &ParsedSqlFile,
+) -> anyhow::Result<&'a Database> {
+    match &sql_file.database {
+        None => Ok(&app_state.db),
+        Some(name) => app_state
+            .databases
+            .get(name)
+            .ok_or_else(|| anyhow!("There is no database named {name:?}")),
+    }
+}
+
+/// The connections used to run the statements of a single SQL file
+struct FileConnections<'c> {
+    /// Connection to the main database, shared with the rest of the request.
+    /// sqlpage functions always use it.
+    main: &'c mut DbConn,
+    /// Connection to a read replica of the main database
+    replica: DbConn,
+    /// The pool of the replica that `replica` comes from
+    replica_pool: Option<AnyPool>,
+    /// Connection to the database selected with a `-- @database name` comment
+    named_database: DbConn,
+}
+
+impl<'c> FileConnections<'c> {
+    fn new(main: &'c mut DbConn) -> Self {
+        Self {
+            main,
+            replica: None,
+            replica_pool: None,
+            named_database: None,
+        }
+    }
+
+    /// Returns the connection on which a statement of the file should run, acquiring it if needed.
+    async fn acquire(
+        &mut self,
+        db: &Database,
+        sql_file: &ParsedSqlFile,
+        read_only: bool,
+    ) -> anyhow::Result<&mut PoolConnection<sqlx::Any>> {
+        let on_replica = self.slot(db, sql_file, read_only).1;
+        if on_replica && self.replica.is_none() {
+            let replica = db
+                .replica()
+                .ok_or_else(|| anyhow!("No read replica is configured"))?;
+            self.replica_pool = Some(replica.clone());
+            acquire_connection(replica, &mut self.replica).await?;
+        }
+        take_connection(db, self.slot(db, sql_file, read_only).0).await
+    }
+
+    /// Like [`FileConnections::acquire`], for a statement that can run for at most `max_query_duration_seconds`
+    async fn acquire_with_deadline(
+        &mut self,
+        db: &Database,
+        sql_file: &ParsedSqlFile,
+        read_only: bool,
+        request: &RequestInfo,
+    ) -> anyhow::Result<(&mut PoolConnection<sqlx::Any>, Option<Deadline>)> {
+        self.acquire(db, sql_file, read_only).await?;
+        let mut deadline = None;
+        if let Some(max_duration) = request.app_state.config.max_query_duration_seconds {
+            let pool = if self.slot(db, sql_file, read_only).1 {
+                self.replica_pool
+                    .clone()
+                    .expect("the replica was just acquired")
+            } else {
+                db.connection.clone()
+            };
+            let connection = self.slot(db, sql_file, read_only).0.as_mut();
+            let connection = connection.expect("the connection was just acquired");
+            let canceller = StatementCanceller::new(pool, connection).await?;
+            deadline = Some(Deadline {
+                at: Instant::now() + Duration::from_secs_f64(max_duration),
+                canceller,
+            });
+        }
+        let connection = self.slot(db, sql_file, read_only).0.as_mut();
+        Ok((
+            connection.expect("the connection was just acquired"),
+            deadline,
+        ))
+    }
+
+    /// Closes the connection returned by [`FileConnections::acquire`] for the same statement
+    fn discard(&mut self, db: &Database, sql_file: &ParsedSqlFile, read_only: bool) {
+        discard_connection(self.slot(db, sql_file, read_only).0);
+    }
+
+    /// Read-only statements run on a replica, unless the request has already used the primary database.
+    /// In that case, it keeps using the primary, to see its own writes and stay in the same transaction.
+    fn slot(
+        &mut self,
+        db: &Database,
+        sql_file: &ParsedSqlFile,
+        read_only: bool,
+    ) -> (&mut DbConn, bool) {
+        let primary = if sql_file.database.is_some() {
+            &mut self.named_database
+        } else {
+            &mut *self.main
+        };
+        let on_replica = read_only
+            && !sql_file.use_primary_database
+            && primary.is_none()
+            && !db.replicas.is_empty();
+        if on_replica {
+            (&mut self.replica, true)
+        } else {
+            (primary, false)
+        }
+    }
+}
+
+/// Closes a connection that may still be running a statement, instead of returning it to the pool,
+/// where it would stay busy until the statement finishes.
+/// This does not cancel the statement: depending on the database,
+/// it may keep running on the server until it notices that the connection is closed.
+pub(crate) fn discard_connection(db_connection: &mut DbConn) {
+    if let Some(connection) = db_connection.take() {
+        log::debug!("Closing a database connection that may still be executing a statement");
+        drop(connection.detach());
+    }
+}
+
+/// The instant after which `SQLPage` stops waiting for a statement,
+/// according to the `max_query_duration_seconds` configuration option
+struct Deadline {
+    at: Instant,
+    canceller: StatementCanceller,
+}
+
+impl Deadline {
+    fn cancel(self) {
+        self.canceller.cancel();
+    }
+}
+
+async fn before_deadline<F: Future>(
+    deadline: Option<&Deadline>,
+    future: F,
+) -> Result<F::Output, Elapsed> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.at, future).await,
+        None => Ok(future.await),
+    }
+}
+
+fn query_timeout_error(request: &RequestInfo, query: &str) -> anyhow::Error {
+    let max_duration = request
+        .app_state
+        .config
+        .max_query_duration_seconds
+        .unwrap_or_default();
+    anyhow!(
+        "The following SQL statement was cancelled because it took longer than {max_duration} seconds.\n\
+        You can change this limit with the max_query_duration_seconds configuration option.\n\n{query}"
+    )
+}
+
 #[inline]
 fn parse_single_sql_result(sql: &str, res: sqlx::Result<Either<AnyQueryResult, AnyRow>>) -> DbItem {
     match res {
@@ -260,29 +768,148 @@ fn clone_anyhow_err(err: &anyhow::Error) -> anyhow::Error {
     e
 }
 
-async fn bind_parameters<'a, 'b>(
+/// Expands the list parameters of the statement (`x IN ($ids)`) to one placeholder per value
+fn expand_list_parameters<'a>(
     stmt: &'a StmtWithParams,
+    db_kind: AnyKind,
+    request: &RequestInfo,
+) -> Cow<'a, str> {
+    let list_lengths: Vec<Option<usize>> = stmt
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            StmtParam::List(values) => Some(list_length(values, request)),
+            _ => None,
+        })
+        .collect();
+    stmt.query_with_lists(db_kind, &list_lengths)
+}
+
+/// The number of values of a list parameter, or None when the variable has a single value
+fn list_length(values: &StmtParam, request: &RequestInfo) -> Option<usize> {
+    match request_variable(values, request) {
+        Some(SingleOrVec::Single(_)) => None,
+        Some(SingleOrVec::Json(value)) if !value.is_array() => None,
+        _ => Some(extract_req_param_list(values, request).len()),
+    }
+}
+
+/// Evaluates the parameters of a statement, in the order in which they are bound
+async fn evaluate_parameters<'a>(
+    stmt: &'a StmtWithParams,
+    sql: &'a str,
+    db_kind: AnyKind,
     request: &'a RequestInfo,
-    db_connection: &'b mut DbConn,
-) -> anyhow::Result<StatementWithParams<'a>> {
-    let sql = stmt.query.as_str();
+    db_connection: &mut DbConn,
+) -> anyhow::Result<EvaluatedStatement<'a>> {
     log::debug!("Preparing statement: {}", sql);
-    let mut arguments = AnyArguments::default();
+    let mut params = Vec::with_capacity(stmt.params.len());
+    // With numbered placeholders, the values of list parameters come after all the other parameters
+    let mut list_values = Vec::new();
     for (param_idx, param) in stmt.params.iter().enumerate() {
         log::trace!("\tevaluating parameter {}: {}", param_idx + 1, param);
-        let argument = extract_req_param(param, request, db_connection).await?;
+        if let StmtParam::List(values) = param {
+            let values = extract_req_param_list(values, request);
+            log::debug!("\tparameter {}: {values:?}", param_idx + 1);
+            let values = values
+                .into_iter()
+                .map(|v| (Some(ParamValue::Text(v)), ParamType::Text));
+            if has_numbered_placeholders(db_kind) {
+                list_values.extend(values);
+            } else {
+                params.extend(values);
+            }
+            continue;
+        }
+        let argument = extract_req_param_value(param, request, db_connection).await?;
         log::debug!(
             "\tparameter {}: {}",
             param_idx + 1,
-            argument.as_ref().unwrap_or(&Cow::Borrowed("NULL"))
+            argument
+                .as_ref()
+                .map_or_else(|| Cow::Borrowed("NULL"), |v| Cow::Owned(v.to_string()))
         );
-        match argument {
-            None => arguments.add(None::<String>),
-            Some(Cow::Owned(s)) => arguments.add(s),
-            Some(Cow::Borrowed(v)) => arguments.add(v),
+        let param_type = match param {
+            StmtParam::Typed(param_type, _) => *param_type,
+            _ => ParamType::Text,
+        };
+        params.push((argument, param_type));
+    }
+    params.extend(list_values);
+    Ok(EvaluatedStatement {
+        sql: Cow::Borrowed(sql),
+        params,
+    })
+}
+
+/// A statement with the values of its parameters, before they are converted to the types
+/// expected by the database
+struct EvaluatedStatement<'a> {
+    sql: Cow<'a, str>,
+    params: Vec<(Option<ParamValue<'a>>, ParamType)>,
+}
+
+impl EvaluatedStatement<'_> {
+    /// Asks the database for the types of the parameters that the SQL does not give.
+    /// Values of types that `SQLPage` cannot convert are sent as text, and converted by the database.
+    async fn infer_types(&mut self, connection: &mut PoolConnection<Any>) {
+        if !self.params.iter().any(|(_, t)| *t == ParamType::Inferred) {
+            return;
+        }
+        let types = inferred_parameter_types(&self.sql, connection).await;
+        let Self { sql, params } = self;
+        for (idx, (_, param_type)) in params.iter_mut().enumerate() {
+            if *param_type != ParamType::Inferred {
+                continue;
+            }
+            *param_type = ParamType::Text;
+            let Some(type_info) = types.get(idx) else {
+                continue;
+            };
+            if let Some(inferred) = ParamType::from_type_info(type_info) {
+                *param_type = inferred;
+            } else {
+                let placeholder = make_placeholder(AnyKind::Postgres, idx + 1);
+                *sql = Cow::Owned(cast_placeholder_from_text(
+                    sql,
+                    &placeholder,
+                    type_info.name(),
+                ));
+            }
+        }
+    }
+
+    /// Converts the parameter values to their types
+    fn bind(&mut self) -> anyhow::Result<StatementWithParams<'_>> {
+        let mut arguments = AnyArguments::default();
+        for (idx, (value, param_type)) in self.params.drain(..).enumerate() {
+            param_type
+                .bind(&mut arguments, value)
+                .with_context(|| format!("Invalid value for parameter {}", idx + 1))?;
+        }
+        Ok(StatementWithParams {
+            sql: &self.sql,
+            arguments,
+        })
+    }
+}
+
+/// Prepares the statement to get the types of its parameters.
+/// If preparing fails, the parameters are bound as text, and executing the statement will report the error.
+async fn inferred_parameter_types(
+    sql: &str,
+    connection: &mut PoolConnection<Any>,
+) -> Vec<AnyTypeInfo> {
+    match connection.prepare(sql).await {
+        Ok(statement) => match statement.parameters() {
+            Some(Either::Left(types)) => types.to_vec(),
+            _ => Vec::new(),
+        },
+        Err(e) => {
+            log::debug!("Unable to get the parameter types of {sql:?}: {e}");
+            Vec::new()
         }
     }
-    Ok(StatementWithParams { sql, arguments })
 }
 
 async fn apply_delayed_functions(
@@ -2,30 +2,55 @@ mod connect;
 mod csv_import;
 pub mod execute_queries;
 pub mod migrations;
+mod parameter_types;
 mod sql;
+mod sqlite_text_functions;
 mod sqlpage_functions;
+mod statement_cancellation;
 mod syntax_tree;
+mod table_query;
 
 mod error_highlighting;
 mod sql_to_json;
 
 pub use sql::{make_placeholder, ParsedSqlFile};
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 pub struct Database {
     pub(crate) connection: sqlx::AnyPool,
+    /// Pools of connections to read-only replicas of the main database
+    pub(crate) replicas: Vec<sqlx::AnyPool>,
+    next_replica: AtomicUsize,
 }
 impl Database {
     pub async fn close(&self) -> anyhow::Result<()> {
         log::info!("Closing all database connections...");
         self.connection.close().await;
+        for replica in &self.replicas {
+            replica.close().await;
+        }
         Ok(())
     }
+
+    /// Picks the replica on which to run the next read-only statement, in round-robin order.
+    /// Returns None if no replicas are configured.
+    pub(crate) fn replica(&self) -> Option<&sqlx::AnyPool> {
+        if self.replicas.is_empty() {
+            return None;
+        }
+        let idx = self.next_replica.fetch_add(1, Ordering::Relaxed);
+        self.replicas.get(idx % self.replicas.len())
+    }
 }
 
 #[derive(Debug)]
 pub enum DbItem {
     Row(serde_json::Value),
     FinishedQuery,
+    /// Variables given to the current component when it is closed, such as the page metadata of a paginated query.
+    /// Sent after the rows of the query.
+    ComponentVariables(serde_json::Map<String, serde_json::Value>),
     Error(anyhow::Error),
 }
 
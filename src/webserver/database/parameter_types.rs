@@ -0,0 +1,217 @@
+//! Parameters are evaluated as text, and then converted to the type the database expects.
+//! The contents of uploaded files are the exception: they are bound as binary data.
+//! The type is known from a `CAST` around the parameter in the SQL (`CAST($id AS INTEGER)`),
+//! or, in `PostgreSQL`, from the parameter types of the prepared statement (`WHERE id = $id`).
+//! Dates and timestamps are left to the database, that knows the time zone of the session.
+
+use std::borrow::Cow;
+
+use anyhow::Context;
+use sqlparser::ast::DataType;
+use sqlx::any::{AnyArguments, AnyTypeInfo};
+use sqlx::{Arguments, TypeInfo};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum ParamType {
+    Text,
+    SmallInt,
+    Int,
+    BigInt,
+    Real,
+    Double,
+    Boolean,
+    Json,
+    /// Dates and timestamps are sent as text, and converted by the cast in the SQL
+    DateTime,
+    /// Binary data (BLOB, BYTEA, VARBINARY), used for the contents of uploaded files
+    Blob,
+    /// The type is given by the database when the statement is prepared
+    Inferred,
+}
+
+impl ParamType {
+    /// The type with which to bind a parameter that the SQL casts to the given type.
+    /// Returns None when the parameter can stay text.
+    pub(super) fn from_cast(data_type: &DataType) -> Option<Self> {
+        Some(match data_type {
+            DataType::TinyInt(_)
+            | DataType::SmallInt(_)
+            | DataType::Int2(_)
+            | DataType::MediumInt(_)
+            | DataType::Int(_)
+            | DataType::Int4(_)
+            | DataType::Int8(_)
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::Integer(_)
+            | DataType::BigInt(_) => Self::BigInt,
+            DataType::Float(_)
+            | DataType::Float4
+            | DataType::Float8
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::Real
+            | DataType::Double
+            | DataType::DoublePrecision => Self::Double,
+            DataType::Bool | DataType::Boolean => Self::Boolean,
+            DataType::JSON | DataType::JSONB => Self::Json,
+            DataType::Date | DataType::Datetime(_) | DataType::Timestamp(_, _) => Self::DateTime,
+            _ => return None,
+        })
+    }
+
+    /// The type with which to bind a parameter of the given type in a prepared statement.
+    /// Returns None for the types that `SQLPage` does not know how to send to the database,
+    /// and for dates and timestamps, that the database parses itself.
+    pub(super) fn from_type_info(type_info: &AnyTypeInfo) -> Option<Self> {
+        Some(match type_info.name() {
+            "TEXT" | "VARCHAR" | "BPCHAR" | "CHAR" | "NAME" => Self::Text,
+            "INT2" => Self::SmallInt,
+            "INT4" => Self::Int,
+            "INT8" => Self::BigInt,
+            "FLOAT4" => Self::Real,
+            "FLOAT8" => Self::Double,
+            "BOOL" => Self::Boolean,
+            "JSON" | "JSONB" => Self::Json,
+            _ => return None,
+        })
+    }
+
+    /// Converts the value of a parameter, and adds it to the arguments of a statement.
+    /// Binary values are always bound as they are.
+    /// Empty values, such as the ones of optional form fields, are NULL when they are not text.
+    pub(super) fn bind<'q>(
+        self,
+        arguments: &mut AnyArguments<'q>,
+        value: Option<ParamValue<'q>>,
+    ) -> anyhow::Result<()> {
+        let value = match value {
+            None => {
+                self.bind_null(arguments);
+                return Ok(());
+            }
+            Some(ParamValue::Bytes(bytes)) => {
+                arguments.add(bytes);
+                return Ok(());
+            }
+            Some(ParamValue::Text(value)) => value,
+        };
+        let text = value.trim();
+        if text.is_empty() && !matches!(self, Self::Text | Self::Inferred | Self::Blob) {
+            self.bind_null(arguments);
+            return Ok(());
+        }
+        let invalid = || format!("{value:?} is not a valid {self}");
+        match self {
+            Self::Text | Self::Inferred | Self::DateTime => match value {
+                Cow::Owned(s) => arguments.add(s),
+                Cow::Borrowed(s) => arguments.add(s),
+            },
+            Self::SmallInt => arguments.add(text.parse::<i16>().with_context(invalid)?),
+            Self::Int => arguments.add(text.parse::<i32>().with_context(invalid)?),
+            Self::BigInt => arguments.add(text.parse::<i64>().with_context(invalid)?),
+            Self::Real => arguments.add(text.parse::<f32>().with_context(invalid)?),
+            Self::Double => arguments.add(text.parse::<f64>().with_context(invalid)?),
+            Self::Boolean => arguments.add(parse_bool(text).with_context(invalid)?),
+            Self::Json => {
+                arguments
+                    .add(serde_json::from_str::<serde_json::Value>(text).with_context(invalid)?);
+            }
+            Self::Blob => arguments.add(value.into_owned().into_bytes()),
+        }
+        Ok(())
+    }
+
+    /// NULL values have a type too: `PostgreSQL` rejects a text NULL for an integer parameter
+    fn bind_null(self, arguments: &mut AnyArguments<'_>) {
+        match self {
+            Self::Text | Self::Inferred | Self::DateTime => arguments.add(None::<String>),
+            Self::SmallInt => arguments.add(None::<i16>),
+            Self::Int => arguments.add(None::<i32>),
+            Self::BigInt => arguments.add(None::<i64>),
+            Self::Real => arguments.add(None::<f32>),
+            Self::Double => arguments.add(None::<f64>),
+            Self::Boolean => arguments.add(None::<bool>),
+            Self::Json => arguments.add(None::<serde_json::Value>),
+            Self::Blob => arguments.add(None::<Vec<u8>>),
+        }
+    }
+}
+
+impl std::fmt::Display for ParamType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Text | Self::Inferred => "text",
+            Self::SmallInt | Self::Int | Self::BigInt => "integer",
+            Self::Real | Self::Double => "number",
+            Self::Boolean => "boolean",
+            Self::Json => "JSON value",
+            Self::DateTime => "date",
+            Self::Blob => "binary value",
+        })
+    }
+}
+
+/// The value of a parameter, before it is bound
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ParamValue<'a> {
+    Text(Cow<'a, str>),
+    /// The contents of an uploaded file, from `sqlpage.uploaded_file_bytes`
+    Bytes(Vec<u8>),
+}
+
+impl std::fmt::Display for ParamValue<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Text(text) => f.write_str(text),
+            Self::Bytes(bytes) => write!(f, "<{} bytes>", bytes.len()),
+        }
+    }
+}
+
+/// Accepts the values sent by checkboxes ("on") in addition to the usual boolean spellings
+fn parse_bool(text: &str) -> anyhow::Result<bool> {
+    match text.to_ascii_lowercase().as_str() {
+        "true" | "t" | "yes" | "y" | "on" | "1" => Ok(true),
+        "false" | "f" | "no" | "n" | "off" | "0" => Ok(false),
+        _ => anyhow::bail!("expected true or false"),
+    }
+}
+
+#[test]
+fn test_parse_parameter_values() {
+    assert!(parse_bool("ON").unwrap());
+    assert!(!parse_bool("0").unwrap());
+    assert!(parse_bool("maybe").is_err());
+    let mut arguments = AnyArguments::default();
+    let err = ParamType::BigInt
+        .bind(
+            &mut arguments,
+            Some(ParamValue::Text(Cow::Borrowed("12abc"))),
+        )
+        .unwrap_err();
+    assert_eq!(err.to_string(), "\"12abc\" is not a valid integer");
+    assert!(ParamType::BigInt
+        .bind(
+            &mut arguments,
+            Some(ParamValue::Text(Cow::Borrowed(" 12 ")))
+        )
+        .is_ok());
+    assert!(ParamType::DateTime
+        .bind(&mut arguments, Some(ParamValue::Text(Cow::Borrowed(""))))
+        .is_ok());
+    // The database parses timestamps with any offset it supports
+    assert!(ParamType::DateTime
+        .bind(
+            &mut arguments,
+            Some(ParamValue::Text(Cow::Borrowed("2024-10-01 15:30:00+02")))
+        )
+        .is_ok());
+    assert!(ParamType::Blob
+        .bind(
+            &mut arguments,
+            Some(ParamValue::Bytes(vec![0, 159, 146, 150]))
+        )
+        .is_ok());
+}
@@ -1,39 +1,177 @@
 use super::csv_import::{extract_csv_copy_statement, CsvImport};
+use super::parameter_types::ParamType;
 use super::sqlpage_functions::functions::SqlPageFunctionName;
+use super::sqlpage_functions::sqlite_native::native_sqlite_function_name;
 use super::sqlpage_functions::{are_params_extractable, func_call_to_param};
 use super::syntax_tree::StmtParam;
+use crate::app_config::AppConfig;
 use crate::file_cache::AsyncFromStrWithState;
 use crate::webserver::database::error_highlighting::quote_source_with_highlight;
 use crate::{AppState, Database};
+use anyhow::Context as _;
 use async_trait::async_trait;
+use futures_util::future::LocalBoxFuture;
 use sqlparser::ast::{
-    BinaryOperator, CastKind, CharacterLength, DataType, Expr, Function, FunctionArg,
-    FunctionArgExpr, FunctionArgumentList, FunctionArguments, Ident, ObjectName,
-    OneOrManyWithParens, SelectItem, SetExpr, Statement, Value, VisitMut, VisitorMut,
+    BinaryOperator, CastKind, CharacterLength, DataType, Expr, Fetch, Function, FunctionArg,
+    FunctionArgExpr, FunctionArgumentList, FunctionArguments, Ident, Insert, ObjectName, Offset,
+    OffsetRows, OneOrManyWithParens, OrderBy, OrderByExpr, Query, SelectItem, SetExpr, Statement,
+    TableFactor, Value, Visit, VisitMut, Visitor, VisitorMut,
 };
 use sqlparser::dialect::{Dialect, MsSqlDialect, MySqlDialect, PostgreSqlDialect, SQLiteDialect};
+use sqlparser::keywords::Keyword;
 use sqlparser::parser::{Parser, ParserError};
-use sqlparser::tokenizer::Token::{SemiColon, EOF};
-use sqlparser::tokenizer::Tokenizer;
+use sqlparser::tokenizer::Token::{self, SemiColon, EOF};
+use sqlparser::tokenizer::{TokenWithLocation, Tokenizer, Whitespace};
 use sqlx::any::AnyKind;
+use std::borrow::Cow;
+use std::fmt::Write as _;
 use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 #[derive(Default)]
 pub struct ParsedSqlFile {
     pub(super) statements: Vec<ParsedStatement>,
+    /// Set by a `-- @primary` comment at the top of the file.
+    /// Forces all statements to run on the primary database, even when read replicas are configured.
+    pub(super) use_primary_database: bool,
+    /// The name of the database the file runs against, set by a `-- @database name` comment.
+    /// None for the main database.
+    pub(super) database: Option<String>,
+    /// `-- @include 'file.sql'` directives that have not been replaced by the included statements yet
+    includes: Vec<IncludeDirective>,
+    /// All the files included by this one, directly or not
+    dependencies: Vec<PathBuf>,
+}
+
+/// A `-- @include 'file.sql'` comment between two statements
+#[derive(Debug)]
+struct IncludeDirective {
+    /// The position in the statement list where the included statements go
+    statement_index: usize,
+    /// The included file, relative to the web root
+    path: PathBuf,
+    line: u64,
 }
 
 impl ParsedSqlFile {
     #[must_use]
     pub fn new(db: &Database, sql: &str) -> ParsedSqlFile {
         let dialect = dialect_for_db(db.connection.any_kind());
-        let parsed_statements = match parse_sql(dialect.as_ref(), sql) {
+        let (statements, includes) = match parse_sql(dialect.as_ref(), sql) {
             Ok(parsed) => parsed,
             Err(err) => return Self::from_err(err),
         };
-        let statements = parsed_statements.collect();
-        ParsedSqlFile { statements }
+        let use_primary_database = file_directives(sql).any(|(name, _)| name == "primary");
+        ParsedSqlFile {
+            statements,
+            use_primary_database,
+            database: None,
+            includes,
+            dependencies: Vec::new(),
+        }
+    }
+
+    /// Replaces the `-- @include` directives with the statements of the included files.
+    /// `including` contains the files that are being parsed, to detect circular includes.
+    fn resolve_includes<'a>(
+        &'a mut self,
+        app_state: &'a AppState,
+        db: &'a Database,
+        including: &'a mut Vec<PathBuf>,
+    ) -> LocalBoxFuture<'a, ()> {
+        Box::pin(async move {
+            // In reverse order, so that the statement indices of the remaining includes stay valid
+            for include in std::mem::take(&mut self.includes).into_iter().rev() {
+                let IncludeDirective {
+                    statement_index,
+                    path,
+                    line,
+                } = include;
+                let included = match load_included_file(app_state, db, &path, including).await {
+                    Ok(file) => {
+                        self.dependencies.extend_from_slice(&file.dependencies);
+                        file.statements
+                    }
+                    Err(err) => vec![ParsedStatement::Error(err)],
+                };
+                let parent = including
+                    .last()
+                    .map_or(Cow::Borrowed(""), |p| p.to_string_lossy());
+                let context = format!(
+                    "Error in {}, included at line {line} of {parent}",
+                    path.display()
+                );
+                let included = included.into_iter().map(|statement| match statement {
+                    ParsedStatement::Error(err) => {
+                        ParsedStatement::Error(err.context(context.clone()))
+                    }
+                    other => other,
+                });
+                self.statements
+                    .splice(statement_index..statement_index, included);
+                self.dependencies.push(path);
+            }
+        })
+    }
+
+    /// The `validate` components must come before any other statement,
+    /// so that invalid submissions are rejected before anything is executed.
+    /// Otherwise, the whole file is replaced by an error, and none of it runs.
+    fn check_validation_order(&mut self) {
+        let misplaced = self
+            .statements
+            .iter()
+            .skip_while(|statement| matches!(statement, ParsedStatement::Validate { .. }))
+            .any(|statement| matches!(statement, ParsedStatement::Validate { .. }));
+        if misplaced && self.first_error().is_none() {
+            self.statements = vec![ParsedStatement::Error(anyhow::anyhow!(
+                "The validate component must be at the top of the file, before any other statement, \
+                so that invalid form submissions are rejected before anything else runs. \
+                Move it above the other statements of the file."
+            ))];
+        }
+    }
+
+    /// Whether the file reads the messages of the `flash` component with `sqlpage.flash_messages()`
+    #[must_use]
+    pub fn reads_flash_messages(&self) -> bool {
+        self.calls(SqlPageFunctionName::flash_messages)
+    }
+
+    /// Whether a statement of the file calls the given sqlpage function.
+    /// Files executed with `sqlpage.run_sql` are not taken into account.
+    fn calls(&self, function: SqlPageFunctionName) -> bool {
+        let static_values_call = |values: &[(String, SimpleSelectValue)]| {
+            values.iter().any(|(_, value)| match value {
+                SimpleSelectValue::Dynamic(param) => param.calls(function),
+                SimpleSelectValue::Static(_) => false,
+            })
+        };
+        self.statements.iter().any(|statement| match statement {
+            ParsedStatement::StmtWithParams(stmt)
+            | ParsedStatement::SetVariable { value: stmt, .. }
+            | ParsedStatement::TableQuery(TableQuery { query: stmt, .. })
+            | ParsedStatement::Stop(Some(stmt)) => stmt.calls(function),
+            ParsedStatement::StaticSimpleSelect(values) => static_values_call(values),
+            ParsedStatement::Validate { properties, fields } => {
+                static_values_call(properties) || fields.iter().any(|f| static_values_call(f))
+            }
+            ParsedStatement::CsvImport(_)
+            | ParsedStatement::Stop(None)
+            | ParsedStatement::Error(_) => false,
+        })
+    }
+
+    /// The first error found while parsing the file, or the files it includes
+    #[must_use]
+    pub fn first_error(&self) -> Option<&anyhow::Error> {
+        self.statements
+            .iter()
+            .find_map(|statement| match statement {
+                ParsedStatement::Error(err) => Some(err),
+                _ => None,
+            })
     }
 
     fn from_err(e: impl Into<anyhow::Error>) -> Self {
@@ -41,17 +179,114 @@ impl ParsedSqlFile {
             statements: vec![ParsedStatement::Error(
                 e.into().context("SQLPage could not parse the SQL file"),
             )],
+            ..Self::default()
         }
     }
 }
 
 #[async_trait(? Send)]
 impl AsyncFromStrWithState for ParsedSqlFile {
-    async fn from_str_with_state(app_state: &AppState, source: &str) -> anyhow::Result<Self> {
-        Ok(ParsedSqlFile::new(&app_state.db, source))
+    async fn from_str_with_state(
+        app_state: &AppState,
+        source: &str,
+        source_path: &Path,
+    ) -> anyhow::Result<Self> {
+        let database = file_directives(source)
+            .find(|(name, _)| *name == "database")
+            .map(|(_, name)| name)
+            .or_else(|| directory_database(&app_state.config, source_path));
+        Ok(ParsedSqlFile::parse_for_database(app_state, source, source_path, database).await)
+    }
+
+    fn dependencies(&self) -> &[PathBuf] {
+        &self.dependencies
     }
 }
 
+impl ParsedSqlFile {
+    /// Parses a SQL file that runs against the database with the given name, or against the main database.
+    /// Its own `-- @database` comment is ignored.
+    pub(crate) async fn parse_for_database(
+        app_state: &AppState,
+        source: &str,
+        source_path: &Path,
+        database: Option<&str>,
+    ) -> Self {
+        let db = match database {
+            None => &app_state.db,
+            Some(name) => match app_state.databases.get(name) {
+                Some(db) => db,
+                None => return unknown_database_error(app_state, name),
+            },
+        };
+        let mut parsed = ParsedSqlFile::new(db, source);
+        parsed.database = database.map(str::to_owned);
+        let mut including = vec![normalize_include_path(source_path)];
+        parsed.resolve_includes(app_state, db, &mut including).await;
+        parsed.check_validation_order();
+        parsed
+    }
+}
+
+/// The database of the files in a directory listed in the `database_directories` configuration option.
+/// When directories are nested, the innermost one wins.
+fn directory_database<'a>(config: &'a AppConfig, source_path: &Path) -> Option<&'a str> {
+    let source_path = normalize_include_path(source_path);
+    config
+        .database_directories
+        .iter()
+        .map(|(directory, name)| {
+            let directory = Path::new(directory.trim_start_matches('/'));
+            (normalize_include_path(directory), name)
+        })
+        .filter(|(directory, _)| source_path.starts_with(directory))
+        .max_by_key(|(directory, _)| directory.components().count())
+        .map(|(_, name)| name.as_str())
+}
+
+fn unknown_database_error(app_state: &AppState, name: &str) -> ParsedSqlFile {
+    let mut known: Vec<&str> = app_state.databases.keys().map(String::as_str).collect();
+    known.sort_unstable();
+    ParsedSqlFile::from_err(anyhow::anyhow!(
+        "The file should run against the database named {name:?}, but there is no such database. \
+         Configured databases: {known:?}. You can add databases with the \"databases\" configuration option."
+    ))
+}
+
+/// Parses an included file, and the files it includes itself.
+/// Included files are parsed with the dialect of the including file, and their own directives are ignored.
+async fn load_included_file(
+    app_state: &AppState,
+    db: &Database,
+    path: &Path,
+    including: &mut Vec<PathBuf>,
+) -> anyhow::Result<ParsedSqlFile> {
+    if including.iter().any(|p| p == path) {
+        let cycle: Vec<String> = including
+            .iter()
+            .map(|p| p.display().to_string())
+            .chain([path.display().to_string()])
+            .collect();
+        anyhow::bail!("Circular include: {}", cycle.join(" -> "));
+    }
+    let source = app_state
+        .file_system
+        .read_to_string(app_state, path, true)
+        .await?;
+    let mut file = ParsedSqlFile::new(db, &source);
+    including.push(path.to_owned());
+    file.resolve_includes(app_state, db, including).await;
+    including.pop();
+    Ok(file)
+}
+
+/// `./common/auth.sql` and `common/auth.sql` are the same file
+fn normalize_include_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != std::path::Component::CurDir)
+        .collect()
+}
+
 /// A single SQL statement that has been parsed from a SQL file.
 #[derive(Debug, PartialEq)]
 pub(super) struct StmtWithParams {
@@ -67,6 +302,59 @@ pub(super) struct StmtWithParams {
     /// Columns that are JSON columns, and which should be converted to JSON objects after the query is executed.
     /// Only relevant for databases that do not have a native JSON type, and which return JSON values as text.
     pub json_columns: Vec<String>,
+    /// Whether the statement only reads data, and can be sent to a read replica.
+    pub read_only: bool,
+}
+
+impl StmtWithParams {
+    fn calls(&self, function: SqlPageFunctionName) -> bool {
+        self.params.iter().any(|param| param.calls(function))
+            || self
+                .delayed_functions
+                .iter()
+                .any(|f| f.function == function)
+    }
+
+    /// Returns the query in which each list parameter (`x IN ($ids)`) is expanded to one placeholder per value.
+    /// `list_lengths` contains the number of values of each list parameter, in order,
+    /// or `None` when the variable has a single value.
+    /// An empty list becomes `NULL`, which matches no rows.
+    /// In `x = ANY($ids)`, a variable with a single value is bound as is, because it can be an array literal such as `{1,2,3}`.
+    pub(super) fn query_with_lists(
+        &self,
+        db_kind: AnyKind,
+        list_lengths: &[Option<usize>],
+    ) -> Cow<'_, str> {
+        if list_lengths.is_empty() {
+            return Cow::Borrowed(&self.query);
+        }
+        let mut query = self.query.clone();
+        // With numbered placeholders, list values are bound after all the other parameters
+        let mut next_placeholder = self.params.len() - list_lengths.len() + 1;
+        for (list_idx, &len) in list_lengths.iter().enumerate() {
+            let is_list = len.is_some();
+            let len = len.unwrap_or(1);
+            let placeholders = if len == 0 {
+                "NULL".to_string()
+            } else {
+                (next_placeholder..next_placeholder + len)
+                    .map(|n| make_placeholder_expr(db_kind, n).to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            next_placeholder += len;
+            let marker = list_marker(list_idx + 1);
+            if query.contains(&marker) {
+                query = query.replacen(&marker, &placeholders, 1);
+            } else if is_list {
+                let array = format!("ARRAY[{placeholders}]");
+                query = query.replacen(&array_marker(list_idx + 1), &array, 1);
+            } else {
+                query = query.replacen(&array_marker(list_idx + 1), &placeholders, 1);
+            }
+        }
+        Cow::Owned(query)
+    }
 }
 
 #[derive(Debug)]
@@ -74,23 +362,85 @@ pub(super) enum ParsedStatement {
     StmtWithParams(StmtWithParams),
     StaticSimpleSelect(Vec<(String, SimpleSelectValue)>),
     SetVariable {
-        variable: StmtParam,
+        variables: SetVariables,
         value: StmtWithParams,
     },
     CsvImport(CsvImport),
+    /// `SELECT 'stop' AS component WHERE ...` ends the execution of the file if the query returns a row.
+    /// None when the statement always returns a row.
+    Stop(Option<StmtWithParams>),
+    /// A query preceded by `-- @paginate` or `-- @sort_and_search`
+    TableQuery(TableQuery),
+    /// `SELECT 'validate' AS component`, followed by the static rows that define the form fields
+    Validate {
+        properties: Vec<(String, SimpleSelectValue)>,
+        fields: Vec<Vec<(String, SimpleSelectValue)>>,
+    },
     Error(anyhow::Error),
 }
 
+/// The variables assigned by a `SET` statement
+#[derive(Debug, PartialEq)]
+pub(super) enum SetVariables {
+    /// `SET x = ...` takes the first column of the first row
+    Single(StmtParam),
+    /// `SET (x, y) = (SELECT a, b ...)` takes each column of the first row
+    Columns(Vec<StmtParam>),
+    /// `SET x[] = (SELECT ...)` takes all the rows, as a JSON array
+    Rows(StmtParam),
+}
+
+impl std::fmt::Display for SetVariables {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Single(variable) => write!(f, "{variable}"),
+            Self::Columns(variables) => {
+                write!(f, "(")?;
+                for (i, variable) in variables.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{variable}")?;
+                }
+                write!(f, ")")
+            }
+            Self::Rows(variable) => write!(f, "{variable}[]"),
+        }
+    }
+}
+
+/// A query whose results are paginated, sorted or filtered on the server, according to URL parameters
+#[derive(Debug)]
+pub(super) struct TableQuery {
+    /// Returns the rows to display
+    pub query: StmtWithParams,
+    /// Set by `-- @paginate 20 page`
+    pub pagination: Option<Pagination>,
+    /// Set by `-- @sort_and_search`: the rows are sorted by the column in the `sort` URL parameter,
+    /// and only the rows that contain the text in the `search` URL parameter are returned
+    pub sort_and_search: bool,
+}
+
+/// The query only returns the rows of the current page. The offset of the page is its last parameter.
+#[derive(Debug)]
+pub(super) struct Pagination {
+    /// Counts the rows on all the pages
+    pub count: StmtWithParams,
+    pub page_size: u64,
+    /// The URL parameter that contains the number of the current page, starting at 1
+    pub page_variable: String,
+}
+
 #[derive(Debug, PartialEq)]
 pub(super) enum SimpleSelectValue {
     Static(serde_json::Value),
     Dynamic(StmtParam),
 }
 
-fn parse_sql<'a>(
-    dialect: &'a dyn Dialect,
-    sql: &'a str,
-) -> anyhow::Result<impl Iterator<Item = ParsedStatement> + 'a> {
+fn parse_sql(
+    dialect: &dyn Dialect,
+    sql: &str,
+) -> anyhow::Result<(Vec<ParsedStatement>, Vec<IncludeDirective>)> {
     log::trace!("Parsing SQL: {sql}");
     let tokens = Tokenizer::new(dialect, sql)
         .tokenize_with_location()
@@ -98,31 +448,203 @@ fn parse_sql<'a>(
             let location = err.location;
             anyhow::Error::new(err).context(format!("The SQLPage parser couldn't understand the SQL file. Tokenization failed. Please check for syntax errors:\n{}", quote_source_with_highlight(sql, location.line, location.column)))
         })?;
-    let mut parser = Parser::new(dialect).with_tokens_with_locations(tokens);
     let db_kind = kind_of_dialect(dialect);
-    let mut has_error = false;
-    Ok(std::iter::from_fn(move || {
-        if has_error {
-            // Return the first error and ignore the rest
-            return None;
+    let mut statements = Vec::new();
+    let mut includes = Vec::new();
+    let mut table = TableDirectives::default();
+    for (tokens, directive) in split_at_statement_directives(tokens, sql)? {
+        let mut parser = Parser::new(dialect).with_tokens_with_locations(tokens);
+        // The directives before a group of statements apply to its first statement
+        while parser.peek_token() != EOF {
+            let table = std::mem::take(&mut table);
+            let validate_fields =
+                matches!(statements.last(), Some(ParsedStatement::Validate { .. }));
+            let Some(statement) =
+                parse_single_statement(&mut parser, db_kind, sql, table, validate_fields)
+            else {
+                break;
+            };
+            let is_error = matches!(statement, ParsedStatement::Error(_));
+            push_statement(&mut statements, statement);
+            if is_error {
+                // Return the first error and ignore the rest
+                return Ok((statements, includes));
+            }
         }
-        let statement = parse_single_statement(&mut parser, db_kind, sql);
-        if let Some(ParsedStatement::Error(_)) = &statement {
-            has_error = true;
+        match directive {
+            Some(StatementDirective::Include { path, line }) => includes.push(IncludeDirective {
+                statement_index: statements.len(),
+                path: normalize_include_path(Path::new(path.as_str())),
+                line,
+            }),
+            Some(StatementDirective::Paginate(directive)) => table.pagination = Some(directive),
+            Some(StatementDirective::SortAndSearch) => table.sort_and_search = true,
+            None => {}
         }
-        statement
-    }))
+    }
+    Ok((statements, includes))
 }
 
+/// Groups the rows of a `validate` component into a single statement,
+/// so that the form can be validated before the rest of the file is executed
+fn push_statement(statements: &mut Vec<ParsedStatement>, statement: ParsedStatement) {
+    let statement = match (statements.last_mut(), statement) {
+        (_, ParsedStatement::StaticSimpleSelect(properties))
+            if static_component(&properties) == Some("validate") =>
+        {
+            ParsedStatement::Validate {
+                properties,
+                fields: Vec::new(),
+            }
+        }
+        (
+            Some(ParsedStatement::Validate { fields, .. }),
+            ParsedStatement::StaticSimpleSelect(field),
+        ) if !field.iter().any(|(name, _)| name == "component") => {
+            fields.push(field);
+            return;
+        }
+        (_, statement) => statement,
+    };
+    statements.push(statement);
+}
+
+fn static_component(properties: &[(String, SimpleSelectValue)]) -> Option<&str> {
+    properties.iter().find_map(|(name, value)| match value {
+        SimpleSelectValue::Static(serde_json::Value::String(s)) if name == "component" => {
+            Some(s.as_str())
+        }
+        _ => None,
+    })
+}
+
+/// A comment placed between two statements, that changes how the file is parsed
+#[derive(Debug, PartialEq)]
+enum StatementDirective {
+    /// `-- @include 'file.sql'` inserts the statements of another file
+    Include { path: String, line: u64 },
+    /// `-- @paginate 20` paginates the results of the next statement
+    Paginate(PaginateDirective),
+    /// `-- @sort_and_search` sorts and filters the results of the next statement
+    SortAndSearch,
+}
+
+/// The directives that apply to the next statement
+#[derive(Debug, Default, PartialEq)]
+struct TableDirectives {
+    pagination: Option<PaginateDirective>,
+    sort_and_search: bool,
+}
+
+/// The page size and the URL parameter that contains the page number
+#[derive(Debug, PartialEq)]
+struct PaginateDirective {
+    page_size: u64,
+    variable: String,
+}
+
+type TokensBeforeDirective = (Vec<TokenWithLocation>, Option<StatementDirective>);
+
+/// Splits the tokens of a file at the `-- @include 'file.sql'`, `-- @paginate 20` and `-- @sort_and_search` comments.
+/// Each group of tokens is followed by the directive that ends it, if any.
+fn split_at_statement_directives(
+    tokens: Vec<TokenWithLocation>,
+    sql: &str,
+) -> anyhow::Result<Vec<TokensBeforeDirective>> {
+    let mut groups = Vec::new();
+    let mut current = Vec::new();
+    let mut between_statements = true;
+    for token in tokens {
+        if let Token::Whitespace(Whitespace::SingleLineComment { comment, prefix }) = &token.token {
+            let location = token.location;
+            if let Some(directive) = statement_directive(prefix, comment, location.line) {
+                let highlighted =
+                    || quote_source_with_highlight(sql, location.line, location.column);
+                let directive = directive.with_context(highlighted)?;
+                if !between_statements {
+                    anyhow::bail!(
+                        "The @{} directive must be placed between two statements, after a semicolon:\n{}",
+                        directive.name(),
+                        highlighted()
+                    );
+                }
+                groups.push((std::mem::take(&mut current), Some(directive)));
+                continue;
+            }
+        }
+        match &token.token {
+            Token::Whitespace(_) => {}
+            SemiColon => between_statements = true,
+            _ => between_statements = false,
+        }
+        current.push(token);
+    }
+    groups.push((current, None));
+    Ok(groups)
+}
+
+impl StatementDirective {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Include { .. } => "include",
+            Self::Paginate(_) => "paginate",
+            Self::SortAndSearch => "sort_and_search",
+        }
+    }
+}
+
+/// Parses a `-- @include 'file.sql'`, `-- @paginate 20 page` or `-- @sort_and_search` comment
+fn statement_directive(
+    prefix: &str,
+    comment: &str,
+    line: u64,
+) -> Option<anyhow::Result<StatementDirective>> {
+    if prefix != "--" {
+        return None;
+    }
+    let comment = comment.trim();
+    if let Some(path) = comment.strip_prefix("@include") {
+        let path = path.trim();
+        let unquoted = ['\'', '"']
+            .into_iter()
+            .find_map(|quote| path.strip_prefix(quote)?.strip_suffix(quote));
+        let path = unquoted.unwrap_or(path).to_owned();
+        return Some(Ok(StatementDirective::Include { path, line }));
+    }
+    if comment == "@sort_and_search" {
+        return Some(Ok(StatementDirective::SortAndSearch));
+    }
+    let arguments = comment.strip_prefix("@paginate")?;
+    let mut arguments = arguments.split_whitespace();
+    let page_size = arguments.next().unwrap_or_default();
+    let variable = arguments.next().unwrap_or("page");
+    Some(match page_size.parse() {
+        Ok(page_size) if page_size > 0 => Ok(StatementDirective::Paginate(PaginateDirective {
+            page_size,
+            variable: variable.trim_start_matches('$').to_owned(),
+        })),
+        _ => Err(anyhow::anyhow!(
+            "Invalid @paginate directive. It must contain the number of rows per page, \
+            and optionally the name of the URL parameter that contains the page number, \
+            as in: -- @paginate 20 page"
+        )),
+    })
+}
+
+/// `validate_fields` is set when the statement follows the rows of a `validate` component,
+/// and is one of them if it is a SELECT without a `component` column
 fn parse_single_statement(
     parser: &mut Parser<'_>,
     db_kind: AnyKind,
     source_sql: &str,
+    table: TableDirectives,
+    validate_fields: bool,
 ) -> Option<ParsedStatement> {
     if parser.peek_token() == EOF {
         return None;
     }
-    let mut stmt = match parser.parse_statement() {
+    let parsed = parse_sqlpage_set_statement(parser).unwrap_or_else(|| parser.parse_statement());
+    let mut stmt = match parsed {
         Ok(stmt) => stmt,
         Err(err) => return Some(syntax_error(err, parser, source_sql)),
     };
@@ -131,39 +653,515 @@ fn parse_single_statement(
     while parser.consume_token(&SemiColon) {
         semicolon = true;
     }
+    let is_table_query = table != TableDirectives::default();
+    if is_table_query && !matches!(stmt, Statement::Query(_)) {
+        return Some(ParsedStatement::Error(anyhow::anyhow!(
+            "The @paginate and @sort_and_search directives must be followed by a SELECT statement, not: {stmt}"
+        )));
+    }
     let mut params = ParameterExtractor::extract_parameters(&mut stmt, db_kind);
-    if let Some((variable, query)) = extract_set_variable(&mut stmt) {
+    let read_only = is_read_only(&stmt);
+    if let Some((variables, query)) = extract_set_variable(&mut stmt) {
         return Some(ParsedStatement::SetVariable {
-            variable,
+            variables,
             value: StmtWithParams {
                 query,
                 params,
                 delayed_functions: Vec::new(),
                 json_columns: Vec::new(),
+                read_only,
             },
         });
     }
     if let Some(csv_import) = extract_csv_copy_statement(&mut stmt) {
         return Some(ParsedStatement::CsvImport(csv_import));
     }
-    if let Some(static_statement) = extract_static_simple_select(&stmt, &params) {
+    let is_stop = is_stop_statement(&stmt);
+    if let Some(static_statement) =
+        extract_static_simple_select(&stmt, &params).filter(|_| !is_table_query)
+    {
+        if is_stop {
+            return Some(ParsedStatement::Stop(None));
+        }
         log::debug!("Optimised a static simple select to avoid a trivial database query: {stmt} optimized to {static_statement:?}");
         return Some(ParsedStatement::StaticSimpleSelect(static_statement));
     }
+    if validate_fields && is_select_without_component(&stmt) {
+        return Some(ParsedStatement::Error(anyhow::anyhow!(
+            "The fields of the validate component must only contain constant values and variables, not: {stmt}\n\
+            To display the results of this query after the validate component, select a new component first."
+        )));
+    }
     let delayed_functions = extract_toplevel_functions(&mut stmt);
-    remove_invalid_function_calls(&mut stmt, &mut params);
+    remove_invalid_function_calls(&mut stmt, &mut params, db_kind);
     let json_columns = extract_json_columns(&stmt, db_kind);
+    let read_only = read_only && matches!(stmt, Statement::Query(_));
     let query = format!(
         "{stmt}{semicolon}",
         semicolon = if semicolon { ";" } else { "" }
     );
     log::debug!("Final transformed statement: {stmt}");
-    Some(ParsedStatement::StmtWithParams(StmtWithParams {
+    let statement = StmtWithParams {
         query,
         params,
         delayed_functions,
         json_columns,
-    }))
+        read_only,
+    };
+    if is_table_query {
+        return Some(
+            table_query(stmt, statement, table, db_kind)
+                .map_or_else(ParsedStatement::Error, ParsedStatement::TableQuery),
+        );
+    }
+    if is_stop {
+        return Some(ParsedStatement::Stop(Some(statement)));
+    }
+    Some(ParsedStatement::StmtWithParams(statement))
+}
+
+fn table_query(
+    stmt: Statement,
+    query: StmtWithParams,
+    TableDirectives {
+        pagination,
+        sort_and_search,
+    }: TableDirectives,
+    db_kind: AnyKind,
+) -> anyhow::Result<TableQuery> {
+    let Statement::Query(stmt) = stmt else {
+        unreachable!("the directives only apply to queries")
+    };
+    let (stmt, mut query) = if sort_and_search {
+        sort_and_search_query(*stmt, query, db_kind)?
+    } else {
+        (*stmt, query)
+    };
+    let pagination = pagination
+        .map(|directive| paginate_query(stmt, &mut query, directive, db_kind))
+        .transpose()?;
+    Ok(TableQuery {
+        query,
+        pagination,
+        sort_and_search,
+    })
+}
+
+/// Wraps a query in `SELECT * FROM (query) WHERE ... ORDER BY ...`, to filter its rows
+/// with the `search` URL parameter, and sort them by the column in the `sort` URL parameter.
+/// The column names are never taken from the request: each column of the query has its own
+/// `ORDER BY` clause, which is only enabled when the column is selected.
+fn sort_and_search_query(
+    mut query: Query,
+    mut statement: StmtWithParams,
+    db_kind: AnyKind,
+) -> anyhow::Result<(Query, StmtWithParams)> {
+    let columns = output_columns(&query)?;
+    let moved_order_by = movable_order_by(&mut query, &columns);
+    let with = query.with.take();
+    let mut placeholder_count = statement
+        .params
+        .iter()
+        .filter(|p| !matches!(p, StmtParam::List(_)))
+        .count();
+    let mut next_placeholder = |param: StmtParam| {
+        placeholder_count += 1;
+        statement.params.push(param);
+        make_placeholder_expr(db_kind, placeholder_count)
+    };
+    let text_type = text_data_type(db_kind);
+    let mut filter = format!("{} IS NULL", next_placeholder(StmtParam::SearchPattern));
+    for column in &columns {
+        let pattern = next_placeholder(StmtParam::SearchPattern);
+        let _ = write!(
+            filter,
+            " OR LOWER(CAST({column} AS {text_type})) LIKE {pattern} ESCAPE '{SEARCH_ESCAPE}'"
+        );
+    }
+    let mut order_by = Vec::with_capacity(columns.len() * 2 + 1);
+    for column in &columns {
+        for (descending, direction) in [(false, "ASC"), (true, "DESC")] {
+            let sorted = next_placeholder(StmtParam::SortedBy {
+                column: column.value.clone(),
+                descending,
+            });
+            order_by.push(format!(
+                "CASE WHEN {sorted} IS NOT NULL THEN {column} END {direction}"
+            ));
+        }
+    }
+    if let Some(moved) = moved_order_by {
+        order_by.extend(moved.exprs.iter().map(ToString::to_string));
+    }
+    let with = with.map(|w| format!("{w} ")).unwrap_or_default();
+    statement.query = format!(
+        "{with}SELECT * FROM ({query}) AS sqlpage_table WHERE {filter} ORDER BY {}",
+        order_by.join(", ")
+    );
+    log::debug!("Sorted and filtered query: {}", statement.query);
+    let wrapped = Parser::new(dialect_for_db(db_kind).as_ref())
+        .try_with_sql(&statement.query)?
+        .parse_query()
+        .with_context(|| format!("Unable to parse the sorted query: {}", statement.query))?;
+    Ok((wrapped, statement))
+}
+
+/// The character used to escape `%` and `_` in the LIKE patterns of `-- @sort_and_search`
+pub(super) const SEARCH_ESCAPE: char = '!';
+
+/// The names of the columns returned by a query, which can be used to sort and filter its results
+fn output_columns(query: &Query) -> anyhow::Result<Vec<Ident>> {
+    let mut body = query.body.as_ref();
+    while let SetExpr::SetOperation { left, .. } = body {
+        body = left.as_ref();
+    }
+    let SetExpr::Select(select) = body else {
+        anyhow::bail!("The @sort_and_search directive only works with SELECT queries: {query}");
+    };
+    let mut columns = Vec::new();
+    for item in &select.projection {
+        let column = match item {
+            SelectItem::ExprWithAlias { alias, .. } => alias,
+            SelectItem::UnnamedExpr(Expr::Identifier(ident)) => ident,
+            SelectItem::UnnamedExpr(Expr::CompoundIdentifier(parts)) => {
+                let Some(ident) = parts.last() else { continue };
+                ident
+            }
+            SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..) => anyhow::bail!(
+                "The @sort_and_search directive needs to know the names of the columns of the query. \
+                Replace the * with the list of columns in: {query}"
+            ),
+            SelectItem::UnnamedExpr(_) => continue,
+        };
+        let is_hidden =
+            column.value.eq_ignore_ascii_case("component") || column.value.starts_with("_sqlpage_");
+        if !is_hidden {
+            columns.push(column.clone());
+        }
+    }
+    if columns.is_empty() {
+        anyhow::bail!("The @sort_and_search directive did not find any named column in: {query}");
+    }
+    Ok(columns)
+}
+
+/// Takes the ORDER BY clause out of a query, when it only references the columns of the query,
+/// so that it can be used as the default order of the sorted query
+fn movable_order_by(query: &mut Query, columns: &[Ident]) -> Option<OrderBy> {
+    let has_limit = query.limit.is_some() || query.offset.is_some() || query.fetch.is_some();
+    let order_by = query.order_by.as_ref()?;
+    let column_named = |ident: &Ident| {
+        columns
+            .iter()
+            .find(|c| c.value.eq_ignore_ascii_case(&ident.value))
+            .cloned()
+    };
+    let mut moved = order_by.clone();
+    for order in &mut moved.exprs {
+        let column = match &order.expr {
+            Expr::Identifier(ident) => column_named(ident),
+            Expr::CompoundIdentifier(parts) => parts.last().and_then(column_named),
+            _ => None,
+        };
+        order.expr = Expr::Identifier(column.filter(|_| !has_limit)?);
+    }
+    query.order_by = None;
+    Some(moved)
+}
+
+/// Rewrites a query to return a single page of results, with the offset of the page bound as its last parameter,
+/// and creates a query that counts the rows on all the pages.
+fn paginate_query(
+    mut query: Query,
+    page: &mut StmtWithParams,
+    PaginateDirective {
+        page_size,
+        variable,
+    }: PaginateDirective,
+    db_kind: AnyKind,
+) -> anyhow::Result<Pagination> {
+    let has_top = matches!(query.body.as_ref(), SetExpr::Select(select) if select.top.is_some());
+    if query.limit.is_some() || query.offset.is_some() || query.fetch.is_some() || has_top {
+        anyhow::bail!(
+            "The @paginate directive adds its own LIMIT and OFFSET to the query. \
+            Remove the limit from the following query: {query}"
+        );
+    }
+    let mut all_rows = query.clone();
+    if db_kind == AnyKind::Mssql {
+        // SQL Server does not accept ORDER BY in subqueries
+        all_rows.order_by = None;
+    }
+    let count_query = match all_rows.with.take() {
+        Some(with) => format!("{with} SELECT COUNT(*) FROM ({all_rows}) AS sqlpage_count"),
+        None => format!("SELECT COUNT(*) FROM ({all_rows}) AS sqlpage_count"),
+    };
+    let count = StmtWithParams {
+        query: count_query,
+        params: page.params.clone(),
+        delayed_functions: Vec::new(),
+        json_columns: Vec::new(),
+        read_only: page.read_only,
+    };
+
+    let placeholder_count = page
+        .params
+        .iter()
+        .filter(|p| !matches!(p, StmtParam::List(_)))
+        .count();
+    let offset = Expr::Value(Value::Placeholder(make_placeholder(
+        db_kind,
+        placeholder_count + 1,
+    )));
+    let page_size_expr = Expr::Value(Value::Number(page_size.to_string(), false));
+    if db_kind == AnyKind::Mssql {
+        // SQL Server only knows OFFSET ... FETCH, which requires an ORDER BY clause
+        if query.order_by.is_none() {
+            let no_order = Parser::new(&MsSqlDialect {})
+                .try_with_sql("(SELECT NULL)")?
+                .parse_expr()?;
+            query.order_by = Some(OrderBy {
+                exprs: vec![OrderByExpr {
+                    expr: no_order,
+                    asc: None,
+                    nulls_first: None,
+                    with_fill: None,
+                }],
+                interpolate: None,
+            });
+        }
+        query.offset = Some(Offset {
+            value: offset,
+            rows: OffsetRows::Rows,
+        });
+        query.fetch = Some(Fetch {
+            with_ties: false,
+            percent: false,
+            quantity: Some(page_size_expr),
+        });
+    } else {
+        query.limit = Some(page_size_expr);
+        query.offset = Some(Offset {
+            value: offset,
+            rows: OffsetRows::None,
+        });
+    }
+    page.query = query.to_string();
+    page.params.push(StmtParam::Typed(
+        ParamType::BigInt,
+        Box::new(StmtParam::PageOffset {
+            variable: variable.clone(),
+            page_size,
+        }),
+    ));
+    log::debug!("Paginated query: {}", page.query);
+    Ok(Pagination {
+        count,
+        page_size,
+        page_variable: variable,
+    })
+}
+
+/// Whether the statement is a `SELECT 'stop' AS component`, with an optional condition
+fn is_stop_statement(stmt: &Statement) -> bool {
+    let Statement::Query(query) = stmt else {
+        return false;
+    };
+    let SetExpr::Select(select) = query.body.as_ref() else {
+        return false;
+    };
+    matches!(
+        select.projection.first(),
+        Some(SelectItem::ExprWithAlias {
+            expr: Expr::Value(Value::SingleQuotedString(component)),
+            alias,
+        }) if alias.value.eq_ignore_ascii_case("component") && component.eq_ignore_ascii_case("stop")
+    )
+}
+
+fn is_select_without_component(stmt: &Statement) -> bool {
+    let Statement::Query(query) = stmt else {
+        return false;
+    };
+    let SetExpr::Select(select) = query.body.as_ref() else {
+        return false;
+    };
+    !select.projection.iter().any(|item| {
+        matches!(item, SelectItem::ExprWithAlias { alias, .. } if alias.value.eq_ignore_ascii_case("component"))
+    })
+}
+
+/// Parses the `-- @name value` comments at the top of a SQL file, before the first statement.
+fn file_directives(sql: &str) -> impl Iterator<Item = (&str, &str)> {
+    sql.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map_while(|line| line.strip_prefix("--"))
+        .filter_map(|comment| comment.trim_start().strip_prefix('@'))
+        .map(|directive| {
+            let (name, value) = directive
+                .split_once(char::is_whitespace)
+                .unwrap_or((directive, ""));
+            (name, value.trim())
+        })
+}
+
+/// Built-in functions that only compute a value, and never modify the database or the session state.
+/// Calls to any other function, including user-defined ones, may write data.
+const READ_ONLY_FUNCTIONS: &[&str] = &[
+    "abs",
+    "array_agg",
+    "array_length",
+    "avg",
+    "bool_and",
+    "bool_or",
+    "ceil",
+    "ceiling",
+    "char_length",
+    "coalesce",
+    "concat",
+    "concat_ws",
+    "count",
+    "current_date",
+    "current_time",
+    "current_timestamp",
+    "date",
+    "date_trunc",
+    "datetime",
+    "dense_rank",
+    "first_value",
+    "floor",
+    "format",
+    "generate_series",
+    "getdate",
+    "greatest",
+    "group_concat",
+    "ifnull",
+    "iif",
+    "instr",
+    "isnull",
+    "json_agg",
+    "json_array",
+    "json_array_length",
+    "json_arrayagg",
+    "json_build_array",
+    "json_build_object",
+    "json_each",
+    "json_extract",
+    "json_group_array",
+    "json_group_object",
+    "json_object",
+    "json_objectagg",
+    "json_query",
+    "json_value",
+    "jsonb_agg",
+    "jsonb_build_array",
+    "jsonb_build_object",
+    "lag",
+    "last_value",
+    "lead",
+    "least",
+    "left",
+    "len",
+    "length",
+    "localtime",
+    "localtimestamp",
+    "lower",
+    "lpad",
+    "ltrim",
+    "max",
+    "md5",
+    "min",
+    "mod",
+    "now",
+    "nullif",
+    "power",
+    "printf",
+    "rank",
+    "replace",
+    "right",
+    "round",
+    "row_number",
+    "row_to_json",
+    "rpad",
+    "rtrim",
+    "string_agg",
+    "strftime",
+    "substr",
+    "substring",
+    "sum",
+    "to_char",
+    "to_json",
+    "to_jsonb",
+    "trim",
+    "unnest",
+    "upper",
+];
+
+/// Returns true if the statement is made only of constructs that are known not to write data,
+/// lock rows, or depend on the state of the current session.
+/// Only such statements can safely be sent to a read replica.
+fn is_read_only(stmt: &Statement) -> bool {
+    struct WriteFinder;
+    impl Visitor for WriteFinder {
+        type Break = ();
+
+        fn pre_visit_statement(&mut self, stmt: &Statement) -> ControlFlow<()> {
+            match stmt {
+                // The value of a SET $variable = ... statement is evaluated in a SELECT
+                Statement::Query(_) | Statement::SetVariable { .. } => ControlFlow::Continue(()),
+                _ => ControlFlow::Break(()),
+            }
+        }
+
+        fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+            let selects_into =
+                matches!(query.body.as_ref(), SetExpr::Select(s) if s.into.is_some());
+            if query.locks.is_empty() && !selects_into {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        }
+
+        fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<()> {
+            match table_factor {
+                TableFactor::Table {
+                    name,
+                    args: Some(_),
+                    ..
+                }
+                | TableFactor::Function { name, .. }
+                    if !is_read_only_function(&name.0) =>
+                {
+                    ControlFlow::Break(())
+                }
+                _ => ControlFlow::Continue(()),
+            }
+        }
+
+        fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+            match expr {
+                Expr::Function(Function {
+                    name: ObjectName(parts),
+                    ..
+                }) if !is_read_only_function(parts) => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        }
+    }
+    stmt.visit(&mut WriteFinder).is_continue()
+}
+
+/// Whether calling the function cannot write data.
+/// `sqlpage.*` functions are evaluated by sqlpage itself, not by the database.
+fn is_read_only_function(name_parts: &[Ident]) -> bool {
+    match name_parts {
+        [Ident { value, .. }] => READ_ONLY_FUNCTIONS
+            .iter()
+            .any(|f| value.eq_ignore_ascii_case(f)),
+        _ => is_sqlpage_func(name_parts),
+    }
 }
 
 fn syntax_error(err: ParserError, parser: &Parser, sql: &str) -> ParsedStatement {
@@ -360,6 +1358,10 @@ fn extract_static_simple_select(
             Expr::Value(Value::Null) => Static(Null),
             e if is_simple_select_placeholder(e) => {
                 if let Some(p) = params_iter.next() {
+                    if p.returns_bytes() {
+                        log::trace!("Cancelling simple select optimization because {p} is binary");
+                        return None;
+                    }
                     Dynamic(p)
                 } else {
                     log::error!("Parameter not extracted for placehorder: {expr:?}");
@@ -394,24 +1396,129 @@ fn is_simple_select_placeholder(e: &Expr) -> bool {
     }
 }
 
-fn extract_set_variable(stmt: &mut Statement) -> Option<(StmtParam, String)> {
-    if let Statement::SetVariable {
-        variables: OneOrManyWithParens::One(ObjectName(name)),
+fn extract_set_variable(stmt: &mut Statement) -> Option<(SetVariables, String)> {
+    let Statement::SetVariable {
+        variables,
         value,
         local: false,
         hivevar: false,
     } = stmt
-    {
-        if let ([ident], [value]) = (name.as_mut_slice(), value.as_mut_slice()) {
-            let variable = if let Some(variable) = extract_ident_param(ident) {
-                variable
-            } else {
-                StmtParam::PostOrGet(std::mem::take(&mut ident.value))
+    else {
+        return None;
+    };
+    let [value] = value.as_mut_slice() else {
+        return None;
+    };
+    match variables {
+        OneOrManyWithParens::One(ObjectName(name)) => {
+            let [ident] = name.as_mut_slice() else {
+                return None;
             };
-            return Some((variable, format!("SELECT {value}")));
+            if let Some(name) = ident.value.strip_suffix("[]") {
+                ident.value.truncate(name.len());
+                let variable = set_variable_param(ident);
+                return Some((SetVariables::Rows(variable), set_value_query(value)));
+            }
+            let variable = set_variable_param(ident);
+            Some((SetVariables::Single(variable), format!("SELECT {value}")))
+        }
+        OneOrManyWithParens::Many(names) => {
+            let variables = names
+                .iter_mut()
+                .map(|ObjectName(name)| match name.as_mut_slice() {
+                    [ident] => Some(set_variable_param(ident)),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some((SetVariables::Columns(variables), set_value_query(value)))
         }
     }
-    None
+}
+
+fn set_variable_param(ident: &mut Ident) -> StmtParam {
+    extract_ident_param(ident)
+        .unwrap_or_else(|| StmtParam::PostOrGet(std::mem::take(&mut ident.value)))
+}
+
+/// `(SELECT a, b FROM t)` runs as `SELECT a, b FROM t`, and `(1, 2)` as `SELECT 1, 2`
+fn set_value_query(value: &Expr) -> String {
+    match value {
+        Expr::Subquery(query) => query.to_string(),
+        Expr::Tuple(values) => {
+            let values: Vec<String> = values.iter().map(ToString::to_string).collect();
+            format!("SELECT {}", values.join(", "))
+        }
+        other => format!("SELECT {other}"),
+    }
+}
+
+/// Parses the forms of `SET` that are specific to `SQLPage`, and that sqlparser does not support:
+/// `SET (a, b) = (SELECT ...)` and `SET rows[] = (SELECT ...)`.
+/// Returns None when the next statement is not one of them.
+fn parse_sqlpage_set_statement(parser: &mut Parser<'_>) -> Option<Result<Statement, ParserError>> {
+    let [set, first, second] = parser.peek_tokens();
+    if !matches!(&set, Token::Word(w) if w.keyword == Keyword::SET) {
+        return None;
+    }
+    let is_columns = first == Token::LParen;
+    let is_rows = matches!(first, Token::Word(_) | Token::Placeholder(_))
+        && (second == Token::LBracket || is_empty_bracket_ident(&second));
+    if !is_columns && !is_rows {
+        return None;
+    }
+    parser.next_token();
+    Some(parse_sqlpage_set_variables(parser, is_columns))
+}
+
+fn parse_sqlpage_set_variables(
+    parser: &mut Parser<'_>,
+    is_columns: bool,
+) -> Result<Statement, ParserError> {
+    let variables = if is_columns {
+        parser.expect_token(&Token::LParen)?;
+        let names = parser.parse_comma_separated(parse_set_variable_name)?;
+        parser.expect_token(&Token::RParen)?;
+        OneOrManyWithParens::Many(
+            names
+                .into_iter()
+                .map(|name| ObjectName(vec![name]))
+                .collect(),
+        )
+    } else {
+        let mut name = parse_set_variable_name(parser)?;
+        // `[]` is tokenized as brackets in some dialects, and as an empty [quoted] identifier in others
+        let token = parser.next_token();
+        match &token.token {
+            Token::LBracket => parser.expect_token(&Token::RBracket)?,
+            t if is_empty_bracket_ident(t) => {}
+            _ => return parser.expected("[]", token),
+        }
+        name.value.push_str("[]");
+        OneOrManyWithParens::One(ObjectName(vec![name]))
+    };
+    if !parser.consume_token(&Token::Eq) && !parser.parse_keyword(Keyword::TO) {
+        return parser.expected("=", parser.peek_token());
+    }
+    let value = parser.parse_expr()?;
+    Ok(Statement::SetVariable {
+        local: false,
+        hivevar: false,
+        variables,
+        value: vec![value],
+    })
+}
+
+fn parse_set_variable_name(parser: &mut Parser<'_>) -> Result<Ident, ParserError> {
+    let token = parser.next_token();
+    match token.token {
+        Token::Word(w) => Ok(w.to_ident()),
+        Token::Placeholder(name) => Ok(Ident::new(name)),
+        _ => parser.expected("a variable name", token),
+    }
+}
+
+fn is_empty_bracket_ident(token: &Token) -> bool {
+    matches!(token, Token::Word(w) if w.quote_style == Some('[') && w.value.is_empty())
 }
 
 struct ParameterExtractor {
@@ -436,25 +1543,103 @@ impl ParameterExtractor {
         this.parameters
     }
 
-    fn make_placeholder(&self) -> Expr {
-        let name = make_placeholder(self.db_kind, self.parameters.len() + 1);
-        // We cast our placeholders to TEXT even though we always bind TEXT data to them anyway
-        // because that helps the database engine to prepare the query.
-        // For instance in PostgreSQL, the query planner will not be able to use an index on a
-        // column if the column is compared to a placeholder of type VARCHAR, but it will be able
-        // to use the index if the column is compared to a placeholder of type TEXT.
-        let data_type = match self.db_kind {
-            // MySQL requires CAST(? AS CHAR) and does not understand CAST(? AS TEXT)
-            AnyKind::MySql => DataType::Char(None),
-            AnyKind::Mssql => DataType::Varchar(Some(CharacterLength::Max)),
-            _ => DataType::Text,
-        };
-        let value = Expr::Value(Value::Placeholder(name));
-        Expr::Cast {
-            expr: Box::new(value),
-            data_type,
-            format: None,
-            kind: CastKind::Cast,
+    fn make_placeholder(&self) -> Expr {
+        make_placeholder_expr(self.db_kind, self.placeholder_count() + 1)
+    }
+
+    /// Number of placeholders emitted so far. List parameters have no placeholder of their own:
+    /// their values are bound after all the other parameters.
+    fn placeholder_count(&self) -> usize {
+        self.parameters
+            .iter()
+            .filter(|p| !matches!(p, StmtParam::List(_)))
+            .count()
+    }
+
+    fn list_count(&self) -> usize {
+        self.parameters.len() - self.placeholder_count()
+    }
+
+    /// Replaces a variable such as `$ids` with a marker that will be expanded to a list of placeholders
+    /// when the statement is executed, one per value of the variable.
+    /// `marker` gives the name of the marker from the number of the list.
+    fn replace_with_list(&mut self, expr: &mut Expr, marker: fn(usize) -> String) {
+        let Some(param) = self.take_variable(expr) else {
+            return;
+        };
+        *expr = Expr::Identifier(Ident::new(marker(self.list_count() + 1)));
+        self.parameters.push(StmtParam::List(Box::new(param)));
+    }
+
+    /// Replaces a variable or a sqlpage function call with a placeholder without a cast to text.
+    /// Its value will be converted to the given type before being bound.
+    fn replace_with_typed(&mut self, expr: &mut Expr, param_type: ParamType) {
+        let Some(param) = self
+            .take_variable(expr)
+            .or_else(|| take_sqlpage_function(expr))
+        else {
+            return;
+        };
+        let param_type = if param.returns_bytes() {
+            ParamType::Blob
+        } else {
+            param_type
+        };
+        *expr = self.push_typed(param_type, param);
+    }
+
+    /// Adds a parameter that is bound to a placeholder without a cast to text
+    fn push_typed(&mut self, param_type: ParamType, param: StmtParam) -> Expr {
+        let name = make_placeholder(self.db_kind, self.placeholder_count() + 1);
+        self.parameters
+            .push(StmtParam::Typed(param_type, Box::new(param)));
+        Expr::Value(Value::Placeholder(name))
+    }
+
+    fn take_variable(&self, expr: &mut Expr) -> Option<StmtParam> {
+        match expr {
+            Expr::Identifier(ident) => extract_ident_param(ident),
+            Expr::Value(Value::Placeholder(param)) if !self.is_own_placeholder(param) => {
+                Some(map_param(std::mem::take(param)))
+            }
+            _ => None,
+        }
+    }
+
+    /// In `INSERT ... VALUES` and `UPDATE ... SET`, `PostgreSQL` knows the type of the target columns,
+    /// and can give it to the parameters.
+    fn infer_statement_parameter_types(&mut self, stmt: &mut Statement) {
+        match stmt {
+            Statement::Insert(Insert {
+                source: Some(source),
+                ..
+            }) => {
+                if let SetExpr::Values(values) = source.body.as_mut() {
+                    for expr in values.rows.iter_mut().flatten() {
+                        self.replace_with_typed(expr, ParamType::Inferred);
+                    }
+                }
+            }
+            Statement::Update { assignments, .. } => {
+                for assignment in assignments {
+                    self.replace_with_typed(&mut assignment.value, ParamType::Inferred);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn convert_double_colon_cast(&self, expr: &mut Expr) {
+        if let Expr::Cast {
+            kind: kind @ CastKind::DoubleColon,
+            ..
+        } = expr
+        {
+            if self.db_kind != AnyKind::Postgres {
+                log::warn!("Casting with '::' is not supported on your database. \
+                For backwards compatibility with older SQLPage versions, we will transform it to CAST(... AS ...).");
+                *kind = CastKind::Cast;
+            }
         }
     }
 
@@ -464,8 +1649,12 @@ impl ParameterExtractor {
         mut arguments: Vec<FunctionArg>,
     ) -> Expr {
         #[allow(clippy::single_match_else)]
-        let placeholder = self.make_placeholder();
         let param = func_call_to_param(func_name, &mut arguments);
+        if param.returns_bytes() {
+            // Binary data would be mangled by a cast to text
+            return self.push_typed(ParamType::Blob, param);
+        }
+        let placeholder = self.make_placeholder();
         self.parameters.push(param);
         placeholder
     }
@@ -477,7 +1666,7 @@ impl ParameterExtractor {
         {
             if let Some(param) = param.strip_prefix(prefix) {
                 if let Ok(index) = param.parse::<usize>() {
-                    return index <= self.parameters.len() + 1;
+                    return index <= self.placeholder_count() + 1;
                 }
             }
             return false;
@@ -486,7 +1675,9 @@ impl ParameterExtractor {
     }
 }
 
-struct BadFunctionRemover;
+struct BadFunctionRemover {
+    db_kind: AnyKind,
+}
 impl VisitorMut for BadFunctionRemover {
     type Break = StmtParam;
     fn pre_visit_expr(&mut self, value: &mut Expr) -> ControlFlow<Self::Break> {
@@ -502,6 +1693,13 @@ impl VisitorMut for BadFunctionRemover {
                 ..
             }) if is_sqlpage_func(func_name_parts) => {
                 let func_name = sqlpage_func_name(func_name_parts);
+                if self.db_kind == AnyKind::Sqlite {
+                    if let Some(native_name) = native_sqlite_function_name(func_name) {
+                        log::debug!("Evaluating sqlpage.{func_name} in SQLite as {native_name}");
+                        *func_name_parts = vec![Ident::new(native_name)];
+                        return ControlFlow::Continue(());
+                    }
+                }
                 log::error!("Invalid function call to sqlpage.{func_name}. SQLPage function arguments must be static if the function is not at the top level of a select statement.");
                 let mut arguments = std::mem::take(args);
                 let param = func_call_to_param(func_name, &mut arguments);
@@ -513,8 +1711,12 @@ impl VisitorMut for BadFunctionRemover {
     }
 }
 
-fn remove_invalid_function_calls(stmt: &mut Statement, params: &mut Vec<StmtParam>) {
-    let mut remover = BadFunctionRemover;
+fn remove_invalid_function_calls(
+    stmt: &mut Statement,
+    params: &mut Vec<StmtParam>,
+    db_kind: AnyKind,
+) {
+    let mut remover = BadFunctionRemover { db_kind };
     if let ControlFlow::Break(param) = stmt.visit(&mut remover) {
         params.push(param);
     }
@@ -647,6 +1849,78 @@ fn function_arg_expr(arg: &mut FunctionArg) -> Option<&mut Expr> {
     }
 }
 
+fn make_placeholder_expr(db_kind: AnyKind, arg_number: usize) -> Expr {
+    let name = make_placeholder(db_kind, arg_number);
+    // We cast our placeholders to TEXT even though we always bind TEXT data to them anyway
+    // because that helps the database engine to prepare the query.
+    // For instance in PostgreSQL, the query planner will not be able to use an index on a
+    // column if the column is compared to a placeholder of type VARCHAR, but it will be able
+    // to use the index if the column is compared to a placeholder of type TEXT.
+    let value = Expr::Value(Value::Placeholder(name));
+    Expr::Cast {
+        expr: Box::new(value),
+        data_type: text_data_type(db_kind),
+        format: None,
+        kind: CastKind::Cast,
+    }
+}
+
+fn text_data_type(db_kind: AnyKind) -> DataType {
+    match db_kind {
+        // MySQL requires CAST(? AS CHAR) and does not understand CAST(? AS TEXT)
+        AnyKind::MySql => DataType::Char(None),
+        AnyKind::Mssql => DataType::Varchar(Some(CharacterLength::Max)),
+        _ => DataType::Text,
+    }
+}
+
+/// Whether the database uses numbered placeholders (`$1`, `@p1`) rather than positional ones (`?`)
+#[must_use]
+pub(super) fn has_numbered_placeholders(db_kind: AnyKind) -> bool {
+    PLACEHOLDER_PREFIXES
+        .iter()
+        .any(|(kind, _)| *kind == db_kind)
+}
+
+/// Lets the database convert the text bound to a placeholder to the given type:
+/// `$1` becomes `CAST(CAST($1 AS TEXT) AS UUID)`.
+#[must_use]
+pub(super) fn cast_placeholder_from_text(sql: &str, placeholder: &str, type_name: &str) -> String {
+    let mut result = String::with_capacity(sql.len() + 32);
+    let mut in_string = false;
+    let mut previous = ' ';
+    let mut rest = sql;
+    while let Some(c) = rest.chars().next() {
+        let is_placeholder = !in_string
+            && rest.starts_with(placeholder)
+            && !rest[placeholder.len()..].starts_with(|c: char| c.is_ascii_digit())
+            && !previous.is_alphanumeric()
+            && previous != '_'
+            && previous != '$';
+        if is_placeholder {
+            let _ = write!(result, "CAST(CAST({placeholder} AS TEXT) AS {type_name})");
+            rest = &rest[placeholder.len()..];
+            previous = ')';
+            continue;
+        }
+        if c == '\'' {
+            in_string = !in_string;
+        }
+        result.push(c);
+        previous = c;
+        rest = &rest[c.len_utf8()..];
+    }
+    result
+}
+
+fn list_marker(list_number: usize) -> String {
+    format!("__sqlpage_list_{list_number}__")
+}
+
+fn array_marker(list_number: usize) -> String {
+    format!("__sqlpage_array_{list_number}__")
+}
+
 #[inline]
 #[must_use]
 pub fn make_placeholder(db_kind: AnyKind, arg_number: usize) -> String {
@@ -659,6 +1933,54 @@ pub fn make_placeholder(db_kind: AnyKind, arg_number: usize) -> String {
     DEFAULT_PLACEHOLDER.to_string()
 }
 
+fn take_sqlpage_function(expr: &mut Expr) -> Option<StmtParam> {
+    let Expr::Function(Function {
+        name: ObjectName(func_name_parts),
+        args:
+            FunctionArguments::List(FunctionArgumentList {
+                args,
+                duplicate_treatment: None,
+                ..
+            }),
+        filter: None,
+        null_treatment: None,
+        over: None,
+        ..
+    }) = expr
+    else {
+        return None;
+    };
+    if !is_sqlpage_func(func_name_parts) || !are_params_extractable(args) {
+        return None;
+    }
+    let mut arguments = std::mem::take(args);
+    Some(func_call_to_param(
+        sqlpage_func_name(func_name_parts),
+        &mut arguments,
+    ))
+}
+
+fn is_comparison(op: &BinaryOperator) -> bool {
+    matches!(
+        op,
+        BinaryOperator::Eq
+            | BinaryOperator::NotEq
+            | BinaryOperator::Lt
+            | BinaryOperator::LtEq
+            | BinaryOperator::Gt
+            | BinaryOperator::GtEq
+    )
+}
+
+/// A reference to a column, which has a type known to the database
+fn is_column(expr: &Expr) -> bool {
+    match expr {
+        Expr::Identifier(Ident { value, .. }) => !value.starts_with(['$', ':']),
+        Expr::CompoundIdentifier(_) => true,
+        _ => false,
+    }
+}
+
 fn extract_ident_param(Ident { value, .. }: &mut Ident) -> Option<StmtParam> {
     if value.starts_with('$') || value.starts_with(':') {
         let name = std::mem::take(value);
@@ -670,14 +1992,54 @@ fn extract_ident_param(Ident { value, .. }: &mut Ident) -> Option<StmtParam> {
 
 impl VisitorMut for ParameterExtractor {
     type Break = ();
+    fn pre_visit_statement(&mut self, stmt: &mut Statement) -> ControlFlow<Self::Break> {
+        if self.db_kind == AnyKind::Postgres {
+            self.infer_statement_parameter_types(stmt);
+        }
+        ControlFlow::Continue(())
+    }
+
     fn pre_visit_expr(&mut self, value: &mut Expr) -> ControlFlow<Self::Break> {
         match value {
+            // CAST($id AS INTEGER): bind $id as an integer
+            Expr::Cast {
+                expr, data_type, ..
+            } => {
+                if let Some(param_type) = ParamType::from_cast(data_type) {
+                    self.replace_with_typed(expr, param_type);
+                }
+                self.convert_double_colon_cast(value);
+            }
+            // WHERE id = $id in PostgreSQL: the type of $id is the type of the id column
+            Expr::BinaryOp { left, op, right }
+                if self.db_kind == AnyKind::Postgres && is_comparison(op) =>
+            {
+                if is_column(left) {
+                    self.replace_with_typed(right, ParamType::Inferred);
+                } else if is_column(right) {
+                    self.replace_with_typed(left, ParamType::Inferred);
+                }
+            }
             Expr::Identifier(ident) => {
                 if let Some(param) = extract_ident_param(ident) {
                     *value = self.make_placeholder();
                     self.parameters.push(param);
                 }
             }
+            // x IN ($ids): bind each value of $ids separately
+            Expr::InList { list, .. } if list.len() == 1 => {
+                self.replace_with_list(&mut list[0], list_marker);
+            }
+            // x = ANY($ids) in PostgreSQL: an array of the values of $ids
+            Expr::AnyOp { right, .. } | Expr::AllOp { right, .. }
+                if self.db_kind == AnyKind::Postgres =>
+            {
+                let array = match right.as_mut() {
+                    Expr::Cast { expr, .. } => expr.as_mut(),
+                    other => other,
+                };
+                self.replace_with_list(array, array_marker);
+            }
             Expr::Value(Value::Placeholder(param)) if !self.is_own_placeholder(param) =>
             // this check is to avoid recursively replacing placeholders in the form of '?', or '$1', '$2', which we emit ourselves
             {
@@ -729,14 +2091,6 @@ impl VisitorMut for ParameterExtractor {
                     within_group: Vec::new(),
                 });
             }
-            Expr::Cast {
-                kind: kind @ CastKind::DoubleColon,
-                ..
-            } if self.db_kind != AnyKind::Postgres => {
-                log::warn!("Casting with '::' is not supported on your database. \
-                For backwards compatibility with older SQLPage versions, we will transform it to CAST(... AS ...).");
-                *kind = CastKind::Cast;
-            }
             _ => (),
         }
         ControlFlow::<()>::Continue(())
@@ -936,9 +2290,7 @@ mod test {
         // The order of the function arguments should be preserved
         // Otherwise the statement parameters will be bound to the wrong arguments
         let sql = "select $a as a, sqlpage.exec('xxx', x = $b) as b, $c as c from t";
-        let all = parse_sql(&PostgreSqlDialect {}, sql)
-            .unwrap()
-            .collect::<Vec<_>>();
+        let (all, _) = parse_sql(&PostgreSqlDialect {}, sql).unwrap();
         assert_eq!(all.len(), 1);
         let ParsedStatement::StmtWithParams(StmtWithParams {
             query,
@@ -951,13 +2303,16 @@ mod test {
         };
         assert_eq!(
             query,
-            "SELECT CAST($1 AS TEXT) AS a, 'xxx' AS _sqlpage_f0_a0, x = CAST($2 AS TEXT) AS _sqlpage_f0_a1, CAST($3 AS TEXT) AS c FROM t"
+            "SELECT CAST($1 AS TEXT) AS a, 'xxx' AS _sqlpage_f0_a0, x = $2 AS _sqlpage_f0_a1, CAST($3 AS TEXT) AS c FROM t"
         );
         assert_eq!(
             params,
             &[
                 StmtParam::PostOrGet("a".to_string()),
-                StmtParam::PostOrGet("b".to_string()),
+                StmtParam::Typed(
+                    ParamType::Inferred,
+                    Box::new(StmtParam::PostOrGet("b".to_string()))
+                ),
                 StmtParam::PostOrGet("c".to_string()),
             ]
         );
@@ -991,20 +2346,44 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_native_sqlite_functions() {
+        let sql = "select upper(sqlpage.url_encode(name)) from t";
+        let mut ast = parse_stmt(sql, &SQLiteDialect {});
+        let mut params = ParameterExtractor::extract_parameters(&mut ast, AnyKind::Sqlite);
+        remove_invalid_function_calls(&mut ast, &mut params, AnyKind::Sqlite);
+        assert_eq!(params, []);
+        assert_eq!(
+            ast.to_string(),
+            "SELECT upper(sqlpage_url_encode(name)) FROM t"
+        );
+
+        let mut ast = parse_postgres_stmt(sql);
+        let mut params = ParameterExtractor::extract_parameters(&mut ast, AnyKind::Postgres);
+        remove_invalid_function_calls(&mut ast, &mut params, AnyKind::Postgres);
+        assert!(matches!(params.as_slice(), [StmtParam::Error(_)]));
+    }
+
     #[test]
     fn test_set_variable() {
         let sql = "set x = $y";
         for &(dialect, db_kind) in ALL_DIALECTS {
             let mut parser = Parser::new(dialect).try_with_sql(sql).unwrap();
-            let stmt = parse_single_statement(&mut parser, db_kind, sql);
+            let stmt = parse_single_statement(
+                &mut parser,
+                db_kind,
+                sql,
+                TableDirectives::default(),
+                false,
+            );
             if let Some(ParsedStatement::SetVariable {
-                variable,
+                variables,
                 value: StmtWithParams { query, params, .. },
             }) = stmt
             {
                 assert_eq!(
-                    variable,
-                    StmtParam::PostOrGet("x".to_string()),
+                    variables,
+                    SetVariables::Single(StmtParam::PostOrGet("x".to_string())),
                     "{dialect:?}"
                 );
                 assert!(query.starts_with("SELECT "));
@@ -1062,6 +2441,146 @@ mod test {
         assert_eq!(parameters, [StmtParam::PostOrGet("1".to_string()),]);
     }
 
+    #[test]
+    fn test_list_parameters() {
+        let mut ast = parse_postgres_stmt(
+            "select * from t where a = $a and b in ($ids) and c = any($cs::int[]) and d = $d",
+        );
+        let parameters = ParameterExtractor::extract_parameters(&mut ast, AnyKind::Postgres);
+        assert_eq!(
+            ast.to_string(),
+            "SELECT * FROM t WHERE a = $1 AND b IN (__sqlpage_list_1__) \
+             AND c = ANY(__sqlpage_array_2__::INT[]) AND d = $2"
+        );
+        assert_eq!(
+            parameters,
+            [
+                StmtParam::Typed(
+                    ParamType::Inferred,
+                    Box::new(StmtParam::PostOrGet("a".to_string()))
+                ),
+                StmtParam::List(Box::new(StmtParam::PostOrGet("ids".to_string()))),
+                StmtParam::List(Box::new(StmtParam::PostOrGet("cs".to_string()))),
+                StmtParam::Typed(
+                    ParamType::Inferred,
+                    Box::new(StmtParam::PostOrGet("d".to_string()))
+                ),
+            ]
+        );
+        let stmt = StmtWithParams {
+            query: ast.to_string(),
+            params: parameters,
+            delayed_functions: vec![],
+            json_columns: vec![],
+            read_only: true,
+        };
+        assert_eq!(
+            stmt.query_with_lists(AnyKind::Postgres, &[Some(2), Some(0)]),
+            "SELECT * FROM t WHERE a = $1 AND b IN (CAST($3 AS TEXT), CAST($4 AS TEXT)) \
+             AND c = ANY(ARRAY[NULL]::INT[]) AND d = $2"
+        );
+        assert_eq!(
+            stmt.query_with_lists(AnyKind::Postgres, &[Some(1), Some(2)]),
+            "SELECT * FROM t WHERE a = $1 AND b IN (CAST($3 AS TEXT)) \
+             AND c = ANY(ARRAY[CAST($4 AS TEXT), CAST($5 AS TEXT)]::INT[]) AND d = $2"
+        );
+        // A single value, such as '{1,2,3}', is cast to an array by the database
+        assert_eq!(
+            stmt.query_with_lists(AnyKind::Postgres, &[None, None]),
+            "SELECT * FROM t WHERE a = $1 AND b IN (CAST($3 AS TEXT)) \
+             AND c = ANY(CAST($4 AS TEXT)::INT[]) AND d = $2"
+        );
+
+        let mut ast = parse_stmt("select * from t where x not in (:xs)", &SQLiteDialect {});
+        let parameters = ParameterExtractor::extract_parameters(&mut ast, AnyKind::Sqlite);
+        let stmt = StmtWithParams {
+            query: ast.to_string(),
+            params: parameters,
+            delayed_functions: vec![],
+            json_columns: vec![],
+            read_only: true,
+        };
+        assert_eq!(
+            stmt.query_with_lists(AnyKind::Sqlite, &[Some(3)]),
+            "SELECT * FROM t WHERE x NOT IN (CAST(? AS TEXT), CAST(? AS TEXT), CAST(? AS TEXT))"
+        );
+    }
+
+    #[test]
+    fn test_typed_parameters() {
+        let mut ast = parse_postgres_stmt(
+            "update t set a = $a where t.id = $id and CAST(sqlpage.cookie('n') AS INT) > $b::timestamptz",
+        );
+        let parameters = ParameterExtractor::extract_parameters(&mut ast, AnyKind::Postgres);
+        assert_eq!(
+            ast.to_string(),
+            "UPDATE t SET a = $1 WHERE t.id = $2 AND CAST($3 AS INT) > $4::TIMESTAMPTZ"
+        );
+        let typed =
+            |t, name: &str| StmtParam::Typed(t, Box::new(StmtParam::PostOrGet(name.into())));
+        assert_eq!(
+            parameters[..2],
+            [
+                typed(ParamType::Inferred, "a"),
+                typed(ParamType::Inferred, "id")
+            ]
+        );
+        assert!(matches!(
+            &parameters[2],
+            StmtParam::Typed(ParamType::BigInt, f) if matches!(**f, StmtParam::FunctionCall(_))
+        ));
+        assert_eq!(parameters[3], typed(ParamType::DateTime, "b"));
+
+        // Other databases do not infer parameter types
+        let mut ast = parse_stmt(
+            "insert into t(a, b) values ($a, CAST($b AS REAL))",
+            &SQLiteDialect {},
+        );
+        let parameters = ParameterExtractor::extract_parameters(&mut ast, AnyKind::Sqlite);
+        assert_eq!(
+            ast.to_string(),
+            "INSERT INTO t (a, b) VALUES (CAST(? AS TEXT), CAST(? AS REAL))"
+        );
+        assert_eq!(
+            parameters,
+            [
+                StmtParam::PostOrGet("a".into()),
+                typed(ParamType::Double, "b")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_uploaded_file_bytes_is_not_cast_to_text() {
+        let is_blob =
+            |p: &StmtParam| matches!(p, StmtParam::Typed(ParamType::Blob, f) if f.returns_bytes());
+        let sql =
+            "insert into files(name, content) values ($name, sqlpage.uploaded_file_bytes('f'))";
+        let mut ast = parse_stmt(sql, &SQLiteDialect {});
+        let parameters = ParameterExtractor::extract_parameters(&mut ast, AnyKind::Sqlite);
+        assert_eq!(
+            ast.to_string(),
+            "INSERT INTO files (name, content) VALUES (CAST(? AS TEXT), ?)"
+        );
+        assert!(is_blob(&parameters[1]));
+
+        let mut ast = parse_postgres_stmt(sql);
+        let parameters = ParameterExtractor::extract_parameters(&mut ast, AnyKind::Postgres);
+        assert_eq!(
+            ast.to_string(),
+            "INSERT INTO files (name, content) VALUES ($1, $2)"
+        );
+        assert!(is_blob(&parameters[1]));
+    }
+
+    #[test]
+    fn test_cast_placeholder_from_text() {
+        assert_eq!(
+            cast_placeholder_from_text("SELECT '$1', $1, $10, a$1 FROM t WHERE x = $1", "$1", "UUID"),
+            "SELECT '$1', CAST(CAST($1 AS TEXT) AS UUID), $10, a$1 FROM t WHERE x = CAST(CAST($1 AS TEXT) AS UUID)"
+        );
+    }
+
     #[test]
     fn test_static_extract() {
         use SimpleSelectValue::Static;
@@ -1095,7 +2614,7 @@ mod test {
             use SimpleSelectValue::{Dynamic, Static};
             use StmtParam::PostOrGet;
 
-            let parsed: Vec<ParsedStatement> = parse_sql(dialect, sql).unwrap().collect();
+            let (parsed, _) = parse_sql(dialect, sql).unwrap();
             match &parsed[..] {
                 [ParsedStatement::StaticSimpleSelect(q)] => assert_eq!(
                     q,
@@ -1131,15 +2650,21 @@ mod test {
         let sql = "set x = 42";
         for &(dialect, db_kind) in ALL_DIALECTS {
             let mut parser = Parser::new(dialect).try_with_sql(sql).unwrap();
-            let stmt = parse_single_statement(&mut parser, db_kind, sql);
+            let stmt = parse_single_statement(
+                &mut parser,
+                db_kind,
+                sql,
+                TableDirectives::default(),
+                false,
+            );
             if let Some(ParsedStatement::SetVariable {
-                variable,
+                variables,
                 value: StmtWithParams { query, params, .. },
             }) = stmt
             {
                 assert_eq!(
-                    variable,
-                    StmtParam::PostOrGet("x".to_string()),
+                    variables,
+                    SetVariables::Single(StmtParam::PostOrGet("x".to_string())),
                     "{dialect:?}"
                 );
                 assert_eq!(query, "SELECT 42");
@@ -1150,6 +2675,310 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_stop_statement() {
+        for &(dialect, db_kind) in ALL_DIALECTS {
+            let sql = "select 'stop' as component; select 'stop' as component where $x is null; select 'text' as component";
+            let mut parser = Parser::new(dialect).try_with_sql(sql).unwrap();
+            let stmt = parse_single_statement(
+                &mut parser,
+                db_kind,
+                sql,
+                TableDirectives::default(),
+                false,
+            );
+            assert!(
+                matches!(stmt, Some(ParsedStatement::Stop(None))),
+                "{stmt:?}"
+            );
+            let stmt = parse_single_statement(
+                &mut parser,
+                db_kind,
+                sql,
+                TableDirectives::default(),
+                false,
+            );
+            let Some(ParsedStatement::Stop(Some(StmtWithParams { params, .. }))) = stmt else {
+                panic!("{dialect:?}: {stmt:?}");
+            };
+            assert_eq!(params, [StmtParam::PostOrGet("x".to_string())]);
+            let stmt = parse_single_statement(
+                &mut parser,
+                db_kind,
+                sql,
+                TableDirectives::default(),
+                false,
+            );
+            assert!(matches!(stmt, Some(ParsedStatement::StaticSimpleSelect(_))));
+        }
+    }
+
+    #[test]
+    fn test_include_directives() {
+        let sql = "select 1;\n-- @include 'a.sql'\n-- @include \"./b.sql\"\nselect 2; select 3;\n-- @include c.sql\n";
+        let (statements, includes) = parse_sql(&PostgreSqlDialect {}, sql).unwrap();
+        assert_eq!(statements.len(), 3);
+        let includes: Vec<_> = includes
+            .iter()
+            .map(|i| (i.statement_index, i.path.to_str().unwrap(), i.line))
+            .collect();
+        assert_eq!(
+            includes,
+            [(1, "a.sql", 2), (1, "b.sql", 3), (3, "c.sql", 5)]
+        );
+
+        let err =
+            parse_sql(&PostgreSqlDialect {}, "select 1\n-- @include 'a.sql'\n+ 1;").unwrap_err();
+        assert!(err.to_string().contains("between two statements"), "{err}");
+    }
+
+    #[test]
+    fn test_paginate_directive() {
+        let sql = "select 1;\n-- @paginate 20 p\nselect x from t where y = $y order by x; select 2";
+        let expected = [
+            (
+                AnyKind::Sqlite,
+                "SELECT x FROM t WHERE y = CAST(? AS TEXT) ORDER BY x LIMIT 20 OFFSET ?",
+                "SELECT COUNT(*) FROM (SELECT x FROM t WHERE y = CAST(? AS TEXT) ORDER BY x) AS sqlpage_count",
+            ),
+            (
+                AnyKind::Postgres,
+                "SELECT x FROM t WHERE y = $1 ORDER BY x LIMIT 20 OFFSET $2",
+                "SELECT COUNT(*) FROM (SELECT x FROM t WHERE y = $1 ORDER BY x) AS sqlpage_count",
+            ),
+            (
+                AnyKind::Mssql,
+                "SELECT x FROM t WHERE y = CAST(@p1 AS VARCHAR(MAX)) ORDER BY x OFFSET @p2 ROWS FETCH FIRST 20 ROWS ONLY",
+                "SELECT COUNT(*) FROM (SELECT x FROM t WHERE y = CAST(@p1 AS VARCHAR(MAX))) AS sqlpage_count",
+            ),
+        ];
+        for (db_kind, page_query, count_query) in expected {
+            let dialect = dialect_for_db(db_kind);
+            let (statements, _) = parse_sql(dialect.as_ref(), sql).unwrap();
+            let [_, ParsedStatement::TableQuery(TableQuery {
+                query: page,
+                pagination: Some(paginated),
+                sort_and_search: false,
+            }), _] = statements.as_slice()
+            else {
+                panic!("{db_kind:?}: {statements:?}");
+            };
+            assert_eq!(page.query, page_query);
+            assert_eq!(paginated.count.query, count_query);
+            assert_eq!(paginated.page_size, 20);
+            assert_eq!(paginated.page_variable, "p");
+            assert_eq!(paginated.count.params.len(), 1);
+            assert_eq!(page.params.len(), 2);
+            assert_eq!(
+                page.params.last(),
+                Some(&StmtParam::Typed(
+                    ParamType::BigInt,
+                    Box::new(StmtParam::PageOffset {
+                        variable: "p".into(),
+                        page_size: 20
+                    })
+                ))
+            );
+        }
+
+        let (statements, _) =
+            parse_sql(&SQLiteDialect {}, "-- @paginate 5\nselect * from t").unwrap();
+        let [ParsedStatement::TableQuery(TableQuery {
+            query: page,
+            pagination: Some(paginated),
+            ..
+        })] = statements.as_slice()
+        else {
+            panic!("{statements:?}");
+        };
+        assert_eq!(page.query, "SELECT * FROM t LIMIT 5 OFFSET ?");
+        assert_eq!(paginated.page_variable, "page");
+
+        let (statements, _) =
+            parse_sql(&SQLiteDialect {}, "-- @paginate 5\nselect * from t limit 3").unwrap();
+        assert!(matches!(statements.as_slice(), [ParsedStatement::Error(_)]));
+        let err = parse_sql(&SQLiteDialect {}, "-- @paginate many\nselect 1").unwrap_err();
+        assert!(format!("{err:#}").contains("Invalid @paginate"), "{err:#}");
+    }
+
+    #[test]
+    fn test_validate_component_grouping() {
+        let sql = "select 'validate' as component, 'form.sql' as form;
+            select 'email' as name, true as required;
+            select 'age' as name, $min as min;
+            select 'text' as component;
+            select 'x' as contents;";
+        let (statements, _) = parse_sql(&SQLiteDialect {}, sql).unwrap();
+        let [ParsedStatement::Validate { properties, fields }, ParsedStatement::StaticSimpleSelect(text), ParsedStatement::StaticSimpleSelect(_)] =
+            statements.as_slice()
+        else {
+            panic!("{statements:?}");
+        };
+        assert_eq!(properties.len(), 2);
+        assert_eq!(fields.len(), 2);
+        assert_eq!(
+            fields[1][1],
+            (
+                "min".into(),
+                SimpleSelectValue::Dynamic(StmtParam::PostOrGet("min".into()))
+            )
+        );
+        assert_eq!(static_component(text), Some("text"));
+    }
+
+    #[test]
+    fn test_validate_component_dynamic_field() {
+        let sql = "select 'validate' as component;
+            select name, true as required from form_fields;";
+        let (statements, _) = parse_sql(&SQLiteDialect {}, sql).unwrap();
+        let [ParsedStatement::Validate { .. }, ParsedStatement::Error(err)] = statements.as_slice()
+        else {
+            panic!("{statements:?}");
+        };
+        assert!(err.to_string().contains("validate component"), "{err}");
+        let sql = "select 'validate' as component;
+            select 'list' as component, upper($title) as title;";
+        let (statements, _) = parse_sql(&SQLiteDialect {}, sql).unwrap();
+        assert!(matches!(statements[1], ParsedStatement::StmtWithParams(_)));
+    }
+
+    #[test]
+    fn test_validate_component_order() {
+        let parse = |sql| {
+            let (statements, _) = parse_sql(&SQLiteDialect {}, sql).unwrap();
+            let mut file = ParsedSqlFile {
+                statements,
+                ..ParsedSqlFile::default()
+            };
+            file.check_validation_order();
+            file.statements
+        };
+        let statements = parse(
+            "insert into t values ($x);
+            select 'validate' as component;
+            select 'x' as name, true as required;",
+        );
+        let [ParsedStatement::Error(err)] = statements.as_slice() else {
+            panic!("{statements:?}");
+        };
+        assert!(err.to_string().contains("at the top of the file"), "{err}");
+        let statements = parse(
+            "select 'validate' as component;
+            select 'x' as name, true as required;
+            insert into t values ($x);",
+        );
+        assert!(matches!(
+            statements.as_slice(),
+            [
+                ParsedStatement::Validate { .. },
+                ParsedStatement::StmtWithParams(_)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_sort_and_search_directive() {
+        let sql = "-- @sort_and_search\n-- @paginate 10\nselect name, t.age, 'x' as _sqlpage_color from t where y = $y order by name";
+        let (statements, _) = parse_sql(&SQLiteDialect {}, sql).unwrap();
+        let [ParsedStatement::TableQuery(TableQuery {
+            query,
+            pagination: Some(pagination),
+            sort_and_search: true,
+        })] = statements.as_slice()
+        else {
+            panic!("{statements:?}");
+        };
+        assert_eq!(
+            query.query,
+            "SELECT * FROM (SELECT name, t.age, 'x' AS _sqlpage_color FROM t WHERE y = CAST(? AS TEXT)) AS sqlpage_table \
+            WHERE CAST(? AS TEXT) IS NULL \
+            OR LOWER(CAST(name AS TEXT)) LIKE CAST(? AS TEXT) ESCAPE '!' \
+            OR LOWER(CAST(age AS TEXT)) LIKE CAST(? AS TEXT) ESCAPE '!' \
+            ORDER BY CASE WHEN CAST(? AS TEXT) IS NOT NULL THEN name END ASC, \
+            CASE WHEN CAST(? AS TEXT) IS NOT NULL THEN name END DESC, \
+            CASE WHEN CAST(? AS TEXT) IS NOT NULL THEN age END ASC, \
+            CASE WHEN CAST(? AS TEXT) IS NOT NULL THEN age END DESC, \
+            name \
+            LIMIT 10 OFFSET ?"
+        );
+        assert_eq!(query.params.len(), 9);
+        assert_eq!(
+            query.params[4],
+            StmtParam::SortedBy {
+                column: "name".into(),
+                descending: false
+            }
+        );
+        assert!(pagination
+            .count
+            .query
+            .starts_with("SELECT COUNT(*) FROM (SELECT * FROM"));
+
+        for db_kind in [AnyKind::Postgres, AnyKind::MySql, AnyKind::Mssql] {
+            let dialect = dialect_for_db(db_kind);
+            let (statements, _) = parse_sql(dialect.as_ref(), sql).unwrap();
+            assert!(
+                matches!(statements.as_slice(), [ParsedStatement::TableQuery(_)]),
+                "{db_kind:?}: {statements:?}"
+            );
+        }
+
+        let (statements, _) =
+            parse_sql(&SQLiteDialect {}, "-- @sort_and_search\nselect * from t").unwrap();
+        assert!(matches!(statements.as_slice(), [ParsedStatement::Error(_)]));
+    }
+
+    #[test]
+    fn test_set_multiple_variables() {
+        for &(dialect, db_kind) in ALL_DIALECTS {
+            let sql = "set (a, b) = (select x, y from t where z = $z); set rows[] = (select 1, 2)";
+            let mut parser = Parser::new(dialect).try_with_sql(sql).unwrap();
+            let Some(ParsedStatement::SetVariable {
+                variables,
+                value: StmtWithParams { query, params, .. },
+            }) = parse_single_statement(
+                &mut parser,
+                db_kind,
+                sql,
+                TableDirectives::default(),
+                false,
+            )
+            else {
+                panic!("Failed to parse SET (a, b) for {dialect:?}");
+            };
+            assert_eq!(
+                variables,
+                SetVariables::Columns(vec![
+                    StmtParam::PostOrGet("a".to_string()),
+                    StmtParam::PostOrGet("b".to_string())
+                ])
+            );
+            assert!(
+                query.starts_with("SELECT x, y FROM t WHERE z = "),
+                "{query}"
+            );
+            assert_eq!(params.len(), 1);
+            let Some(ParsedStatement::SetVariable {
+                variables,
+                value: StmtWithParams { query, .. },
+            }) = parse_single_statement(
+                &mut parser,
+                db_kind,
+                sql,
+                TableDirectives::default(),
+                false,
+            )
+            else {
+                panic!("Failed to parse SET rows[] for {dialect:?}");
+            };
+            assert_eq!(
+                variables,
+                SetVariables::Rows(StmtParam::PostOrGet("rows".to_string()))
+            );
+            assert_eq!(query, "SELECT 1, 2");
+        }
+    }
+
     #[test]
     fn test_static_extract_doesnt_match() {
         assert_eq!(
@@ -1234,4 +3063,58 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn test_is_read_only() {
+        for sql in [
+            "SELECT * FROM users WHERE id = $1",
+            "WITH t AS (SELECT 1 AS x) SELECT x FROM t UNION SELECT 2",
+            "SELECT (SELECT count(*) FROM posts) AS n, upper(name) FROM users",
+            "SET x = (SELECT name FROM users LIMIT 1)",
+            "SELECT n FROM generate_series(1, 10) AS n",
+            "SELECT sqlpage.url_encode(name) FROM users",
+        ] {
+            assert!(is_read_only(&parse_postgres_stmt(sql)), "{sql}");
+        }
+        for sql in [
+            "INSERT INTO users (name) VALUES ('x')",
+            "UPDATE users SET name = 'x'",
+            "BEGIN",
+            "SELECT * FROM users FOR UPDATE",
+            "SELECT * INTO new_users FROM users",
+            "SELECT nextval('users_id_seq')",
+            "SELECT x FROM t WHERE y = pg_catalog.set_config('a', 'b', false)",
+            "WITH inserted AS (INSERT INTO users (name) VALUES ('x') RETURNING *) SELECT * FROM inserted",
+            "SET x = nextval('seq')",
+            "SELECT create_user('x')",
+            "SELECT * FROM archive_old_orders()",
+            "SELECT upper(my_schema.log_visit(name)) FROM users",
+        ] {
+            assert!(!is_read_only(&parse_postgres_stmt(sql)), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_file_directives() {
+        let sql = "\n-- A page that must see fresh data\n-- @primary\n--@other  some value \nSELECT 1;\n-- @ignored\n";
+        assert_eq!(
+            file_directives(sql).collect::<Vec<_>>(),
+            [("primary", ""), ("other", "some value")]
+        );
+        assert_eq!(file_directives("SELECT 1; -- @primary").count(), 0);
+    }
+
+    #[test]
+    fn test_directory_database() {
+        let mut config = crate::app_config::tests::test_config();
+        config.database_directories = [("admin", "legacy"), ("/admin/reports/", "reports")]
+            .into_iter()
+            .map(|(directory, name)| (directory.to_string(), name.to_string()))
+            .collect();
+        let database = |path: &str| directory_database(&config, Path::new(path));
+        assert_eq!(database("admin/users.sql"), Some("legacy"));
+        assert_eq!(database("./admin/reports/sales.sql"), Some("reports"));
+        assert_eq!(database("administration.sql"), None);
+        assert_eq!(database("index.sql"), None);
+    }
 }
@@ -86,6 +86,9 @@ pub fn sql_nonnull_to_json<'r>(mut get_ref: impl FnMut() -> sqlx::any::AnyValueR
         "JSON" | "JSON[]" | "JSONB" | "JSONB[]" => {
             <Value as Decode<sqlx::any::Any>>::decode(raw_value).unwrap_or_default()
         }
+        "BLOB" | "TINYBLOB" | "MEDIUMBLOB" | "LONGBLOB" | "BYTEA" | "BINARY" | "VARBINARY"
+        | "IMAGE" => <Vec<u8> as Decode<sqlx::any::Any>>::decode(raw_value)
+            .map_or(Value::Null, |bytes| blob_to_data_url(&bytes).into()),
         // Deserialize as a string by default
         _ => <String as Decode<sqlx::any::Any>>::decode(raw_value)
             .unwrap_or_default()
@@ -93,14 +96,15 @@ pub fn sql_nonnull_to_json<'r>(mut get_ref: impl FnMut() -> sqlx::any::AnyValueR
     }
 }
 
-/// Takes the first column of a row and converts it to a string.
-pub fn row_to_string(row: &AnyRow) -> Option<String> {
-    let col = row.columns().first()?;
-    match sql_to_json(row, col) {
-        serde_json::Value::String(s) => Some(s),
-        serde_json::Value::Null => None,
-        other => Some(other.to_string()),
-    }
+/// Binary data is represented as a data URL, that can be displayed by components or downloaded
+fn blob_to_data_url(bytes: &[u8]) -> String {
+    let mut data_url = String::from("data:application/octet-stream;base64,");
+    base64::Engine::encode_string(
+        &base64::engine::general_purpose::STANDARD,
+        bytes,
+        &mut data_url,
+    );
+    data_url
 }
 
 #[actix_web::test]
@@ -114,7 +118,8 @@ async fn test_row_to_json() -> anyhow::Result<()> {
         2 as two_values, \
         'x' as three_values, \
         'y' as three_values, \
-        'z' as three_values \
+        'z' as three_values, \
+        x'414243' as blob \
     ",
     )
     .fetch_one(&mut c)
@@ -125,6 +130,7 @@ async fn test_row_to_json() -> anyhow::Result<()> {
             "one_value": 123.456,
             "two_values": [1,2],
             "three_values": ["x","y","z"],
+            "blob": "data:application/octet-stream;base64,QUJD",
         })
     );
     Ok(())
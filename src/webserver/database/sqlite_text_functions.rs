@@ -0,0 +1,138 @@
+//! Regular expression and unicode text functions that `SQLite` does not provide by default.
+//! They are registered on every `SQLite` connection, unless `sqlite_text_functions` is disabled.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use regex::Regex;
+use sqlx::sqlite::{Function, SqliteConnectOptions, SqliteFunctionCtx};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Maximum number of compiled regular expressions kept in memory by each thread
+const REGEX_CACHE_SIZE: usize = 64;
+
+pub(super) fn register(options: SqliteConnectOptions) -> SqliteConnectOptions {
+    options
+        // `x REGEXP y` is evaluated by sqlite as `regexp(y, x)`
+        .function(text_function("regexp", 2..=2, |args| {
+            with_regex(&args[0], |re| Ok(i64::from(re.is_match(&args[1]))))
+        }))
+        .function(text_function("regexp_replace", 3..=3, |args| {
+            with_regex(&args[1], |re| {
+                Ok(re.replace_all(&args[0], args[2].as_str()).into_owned())
+            })
+        }))
+        .function(text_function("regexp_capture", 2..=3, |args| {
+            let group = args.get(2).map_or("1", String::as_str);
+            with_regex(&args[1], |re| Ok(capture(re, &args[0], group)))
+        }))
+        .function(text_function("normalize", 1..=2, |args| {
+            normalize(&args[0], args.get(1).map_or("NFC", String::as_str))
+        }))
+        .function(text_function("unaccent", 1..=1, |args| {
+            Ok(unaccent(&args[0]))
+        }))
+        .collation("NOACCENT", compare_without_accents)
+}
+
+/// Creates a deterministic `SQLite` function that takes text arguments, and returns NULL
+/// when any of them is NULL.
+fn text_function<R>(
+    name: &'static str,
+    arg_count: RangeInclusive<usize>,
+    f: fn(&[String]) -> anyhow::Result<R>,
+) -> Function
+where
+    R: for<'q> sqlx::Encode<'q, sqlx::Sqlite> + sqlx::Type<sqlx::Sqlite> + 'static,
+{
+    Function::new(name, move |ctx: &SqliteFunctionCtx| {
+        let mut args = Vec::with_capacity(*arg_count.end());
+        while let Ok(arg) = ctx.try_get_arg::<Option<String>>(args.len()) {
+            args.push(arg);
+        }
+        if !arg_count.contains(&args.len()) {
+            let expected = if arg_count.start() == arg_count.end() {
+                arg_count.start().to_string()
+            } else {
+                format!("{} to {}", arg_count.start(), arg_count.end())
+            };
+            return ctx.set_error(&format!(
+                "{name} expects {expected} arguments, but got {}",
+                args.len()
+            ));
+        }
+        let Some(args) = args.into_iter().collect::<Option<Vec<String>>>() else {
+            return ctx.set_result(None::<String>);
+        };
+        match f(&args) {
+            Ok(result) => ctx.set_result(result),
+            Err(e) => ctx.set_error(&format!("{name}: {e:#}")),
+        }
+    })
+    .deterministic()
+}
+
+fn with_regex<T>(pattern: &str, f: impl FnOnce(&Regex) -> anyhow::Result<T>) -> anyhow::Result<T> {
+    thread_local! {
+        static CACHE: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+    }
+    CACHE.with_borrow_mut(|cache| {
+        if let Some(re) = cache.get(pattern) {
+            return f(re);
+        }
+        let re = Regex::new(pattern)?;
+        if cache.len() >= REGEX_CACHE_SIZE {
+            cache.clear();
+        }
+        f(cache.entry(pattern.to_owned()).or_insert(re))
+    })
+}
+
+/// Returns the given capture group (by number or by name) of the first match of the regex
+fn capture(re: &Regex, text: &str, group: &str) -> Option<String> {
+    let captures = re.captures(text)?;
+    let matched = match group.parse::<usize>() {
+        Ok(index) => captures.get(index),
+        Err(_) => captures.name(group),
+    };
+    matched.map(|m| m.as_str().to_owned())
+}
+
+fn normalize(text: &str, form: &str) -> anyhow::Result<String> {
+    Ok(match form.to_ascii_uppercase().as_str() {
+        "NFC" => text.nfc().collect(),
+        "NFD" => text.nfd().collect(),
+        "NFKC" => text.nfkc().collect(),
+        "NFKD" => text.nfkd().collect(),
+        _ => anyhow::bail!("unknown normalization form {form:?}. Expected NFC, NFD, NFKC or NFKD"),
+    })
+}
+
+/// Removes diacritics: "Élève" becomes "Eleve"
+fn unaccent(text: &str) -> String {
+    text.nfd()
+        .filter(|&c| !is_combining_mark(c))
+        .nfc()
+        .collect()
+}
+
+fn compare_without_accents(a: &str, b: &str) -> Ordering {
+    unaccent(a).to_lowercase().cmp(&unaccent(b).to_lowercase())
+}
+
+#[test]
+fn test_text_helpers() {
+    assert_eq!(unaccent("Élève à Łódź"), "Eleve a Łodz");
+    assert_eq!(compare_without_accents("ÉLÈVE", "eleve"), Ordering::Equal);
+    assert_eq!(normalize("e\u{301}", "nfc").unwrap(), "é");
+    assert!(normalize("x", "other").is_err());
+    let re = Regex::new(r"(?<year>\d{4})-(\d{2})").unwrap();
+    assert_eq!(
+        capture(&re, "on 2024-10-01", "year").as_deref(),
+        Some("2024")
+    );
+    assert_eq!(capture(&re, "on 2024-10-01", "2").as_deref(), Some("10"));
+    assert_eq!(capture(&re, "never", "1"), None);
+}
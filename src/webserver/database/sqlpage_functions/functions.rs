@@ -1,16 +1,19 @@
 use super::RequestInfo;
 use crate::webserver::{
     database::{
-        execute_queries::DbConn, sqlpage_functions::url_parameter_deserializer::URLParameters,
+        execute_queries::DbConn, sql::ParsedSqlFile,
+        sqlpage_functions::url_parameter_deserializer::URLParameters,
     },
     http::SingleOrVec,
+    http_client::make_http_client,
     request_variables::ParamMap,
     ErrorWithStatus,
 };
+use actix_multipart::form::tempfile::TempFile;
 use anyhow::{anyhow, Context};
 use futures_util::StreamExt;
 use mime_guess::mime;
-use std::{borrow::Cow, ffi::OsStr, str::FromStr, sync::OnceLock};
+use std::{borrow::Cow, ffi::OsStr, path::Path, str::FromStr};
 
 super::function_definition_macro::sqlpage_functions! {
     basic_auth_password((&RequestInfo));
@@ -23,6 +26,7 @@ super::function_definition_macro::sqlpage_functions! {
     exec((&RequestInfo), program_name: Cow<str>, args: Vec<Cow<str>>);
 
     fetch((&RequestInfo), http_request: SqlPageFunctionParam<super::http_fetch_request::HttpFetchRequest<'_>>);
+    flash_messages((&RequestInfo));
 
     hash_password(password: Option<String>);
     header((&RequestInfo), name: Cow<str>);
@@ -31,23 +35,35 @@ super::function_definition_macro::sqlpage_functions! {
 
     path((&RequestInfo));
     persist_uploaded_file((&RequestInfo), field_name: Cow<str>, folder: Option<Cow<str>>, allowed_extensions: Option<Cow<str>>);
+    persist_uploaded_files((&RequestInfo), field_name: Cow<str>, folder: Option<Cow<str>>, allowed_extensions: Option<Cow<str>>);
     protocol((&RequestInfo));
+    purge_cache((&RequestInfo), path: Option<Cow<str>>);
 
     random_string(string_length: SqlPageFunctionParam<usize>);
     read_file_as_data_url((&RequestInfo), file_path: Option<Cow<str>>);
     read_file_as_text((&RequestInfo), file_path: Option<Cow<str>>);
     request_method((&RequestInfo));
-    run_sql((&RequestInfo, &mut DbConn), sql_file_path: Option<Cow<str>>, variables: Option<Cow<str>>);
+    run_sql((&RequestInfo, &mut DbConn), sql_file_path: Option<Cow<str>>, variables: Option<Cow<str>>, database: Option<Cow<str>>);
 
+    uploaded_file_bytes(upload_name: Cow<str>);
     uploaded_file_mime_type((&RequestInfo), upload_name: Cow<str>);
     uploaded_file_path((&RequestInfo), upload_name: Cow<str>);
     uploaded_file_name((&RequestInfo), upload_name: Cow<str>);
+    uploaded_files((&RequestInfo), upload_name: Cow<str>);
     url_encode(raw_text: Option<Cow<str>>);
 
     variables((&RequestInfo), get_or_post: Option<Cow<str>>);
     version();
 }
 
+impl SqlPageFunctionName {
+    /// Functions whose result is binary data, that is bound to its placeholder without a cast to text
+    #[must_use]
+    pub(crate) fn returns_bytes(self) -> bool {
+        self == Self::uploaded_file_bytes
+    }
+}
+
 /// Returns the password from the HTTP basic auth header, if present.
 async fn basic_auth_password(request: &RequestInfo) -> anyhow::Result<&str> {
     let password = extract_basic_auth(request)?.password().ok_or_else(|| {
@@ -188,63 +204,32 @@ async fn fetch(
     Ok(response_str)
 }
 
-static NATIVE_CERTS: OnceLock<anyhow::Result<rustls::RootCertStore>> = OnceLock::new();
-
-fn make_http_client(config: &crate::app_config::AppConfig) -> anyhow::Result<awc::Client> {
-    let connector = if config.system_root_ca_certificates {
-        let roots = NATIVE_CERTS
-            .get_or_init(|| {
-                log::debug!("Loading native certificates because system_root_ca_certificates is enabled");
-                let certs = rustls_native_certs::load_native_certs()
-                    .with_context(|| "Initial native certificates load failed")?;
-                log::info!("Loaded {} native certificates", certs.len());
-                let mut roots = rustls::RootCertStore::empty();
-                for cert in certs {
-                    log::trace!("Adding native certificate to root store: {cert:?}");
-                    roots.add(cert.clone()).with_context(|| {
-                        format!("Unable to add certificate to root store: {cert:?}")
-                    })?;
-                }
-                Ok(roots)
-            })
-            .as_ref()
-            .map_err(|e| anyhow!("Unable to load native certificates, make sure the system root CA certificates are available: {e}"))?;
-
-        log::trace!("Creating HTTP client with custom TLS connector using native certificates. SSL_CERT_FILE={:?}, SSL_CERT_DIR={:?}",
-            std::env::var("SSL_CERT_FILE").unwrap_or_default(),
-            std::env::var("SSL_CERT_DIR").unwrap_or_default());
-
-        let tls_conf = rustls::ClientConfig::builder()
-            .with_root_certificates(roots.clone())
-            .with_no_client_auth();
-
-        awc::Connector::new().rustls_0_22(std::sync::Arc::new(tls_conf))
-    } else {
-        log::debug!("Using the default tls connector with builtin certs because system_root_ca_certificates is disabled");
-        awc::Connector::new()
+/// Returns the messages set by the `flash` component on the previous page, as a JSON array.
+async fn flash_messages(request: &RequestInfo) -> anyhow::Result<Option<String>> {
+    let Some(cookie) = request.cookies.get(crate::webserver::flash::FLASH_COOKIE) else {
+        return Ok(None);
     };
-    let client = awc::Client::builder()
-        .connector(connector)
-        .add_default_header((awc::http::header::USER_AGENT, env!("CARGO_PKG_NAME")))
-        .finish();
-    log::debug!("Created HTTP client");
-    Ok(client)
+    let messages =
+        crate::webserver::flash::decode(&cookie.as_json_str(), &request.app_state.cookie_key);
+    Ok(Some(serde_json::to_string(&messages)?))
 }
 
 pub(crate) async fn hash_password(password: Option<String>) -> anyhow::Result<Option<String>> {
     let Some(password) = password else {
         return Ok(None);
     };
-    actix_web::rt::task::spawn_blocking(move || {
-        // Hashes a password using Argon2. This is a CPU-intensive blocking operation.
-        let phf = argon2::Argon2::default();
-        let salt = password_hash::SaltString::generate(&mut password_hash::rand_core::OsRng);
-        let password_hash = &password_hash::PasswordHash::generate(phf, password, &salt)
-            .map_err(|e| anyhow!("Unable to hash password: {}", e))?;
-        Ok(password_hash.to_string())
-    })
-    .await?
-    .map(Some)
+    actix_web::rt::task::spawn_blocking(move || hash_password_sync(&password))
+        .await?
+        .map(Some)
+}
+
+/// Hashes a password using Argon2. This is a CPU-intensive blocking operation.
+pub(crate) fn hash_password_sync(password: &str) -> anyhow::Result<String> {
+    let phf = argon2::Argon2::default();
+    let salt = password_hash::SaltString::generate(&mut password_hash::rand_core::OsRng);
+    let password_hash = &password_hash::PasswordHash::generate(phf, password, &salt)
+        .map_err(|e| anyhow!("Unable to hash password: {}", e))?;
+    Ok(password_hash.to_string())
 }
 
 async fn header<'a>(request: &'a RequestInfo, name: Cow<'a, str>) -> Option<Cow<'a, str>> {
@@ -288,13 +273,75 @@ async fn persist_uploaded_file<'a>(
     folder: Option<Cow<'a, str>>,
     allowed_extensions: Option<Cow<'a, str>>,
 ) -> anyhow::Result<Option<String>> {
-    let folder = folder.unwrap_or(Cow::Borrowed("uploads"));
-    let allowed_extensions_str =
-        allowed_extensions.unwrap_or(Cow::Borrowed(DEFAULT_ALLOWED_EXTENSIONS));
-    let allowed_extensions = allowed_extensions_str.split(',');
-    let Some(uploaded_file) = request.uploaded_files.get(&field_name.to_string()) else {
+    let Some(uploaded_file) = request.uploaded_files.get(&field_name) else {
         return Ok(None);
     };
+    let paths = persist_files(
+        request,
+        std::slice::from_ref(uploaded_file),
+        folder.as_deref(),
+        allowed_extensions.as_deref(),
+    )
+    .await
+    .with_context(|| format!("unable to persist uploaded file {field_name:?}"))?;
+    Ok(paths.into_iter().next())
+}
+
+/// Persists all the files of a field with the `multiple` attribute,
+/// and returns a JSON array with their paths, in the order in which they were uploaded.
+async fn persist_uploaded_files<'a>(
+    request: &'a RequestInfo,
+    field_name: Cow<'a, str>,
+    folder: Option<Cow<'a, str>>,
+    allowed_extensions: Option<Cow<'a, str>>,
+) -> anyhow::Result<Option<String>> {
+    let uploaded_files = request.uploaded_files.get_all(&field_name);
+    if uploaded_files.is_empty() {
+        return Ok(None);
+    }
+    let paths = persist_files(
+        request,
+        uploaded_files,
+        folder.as_deref(),
+        allowed_extensions.as_deref(),
+    )
+    .await
+    .with_context(|| format!("unable to persist the files uploaded in {field_name:?}"))?;
+    Ok(Some(serde_json::to_string(&paths)?))
+}
+
+async fn persist_files(
+    request: &RequestInfo,
+    uploaded_files: &[TempFile],
+    folder: Option<&str>,
+    allowed_extensions: Option<&str>,
+) -> anyhow::Result<Vec<String>> {
+    let folder = Path::new(folder.unwrap_or(&request.app_state.config.uploads_directory));
+    let allowed_extensions_str = allowed_extensions.unwrap_or(DEFAULT_ALLOWED_EXTENSIONS);
+    if !folder.is_relative() {
+        anyhow::bail!(
+            "the folder {} must be relative to the web root",
+            folder.display()
+        );
+    }
+    // All the files are checked before any of them is saved, so that no file is left behind
+    let extensions = uploaded_files
+        .iter()
+        .map(|uploaded_file| allowed_extension(uploaded_file, allowed_extensions_str))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut paths = Vec::with_capacity(uploaded_files.len());
+    for (uploaded_file, extension) in uploaded_files.iter().zip(extensions) {
+        paths.push(persist_file(request, uploaded_file, folder, extension).await?);
+    }
+    Ok(paths)
+}
+
+/// The extension of the uploaded file, if it is one of the allowed extensions
+fn allowed_extension<'a>(
+    uploaded_file: &'a TempFile,
+    allowed_extensions_str: &str,
+) -> anyhow::Result<&'a str> {
+    let allowed_extensions = allowed_extensions_str.split(',');
     let file_name = uploaded_file.file_name.as_deref().unwrap_or_default();
     let extension = file_name.split('.').last().unwrap_or_default();
     if !allowed_extensions
@@ -304,29 +351,31 @@ async fn persist_uploaded_file<'a>(
         let exts = allowed_extensions.collect::<Vec<_>>().join(", ");
         anyhow::bail!("file extension {extension} is not allowed. Allowed extensions: {exts}");
     }
-    // resolve the folder path relative to the web root
-    let web_root = &request.app_state.config.web_root;
-    let target_folder = web_root.join(&*folder);
-    // create the folder if it doesn't exist
-    tokio::fs::create_dir_all(&target_folder)
-        .await
-        .with_context(|| format!("unable to create folder {target_folder:?}"))?;
+    Ok(extension)
+}
+
+async fn persist_file(
+    request: &RequestInfo,
+    uploaded_file: &TempFile,
+    folder: &Path,
+    extension: &str,
+) -> anyhow::Result<String> {
+    let file_name = uploaded_file.file_name.as_deref().unwrap_or_default();
     let date = chrono::Utc::now().format("%Y-%m-%d %Hh%Mm%Ss");
     let random_part = random_string_sync(8);
-    let random_target_name = format!("{date} {random_part}.{extension}");
-    let target_path = target_folder.join(&random_target_name);
-    tokio::fs::copy(&uploaded_file.file.path(), &target_path)
+    let target_path = folder.join(format!("{date} {random_part}.{extension}"));
+    let app_state = &request.app_state;
+    app_state
+        .file_system
+        .persist(app_state, &target_path, uploaded_file.file.path())
         .await
-        .with_context(|| {
-            format!("unable to copy uploaded file {field_name:?} to {target_path:?}")
-        })?;
-    // remove the WEB_ROOT prefix from the path, but keep the leading slash
+        .with_context(|| format!("unable to save {file_name:?} to {}", target_path.display()))?;
+    // the path of the file in the web root, with a leading slash
     let path = "/".to_string()
         + target_path
-            .strip_prefix(web_root)?
             .to_str()
             .with_context(|| format!("unable to convert path {target_path:?} to a string"))?;
-    Ok(Some(path))
+    Ok(path)
 }
 
 /// Returns the protocol of the current request (http or https).
@@ -334,6 +383,14 @@ async fn protocol(request: &RequestInfo) -> &str {
     &request.protocol
 }
 
+/// Removes the pages stored by the `cache` component for the given path, or all of them.
+/// Returns the number of removed pages.
+async fn purge_cache(request: &RequestInfo, path: Option<Cow<'_, str>>) -> String {
+    let purged = request.app_state.response_cache.purge(path.as_deref());
+    log::debug!("Purged {purged} cached pages for {path:?}");
+    purged.to_string()
+}
+
 /// Returns a random string of the specified length.
 pub(crate) async fn random_string(len: usize) -> anyhow::Result<String> {
     // OsRng can block on Linux, so we run this on a blocking thread.
@@ -415,7 +472,7 @@ async fn read_file_as_text<'a>(
 }
 
 fn mime_from_upload_path<'a>(request: &'a RequestInfo, path: &str) -> Option<&'a mime_guess::Mime> {
-    request.uploaded_files.values().find_map(|uploaded_file| {
+    request.uploaded_files.files().find_map(|uploaded_file| {
         if uploaded_file.file.path() == OsStr::new(path) {
             uploaded_file.content_type.as_ref()
         } else {
@@ -438,22 +495,38 @@ async fn run_sql<'a>(
     db_connection: &mut DbConn,
     sql_file_path: Option<Cow<'a, str>>,
     variables: Option<Cow<'a, str>>,
+    database: Option<Cow<'a, str>>,
 ) -> anyhow::Result<Option<Cow<'a, str>>> {
     use serde::ser::{SerializeSeq, Serializer};
     let Some(sql_file_path) = sql_file_path else {
         log::debug!("run_sql: first argument is NULL, returning NULL");
         return Ok(None);
     };
-    let sql_file = request
-        .app_state
-        .sql_file_cache
-        .get_with_privilege(
-            &request.app_state,
-            std::path::Path::new(sql_file_path.as_ref()),
-            true,
+    let app_state = &request.app_state;
+    let path = Path::new(sql_file_path.as_ref());
+    // Boxed, because run_sql is recursive: the nested inclusions would otherwise overflow the stack
+    let sql_file = if let Some(database) = database {
+        // The file has to be parsed again, with the dialect of the requested database
+        let source = Box::pin(app_state.file_system.read_to_string(app_state, path, true))
+            .await
+            .with_context(|| format!("run_sql: invalid path {sql_file_path:?}"))?;
+        let parsed = Box::pin(ParsedSqlFile::parse_for_database(
+            app_state,
+            &source,
+            path,
+            Some(&database),
+        ))
+        .await;
+        std::sync::Arc::new(parsed)
+    } else {
+        Box::pin(
+            app_state
+                .sql_file_cache
+                .get_with_privilege(app_state, path, true),
         )
         .await
-        .with_context(|| format!("run_sql: invalid path {sql_file_path:?}"))?;
+        .with_context(|| format!("run_sql: invalid path {sql_file_path:?}"))?
+    };
     let mut tmp_req = if let Some(variables) = variables {
         let mut tmp_req = request.clone_without_variables();
         let variables: ParamMap = serde_json::from_str(&variables)?;
@@ -478,13 +551,13 @@ async fn run_sql<'a>(
     let mut json_encoder = serde_json::Serializer::new(&mut json_results_bytes);
     let mut seq = json_encoder.serialize_seq(None)?;
     while let Some(db_item) = results_stream.next().await {
-        use crate::webserver::database::DbItem::{Error, FinishedQuery, Row};
+        use crate::webserver::database::DbItem::{ComponentVariables, Error, FinishedQuery, Row};
         match db_item {
             Row(row) => {
                 log::debug!("run_sql: row: {:?}", row);
                 seq.serialize_element(&row)?;
             }
-            FinishedQuery => log::trace!("run_sql: Finished query"),
+            FinishedQuery | ComponentVariables(_) => log::trace!("run_sql: Finished query"),
             Error(err) => {
                 return Err(err.context(format!("run_sql: unable to run {sql_file_path:?}")))
             }
@@ -509,17 +582,46 @@ async fn uploaded_file_mime_type<'a>(
 ) -> Option<Cow<'a, str>> {
     let mime = request
         .uploaded_files
-        .get(&*upload_name)?
+        .get(&upload_name)?
         .content_type
         .as_ref()?;
     Some(Cow::Borrowed(mime.as_ref()))
 }
 
+/// The contents of an uploaded file can only be bound as a binary parameter:
+/// this is called when the result is used as text, in a string concatenation for instance.
+async fn uploaded_file_bytes(upload_name: Cow<'_, str>) -> anyhow::Result<Option<String>> {
+    anyhow::bail!(
+        "sqlpage.uploaded_file_bytes({upload_name:?}) returns binary data. \
+        It can only be used directly as a value sent to the database, as in \
+        INSERT INTO files (content) VALUES (sqlpage.uploaded_file_bytes('my_file'))"
+    )
+}
+
+/// Reads the contents of an uploaded file, for `sqlpage.uploaded_file_bytes`.
+/// Returns None when no file was uploaded with the given name.
+pub(crate) async fn read_uploaded_file_bytes(
+    request: &RequestInfo,
+    upload_name: &str,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let Some(uploaded_file) = request.uploaded_files.get(upload_name) else {
+        return Ok(None);
+    };
+    let path = uploaded_file.file.path();
+    let bytes = tokio::fs::read(path).await.with_context(|| {
+        format!(
+            "unable to read uploaded file {upload_name:?} from {}",
+            path.display()
+        )
+    })?;
+    Ok(Some(bytes))
+}
+
 async fn uploaded_file_path<'a>(
     request: &'a RequestInfo,
     upload_name: Cow<'a, str>,
 ) -> Option<Cow<'a, str>> {
-    let uploaded_file = request.uploaded_files.get(&*upload_name)?;
+    let uploaded_file = request.uploaded_files.get(&upload_name)?;
     Some(uploaded_file.file.path().to_string_lossy())
 }
 
@@ -529,12 +631,35 @@ async fn uploaded_file_name<'a>(
 ) -> Option<Cow<'a, str>> {
     let fname = request
         .uploaded_files
-        .get(&*upload_name)?
+        .get(&upload_name)?
         .file_name
         .as_ref()?;
     Some(Cow::Borrowed(fname.as_str()))
 }
 
+/// Returns a JSON array that describes all the files uploaded in a field with the `multiple` attribute,
+/// with the `name`, `path` and `mime_type` of each file.
+async fn uploaded_files(
+    request: &RequestInfo,
+    upload_name: Cow<'_, str>,
+) -> anyhow::Result<Option<String>> {
+    let uploaded_files = request.uploaded_files.get_all(&upload_name);
+    if uploaded_files.is_empty() {
+        return Ok(None);
+    }
+    let files = uploaded_files
+        .iter()
+        .map(|uploaded_file| {
+            serde_json::json!({
+                "name": uploaded_file.file_name,
+                "path": uploaded_file.file.path(),
+                "mime_type": uploaded_file.content_type.as_ref().map(AsRef::<str>::as_ref),
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(Some(serde_json::to_string(&files)?))
+}
+
 /// escapes a string for use in a URL using percent encoding
 /// for example, spaces are replaced with %20, '/' with %2F, etc.
 /// This is useful for constructing URLs in SQL queries.
@@ -542,23 +667,15 @@ async fn uploaded_file_name<'a>(
 /// rather than an empty string or an error.
 async fn url_encode(raw_text: Option<Cow<'_, str>>) -> Option<Cow<'_, str>> {
     Some(match raw_text? {
-        Cow::Borrowed(inner) => {
-            let encoded = percent_encoding::percent_encode(
-                inner.as_bytes(),
-                percent_encoding::NON_ALPHANUMERIC,
-            );
-            encoded.into()
-        }
-        Cow::Owned(inner) => {
-            let encoded = percent_encoding::percent_encode(
-                inner.as_bytes(),
-                percent_encoding::NON_ALPHANUMERIC,
-            );
-            Cow::Owned(encoded.collect())
-        }
+        Cow::Borrowed(inner) => url_encode_str(inner),
+        Cow::Owned(inner) => Cow::Owned(url_encode_str(&inner).into_owned()),
     })
 }
 
+pub(crate) fn url_encode_str(raw_text: &str) -> Cow<'_, str> {
+    percent_encoding::percent_encode(raw_text.as_bytes(), percent_encoding::NON_ALPHANUMERIC).into()
+}
+
 /// Returns all variables in the request as a JSON object.
 async fn variables<'a>(
     request: &'a RequestInfo,
@@ -2,6 +2,7 @@ mod function_definition_macro;
 mod function_traits;
 pub(super) mod functions;
 mod http_fetch_request;
+pub(super) mod sqlite_native;
 mod url_parameter_deserializer;
 
 use sqlparser::ast::FunctionArg;
@@ -0,0 +1,97 @@
+//! Pure sqlpage functions, that do not depend on the request, are also registered as native `SQLite` functions.
+//! This lets them be called anywhere in an expression, once per row, where the `SQLPage` parser
+//! cannot evaluate them before or after running the query.
+//! `sqlpage.url_encode(x)` is sent to `SQLite` as `sqlpage_url_encode(x)`.
+
+use super::functions::{hash_password_sync, random_string_sync, url_encode_str};
+use sqlx::sqlite::{Function, SqliteFunctionCtx};
+
+const NATIVE_FUNCTION_PREFIX: &str = "sqlpage_";
+
+struct NativeFunction {
+    name: &'static str,
+    arg_count: usize,
+    /// The function always returns the same result for the same arguments
+    deterministic: bool,
+    call: fn(&[Option<String>]) -> anyhow::Result<Option<String>>,
+}
+
+const NATIVE_FUNCTIONS: [NativeFunction; 4] = [
+    NativeFunction {
+        name: "url_encode",
+        arg_count: 1,
+        deterministic: true,
+        call: |args| Ok(args[0].as_deref().map(|s| url_encode_str(s).into_owned())),
+    },
+    NativeFunction {
+        name: "hash_password",
+        arg_count: 1,
+        deterministic: false,
+        call: |args| args[0].as_deref().map(hash_password_sync).transpose(),
+    },
+    NativeFunction {
+        name: "random_string",
+        arg_count: 1,
+        deterministic: false,
+        call: |args| {
+            let Some(len) = &args[0] else {
+                anyhow::bail!("The length of the random string cannot be NULL");
+            };
+            Ok(Some(random_string_sync(len.trim().parse()?)))
+        },
+    },
+    NativeFunction {
+        name: "version",
+        arg_count: 0,
+        deterministic: true,
+        call: |_args| Ok(Some(env!("CARGO_PKG_VERSION").to_string())),
+    },
+];
+
+/// Returns the name of the native `SQLite` function that implements the given sqlpage function, if any.
+pub(crate) fn native_sqlite_function_name(sqlpage_function_name: &str) -> Option<String> {
+    NATIVE_FUNCTIONS
+        .iter()
+        .find(|f| f.name.eq_ignore_ascii_case(sqlpage_function_name))
+        .map(|f| format!("{NATIVE_FUNCTION_PREFIX}{}", f.name))
+}
+
+/// The native `SQLite` functions to register on each new `SQLite` connection
+pub(crate) fn native_sqlite_functions() -> impl Iterator<Item = Function> {
+    NATIVE_FUNCTIONS.iter().map(|f| {
+        let NativeFunction {
+            name,
+            arg_count,
+            deterministic,
+            call,
+        } = *f;
+        let function = Function::new(
+            format!("{NATIVE_FUNCTION_PREFIX}{name}"),
+            move |ctx: &SqliteFunctionCtx| match native_function_args(ctx, arg_count)
+                .and_then(|args| call(&args))
+            {
+                Ok(result) => ctx.set_result(result),
+                Err(e) => ctx.set_error(&format!("sqlpage.{name}: {e:#}")),
+            },
+        );
+        if deterministic {
+            function.deterministic()
+        } else {
+            function
+        }
+    })
+}
+
+fn native_function_args(
+    ctx: &SqliteFunctionCtx,
+    arg_count: usize,
+) -> anyhow::Result<Vec<Option<String>>> {
+    let args = (0..arg_count)
+        .map(|i| ctx.try_get_arg::<Option<String>>(i))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow::anyhow!("expected {arg_count} text arguments: {e}"))?;
+    if ctx.try_get_arg::<Option<String>>(arg_count).is_ok() {
+        anyhow::bail!("expected {arg_count} arguments, but got more");
+    }
+    Ok(args)
+}
@@ -0,0 +1,77 @@
+//! Cancels the statements that run for longer than `max_query_duration_seconds` on the database server,
+//! so that they stop using its resources when `SQLPage` stops waiting for them.
+
+use sqlx::any::{AnyConnection, AnyConnectionKind, AnyKind};
+use sqlx::pool::PoolConnection;
+use sqlx::{Any, AnyPool, Connection, Executor};
+
+pub(super) enum StatementCanceller {
+    /// A statement that cancels the running one, such as `KILL QUERY 42`,
+    /// sent on a new connection to the same database server
+    Remote { pool: AnyPool, statement: String },
+    /// The address of the handle of a `SQLite` connection, that can be interrupted from another thread
+    Sqlite(usize),
+}
+
+impl StatementCanceller {
+    /// Prepares the cancellation of the next statement that runs on `connection`.
+    /// On databases other than `SQLite`, this asks the server for the id of the connection.
+    /// `pool` is the pool the connection comes from.
+    pub(super) async fn new(
+        pool: AnyPool,
+        connection: &mut PoolConnection<Any>,
+    ) -> anyhow::Result<Self> {
+        let (id_query, cancel_statement) = match connection.private_get_mut() {
+            AnyConnectionKind::Sqlite(sqlite) => {
+                // The handle is only used to call sqlite3_interrupt, which is safe from any thread
+                #[allow(deprecated)]
+                let handle = sqlite.as_raw_handle() as usize;
+                return Ok(Self::Sqlite(handle));
+            }
+            AnyConnectionKind::Postgres(_) => (
+                "SELECT CAST(pg_backend_pid() AS BIGINT)",
+                "SELECT pg_cancel_backend",
+            ),
+            AnyConnectionKind::MySql(_) => ("SELECT CAST(CONNECTION_ID() AS SIGNED)", "KILL QUERY"),
+            AnyConnectionKind::Mssql(_) => ("SELECT CAST(@@SPID AS BIGINT)", "KILL"),
+        };
+        let id: i64 = sqlx::query_scalar(id_query)
+            .fetch_one(&mut **connection)
+            .await?;
+        let statement = if connection.kind() == AnyKind::Postgres {
+            format!("{cancel_statement}({id})")
+        } else {
+            format!("{cancel_statement} {id}")
+        };
+        Ok(Self::Remote { pool, statement })
+    }
+
+    /// Cancels the statement on the server.
+    /// Must be called before the connection that runs the statement is closed.
+    pub(super) fn cancel(self) {
+        match self {
+            Self::Sqlite(handle) => {
+                log::debug!("Interrupting the SQLite statement");
+                // SAFETY: the connection, and thus its handle, is still open.
+                // sqlite3_interrupt can be called from any thread while a statement is running.
+                unsafe {
+                    libsqlite3_sys::sqlite3_interrupt(handle as *mut libsqlite3_sys::sqlite3);
+                }
+            }
+            Self::Remote { pool, statement } => {
+                actix_web::rt::spawn(async move {
+                    log::debug!("Cancelling the statement on the database server: {statement}");
+                    let cancelled = async {
+                        let mut connection =
+                            AnyConnection::connect_with(pool.connect_options()).await?;
+                        connection.execute(statement.as_str()).await?;
+                        connection.close().await
+                    };
+                    if let Err(e) = cancelled.await {
+                        log::warn!("Unable to cancel a statement on the database server with {statement}: {e}");
+                    }
+                });
+            }
+        }
+    }
+}
@@ -20,8 +20,10 @@ use crate::webserver::http::SingleOrVec;
 use crate::webserver::http_request_info::RequestInfo;
 
 use super::{
-    execute_queries::DbConn, sql::function_args_to_stmt_params,
-    sqlpage_functions::functions::SqlPageFunctionName,
+    execute_queries::DbConn,
+    parameter_types::{ParamType, ParamValue},
+    sql::{function_args_to_stmt_params, SEARCH_ESCAPE},
+    sqlpage_functions::functions::{read_uploaded_file_bytes, SqlPageFunctionName},
 };
 use anyhow::Context as _;
 
@@ -40,6 +42,51 @@ pub(crate) enum StmtParam {
     Concat(Vec<StmtParam>),
     JsonObject(Vec<StmtParam>),
     FunctionCall(SqlPageFunctionCall),
+    /// A multi-valued request parameter used as a list, as in `x IN ($ids)`.
+    /// Each of its values is bound to a separate placeholder.
+    List(Box<StmtParam>),
+    /// A parameter whose value is converted from text to the given type before being bound
+    Typed(ParamType, Box<StmtParam>),
+    /// The number of rows before the current page of a paginated query,
+    /// computed from the page number in the given URL parameter
+    PageOffset {
+        variable: String,
+        page_size: u64,
+    },
+    /// Not null when the rows are sorted by the given column, in the given direction,
+    /// according to the `sort` URL parameter
+    SortedBy {
+        column: String,
+        descending: bool,
+    },
+    /// The LIKE pattern that matches the text in the `search` URL parameter
+    SearchPattern,
+}
+
+impl StmtParam {
+    /// Whether the parameter calls the given sqlpage function, directly or in one of its arguments
+    pub(super) fn calls(&self, function: SqlPageFunctionName) -> bool {
+        match self {
+            StmtParam::FunctionCall(call) => {
+                call.function == function || call.arguments.iter().any(|arg| arg.calls(function))
+            }
+            StmtParam::Concat(params) | StmtParam::JsonObject(params) => {
+                params.iter().any(|param| param.calls(function))
+            }
+            StmtParam::List(param) | StmtParam::Typed(_, param) => param.calls(function),
+            _ => false,
+        }
+    }
+
+    /// Whether the parameter is a call to a function that returns binary data,
+    /// such as `sqlpage.uploaded_file_bytes('my_file')`
+    pub(super) fn returns_bytes(&self) -> bool {
+        match self {
+            StmtParam::FunctionCall(call) => call.function.returns_bytes(),
+            StmtParam::Typed(_, param) => param.returns_bytes(),
+            _ => false,
+        }
+    }
 }
 
 impl std::fmt::Display for StmtParam {
@@ -65,6 +112,16 @@ impl std::fmt::Display for StmtParam {
                 write!(f, ")")
             }
             StmtParam::FunctionCall(call) => write!(f, "{call}"),
+            StmtParam::List(values) => write!(f, "LIST({values})"),
+            StmtParam::Typed(param_type, param) => write!(f, "{param} AS {param_type}"),
+            StmtParam::PageOffset {
+                variable,
+                page_size,
+            } => write!(f, "PAGE_OFFSET(?{variable}, {page_size})"),
+            StmtParam::SortedBy { column, descending } => {
+                write!(f, "SORTED_BY(?{SORT_VARIABLE}, {column}, {descending})")
+            }
+            StmtParam::SearchPattern => write!(f, "SEARCH_PATTERN(?{SEARCH_VARIABLE})"),
             StmtParam::Error(x) => {
                 if let Some((i, _)) = x.char_indices().nth(21) {
                     write!(f, "## {}... ##", &x[..i])
@@ -114,6 +171,27 @@ impl SqlPageFunctionCall {
         );
         Ok(result)
     }
+
+    /// Evaluates a function that returns binary data instead of text
+    pub async fn evaluate_bytes(
+        &self,
+        request: &RequestInfo,
+        db_connection: &mut DbConn,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let [upload_name] = self.arguments.as_slice() else {
+            anyhow::bail!("Expected {:#}", self.function);
+        };
+        let Some(upload_name) = extract_req_param(upload_name, request, db_connection).await?
+        else {
+            return Ok(None);
+        };
+        match self.function {
+            SqlPageFunctionName::uploaded_file_bytes => {
+                read_uploaded_file_bytes(request, &upload_name).await
+            }
+            other => anyhow::bail!("{other} does not return binary data"),
+        }
+    }
 }
 
 impl std::fmt::Display for SqlPageFunctionCall {
@@ -142,27 +220,143 @@ pub(super) async fn extract_req_param<'a, 'b>(
         // sync functions
         StmtParam::Get(x) => request.get_variables.get(x).map(SingleOrVec::as_json_str),
         StmtParam::Post(x) => request.post_variables.get(x).map(SingleOrVec::as_json_str),
-        StmtParam::PostOrGet(x) => if let Some(v) = request.post_variables.get(x) {
-            log::warn!("Deprecation warning! ${x} was used to reference a form field value (a POST variable) instead of a URL parameter. This will stop working soon. Please use :{x} instead.");
-            Some(v)
-        } else {
-            request.get_variables.get(x)
-        }
-        .map(SingleOrVec::as_json_str),
+        StmtParam::PostOrGet(x) => post_or_get_variable(request, x).map(SingleOrVec::as_json_str),
         StmtParam::Error(x) => anyhow::bail!("{}", x),
         StmtParam::Literal(x) => Some(Cow::Owned(x.to_string())),
         StmtParam::Null => None,
         StmtParam::Concat(args) => concat_params(&args[..], request, db_connection).await?,
-        StmtParam::JsonObject(args) => json_object_params(&args[..], request, db_connection).await?,
-        StmtParam::FunctionCall(func) => func.evaluate(request, db_connection).await.with_context(|| {
-            format!(
-                "Error in function call {func}.\nExpected {:#}",
-                func.function
-            )
-        })?,
+        StmtParam::JsonObject(args) => {
+            json_object_params(&args[..], request, db_connection).await?
+        }
+        StmtParam::FunctionCall(func) => {
+            func.evaluate(request, db_connection)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Error in function call {func}.\nExpected {:#}",
+                        func.function
+                    )
+                })?
+        }
+        StmtParam::List(values) | StmtParam::Typed(_, values) => {
+            Box::pin(extract_req_param(values, request, db_connection)).await?
+        }
+        StmtParam::PageOffset {
+            variable,
+            page_size,
+        } => Some(Cow::Owned(
+            ((page_number(request, variable) - 1).saturating_mul(*page_size)).to_string(),
+        )),
+        StmtParam::SortedBy { column, descending } => sort_order(request)
+            .filter(|(sort_column, sort_descending)| {
+                sort_column.eq_ignore_ascii_case(column) && sort_descending == descending
+            })
+            .map(|_| Cow::Borrowed("1")),
+        StmtParam::SearchPattern => search_text(request).map(|text| {
+            let mut pattern = String::with_capacity(text.len() + 2);
+            pattern.push('%');
+            for c in text.to_lowercase().chars() {
+                if matches!(c, '%' | '_') || c == SEARCH_ESCAPE {
+                    pattern.push(SEARCH_ESCAPE);
+                }
+                pattern.push(c);
+            }
+            pattern.push('%');
+            Cow::Owned(pattern)
+        }),
     })
 }
 
+/// Extracts the value that is bound to the placeholder of a parameter.
+/// Unlike [`extract_req_param`], it can be binary data, read by `sqlpage.uploaded_file_bytes`.
+pub(super) async fn extract_req_param_value<'a>(
+    param: &StmtParam,
+    request: &'a RequestInfo,
+    db_connection: &mut DbConn,
+) -> anyhow::Result<Option<ParamValue<'a>>> {
+    if let StmtParam::Typed(ParamType::Blob, inner) = param {
+        if let StmtParam::FunctionCall(func) = inner.as_ref() {
+            return func
+                .evaluate_bytes(request, db_connection)
+                .await
+                .map(|bytes| bytes.map(ParamValue::Bytes))
+                .with_context(|| format!("Error in function call {func}"));
+        }
+    }
+    let value = extract_req_param(param, request, db_connection).await?;
+    Ok(value.map(ParamValue::Text))
+}
+
+/// The URL parameter that contains the column by which the rows of a `-- @sort_and_search` query are sorted.
+/// A leading `-` sorts in descending order.
+pub(super) const SORT_VARIABLE: &str = "sort";
+/// The URL parameter that contains the text searched in the rows of a `-- @sort_and_search` query
+pub(super) const SEARCH_VARIABLE: &str = "search";
+
+/// The column and the direction requested in the `sort` URL parameter
+pub(super) fn sort_order(request: &RequestInfo) -> Option<(&str, bool)> {
+    let sort = request.get_variables.get(SORT_VARIABLE)?;
+    let SingleOrVec::Single(sort) = sort else {
+        return None;
+    };
+    let sort = sort.trim();
+    Some(match sort.strip_prefix('-') {
+        Some(column) => (column, true),
+        None => (sort, false),
+    })
+    .filter(|(column, _)| !column.is_empty())
+}
+
+/// The text requested in the `search` URL parameter, if it is not empty
+pub(super) fn search_text(request: &RequestInfo) -> Option<&str> {
+    match request.get_variables.get(SEARCH_VARIABLE)? {
+        SingleOrVec::Single(search) => Some(search.trim()).filter(|s| !s.is_empty()),
+        _ => None,
+    }
+}
+
+/// The page requested in a URL parameter, starting at 1.
+/// Missing and invalid page numbers are replaced by the first page.
+pub(super) fn page_number(request: &RequestInfo, variable: &str) -> u64 {
+    request
+        .get_variables
+        .get(variable)
+        .and_then(|page| page.as_json_str().trim().parse().ok())
+        .filter(|&page| page > 0)
+        .unwrap_or(1)
+}
+
+/// Extracts all the values of a multi-valued request parameter.
+/// A parameter that is missing from the request has no values.
+pub(super) fn extract_req_param_list<'a>(
+    param: &StmtParam,
+    request: &'a RequestInfo,
+) -> Vec<Cow<'a, str>> {
+    request_variable(param, request).map_or_else(Vec::new, SingleOrVec::values)
+}
+
+/// The variable a parameter references, if it is a plain request variable
+pub(super) fn request_variable<'a>(
+    param: &StmtParam,
+    request: &'a RequestInfo,
+) -> Option<&'a SingleOrVec> {
+    match param {
+        StmtParam::Get(x) => request.get_variables.get(x),
+        StmtParam::Post(x) => request.post_variables.get(x),
+        StmtParam::PostOrGet(x) => post_or_get_variable(request, x),
+        _ => None,
+    }
+}
+
+fn post_or_get_variable<'a>(request: &'a RequestInfo, name: &str) -> Option<&'a SingleOrVec> {
+    if let Some(v) = request.post_variables.get(name) {
+        log::warn!("Deprecation warning! ${name} was used to reference a form field value (a POST variable) instead of a URL parameter. This will stop working soon. Please use :{name} instead.");
+        Some(v)
+    } else {
+        request.get_variables.get(name)
+    }
+}
+
 async fn concat_params<'a, 'b>(
     args: &[StmtParam],
     request: &'a RequestInfo,
@@ -0,0 +1,156 @@
+//! Metadata about the queries paginated with `-- @paginate`, and sorted with `-- @sort_and_search`,
+//! used by the `table` component to display links to the other pages, and to send the sort and search
+//! interactions back to the server.
+
+use super::sql::{Pagination, TableQuery};
+use super::syntax_tree::{page_number, search_text, sort_order};
+use crate::webserver::http::SingleOrVec;
+use crate::webserver::http_request_info::RequestInfo;
+use actix_web::web;
+use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+use serde_json::{json, Map, Value};
+use std::fmt::Write;
+
+/// Number of links to the neighbouring pages displayed on each side of the current page
+const NEIGHBOUR_PAGES: u64 = 2;
+
+/// The variables given to the table component: `@pagination` and `@sort_and_search`
+pub(super) fn table_variables(
+    table: &TableQuery,
+    total_rows: Option<u64>,
+    request: &RequestInfo,
+) -> Map<String, Value> {
+    let mut variables = Map::new();
+    if let (Some(pagination), Some(total_rows)) = (&table.pagination, total_rows) {
+        variables.insert(
+            "pagination".into(),
+            page_metadata(pagination, total_rows, request),
+        );
+    }
+    if table.sort_and_search {
+        let sort = sort_order(request)
+            .map(|(column, descending)| format!("{}{column}", if descending { "-" } else { "" }));
+        let page_variable = table.pagination.as_ref().map(|p| p.page_variable.as_str());
+        variables.insert(
+            "sort_and_search".into(),
+            json!({
+                "sort": sort,
+                "search": search_text(request),
+                "page_variable": page_variable,
+            }),
+        );
+    }
+    variables
+}
+
+fn page_metadata(pagination: &Pagination, total_rows: u64, request: &RequestInfo) -> Value {
+    let variable = pagination.page_variable.as_str();
+    let page = page_number(request, variable);
+    pages_metadata(page, pagination.page_size, total_rows, |number| {
+        page_link(&request.query_string, variable, number)
+    })
+}
+
+fn page_count(page_size: u64, total_rows: u64) -> u64 {
+    total_rows.div_ceil(page_size).max(1)
+}
+
+/// Replaces a page number past the last page with the last page,
+/// so that the query returns the rows of the last page, and the pager shows it
+pub(super) fn clamp_page_number(
+    request: &mut RequestInfo,
+    pagination: &Pagination,
+    total_rows: u64,
+) {
+    let variable = pagination.page_variable.as_str();
+    let page_count = page_count(pagination.page_size, total_rows);
+    if page_number(request, variable) > page_count {
+        log::debug!("Page {variable} is past the last page, showing page {page_count}");
+        request.get_variables.insert(
+            variable.to_string(),
+            SingleOrVec::Single(page_count.to_string()),
+        );
+    }
+}
+
+fn pages_metadata(
+    page: u64,
+    page_size: u64,
+    total_rows: u64,
+    link: impl Fn(u64) -> String,
+) -> Value {
+    let page_count = page_count(page_size, total_rows);
+    let page = page.min(page_count);
+    let first_shown = page.saturating_sub(NEIGHBOUR_PAGES).max(1);
+    let last_shown = page.saturating_add(NEIGHBOUR_PAGES).min(page_count);
+    let pages: Vec<Value> = (first_shown..=last_shown)
+        .map(|number| {
+            json!({
+                "number": number,
+                "link": link(number),
+                "active": number == page,
+            })
+        })
+        .collect();
+    json!({
+        "page": page,
+        "page_size": page_size,
+        "page_count": page_count,
+        "total_rows": total_rows,
+        "first_link": link(1),
+        "last_link": link(page_count),
+        "previous_link": (page > 1).then(|| link(page - 1)),
+        "next_link": (page < page_count).then(|| link(page + 1)),
+        "pages": pages,
+    })
+}
+
+/// A link to the current page with the given page number, which keeps the other parameters of the URL.
+/// It is built from the query string of the request, so that the variables set by the page are not exposed.
+fn page_link(query_string: &str, variable: &str, page: u64) -> String {
+    let parameters = web::Query::<Vec<(String, String)>>::from_query(query_string)
+        .map(web::Query::into_inner)
+        .unwrap_or_default();
+    let mut link = String::from("?");
+    for (name, value) in &parameters {
+        if name.strip_suffix("[]").unwrap_or(name) == variable {
+            continue;
+        }
+        let name = percent_encode(name.as_bytes(), NON_ALPHANUMERIC);
+        let value = percent_encode(value.as_bytes(), NON_ALPHANUMERIC);
+        let _ = write!(link, "{name}={value}&");
+    }
+    let variable = percent_encode(variable.as_bytes(), NON_ALPHANUMERIC);
+    let _ = write!(link, "{variable}={page}");
+    link
+}
+
+#[test]
+fn test_page_metadata() {
+    let query_string = "q=a+b&p=4&tag[]=x&tag[]=y";
+    let link = |number| page_link(query_string, "p", number);
+    assert_eq!(link(3), "?q=a%20b&tag%5B%5D=x&tag%5B%5D=y&p=3");
+
+    let metadata = pages_metadata(4, 10, 45, link);
+    assert_eq!(metadata["page_count"], 5);
+    assert_eq!(
+        metadata["previous_link"],
+        "?q=a%20b&tag%5B%5D=x&tag%5B%5D=y&p=3"
+    );
+    let numbers: Vec<&Value> = metadata["pages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| &p["number"])
+        .collect();
+    assert_eq!(numbers, [2, 3, 4, 5]);
+    assert_eq!(metadata["pages"][2]["active"], true);
+
+    let last_page = pages_metadata(4, 10, 40, |n| n.to_string());
+    assert_eq!(last_page["next_link"], Value::Null);
+    let empty = pages_metadata(1, 10, 0, |n| n.to_string());
+    assert_eq!(empty["page_count"], 1);
+    let past_the_end = pages_metadata(9, 10, 45, |n| n.to_string());
+    assert_eq!(past_the_end["page"], 5);
+    assert_eq!(past_the_end["pages"].as_array().unwrap().len(), 3);
+}
@@ -0,0 +1,202 @@
+//! The `download` component, that answers with a file instead of an HTML page.
+
+use crate::app_config::UploadStorage;
+use crate::filesystem::OpenedFile;
+use crate::webserver::ErrorWithStatus;
+use crate::AppState;
+use actix_web::body::{BoxBody, SizedStream};
+use actix_web::http::header::{
+    self, Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue, HeaderMap,
+};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use anyhow::Context as _;
+use base64::Engine as _;
+use serde_json::Value;
+use std::path::{Component, Path};
+
+const OCTET_STREAM: &str = "application/octet-stream";
+
+pub struct Download {
+    body: BoxBody,
+    content_type: String,
+    disposition: ContentDisposition,
+}
+
+impl Download {
+    /// Reads the properties of the `download` component.
+    /// The downloaded data comes either from `contents` (text, or a data URL, which is how BLOB columns are returned),
+    /// or from a `file` of the uploads directory.
+    pub async fn from_properties(app_state: &AppState, data: &Value) -> anyhow::Result<Self> {
+        let property = |name: &str| data.get(name).and_then(Value::as_str);
+        let filename = property("filename");
+        let (body, data_url_type, fallback_type) = match (property("contents"), property("file")) {
+            (Some(contents), None) => match parse_data_url(contents) {
+                Some((mime, bytes)) => (BoxBody::new(bytes?), Some(mime), OCTET_STREAM.to_owned()),
+                None => (
+                    BoxBody::new(contents.as_bytes().to_vec()),
+                    None,
+                    "text/plain; charset=utf-8".to_owned(),
+                ),
+            },
+            (None, Some(path)) => (
+                open_upload(app_state, path).await?,
+                None,
+                guess_mime_type(path),
+            ),
+            _ => anyhow::bail!(
+                "The download component requires either a 'contents' or a 'file' property"
+            ),
+        };
+        // The most specific type wins
+        let content_type = property("content_type").map_or_else(
+            || {
+                data_url_type
+                    .filter(|t| !t.is_empty() && *t != OCTET_STREAM)
+                    .map(str::to_owned)
+                    .or_else(|| filename.map(guess_mime_type).filter(|t| t != OCTET_STREAM))
+                    .unwrap_or(fallback_type)
+            },
+            str::to_owned,
+        );
+        let inline = matches!(data.get("inline"), Some(Value::Bool(true)))
+            || data.get("inline").and_then(Value::as_i64) == Some(1);
+        Ok(Self {
+            body,
+            content_type,
+            disposition: content_disposition(inline, filename),
+        })
+    }
+
+    /// Sends the data, without loading local files in memory
+    pub fn respond(self, mut response: HttpResponseBuilder) -> HttpResponse {
+        response
+            .insert_header((header::CONTENT_TYPE, self.content_type))
+            .insert_header(self.disposition)
+            .body(self.body)
+    }
+}
+
+/// Opens a file of the uploads directory, from where `upload_storage` says uploaded files are stored.
+/// Other files of the web root, such as the SQL files and the configuration, cannot be sent.
+async fn open_upload(app_state: &AppState, path: &str) -> anyhow::Result<BoxBody> {
+    let config = &app_state.config;
+    let path = Path::new(path.trim_start_matches('/'));
+    let uploads_directory = Path::new(config.uploads_directory.trim_start_matches('/'));
+    let in_uploads_directory = path.starts_with(uploads_directory)
+        && path.components().all(|c| matches!(c, Component::Normal(_)));
+    if !in_uploads_directory {
+        anyhow::bail!(
+            "The download component can only send files from the {} directory, where uploaded files are stored, not {}",
+            uploads_directory.display(),
+            path.display()
+        );
+    }
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("sql"))
+    {
+        anyhow::bail!(
+            "The download component cannot send the source of SQL files: {}",
+            path.display()
+        );
+    }
+    let file_system = &app_state.file_system;
+    let file = match config.upload_storage {
+        UploadStorage::Local => file_system
+            .open_local_file_with_date(app_state, path)
+            .await?
+            .map(|(file, _)| file)
+            .ok_or_else(|| {
+                anyhow::Error::new(ErrorWithStatus {
+                    status: StatusCode::NOT_FOUND,
+                })
+            }),
+        UploadStorage::Database => file_system
+            .open_database_file_with_date(app_state, path)
+            .await
+            .map(|(file, _)| file),
+        UploadStorage::S3 => {
+            let storage = file_system
+                .object_storage()
+                .context("upload_storage is set to s3, but the bucket is not configured")?;
+            let key = path.to_string_lossy();
+            return storage
+                .serve(config, &key, &HeaderMap::new())
+                .await
+                .map(HttpResponse::into_body)
+                .with_context(|| format!("Unable to download {} from the bucket", path.display()));
+        }
+    };
+    Ok(
+        match file.with_context(|| format!("Unable to download {}", path.display()))? {
+            OpenedFile::InMemory(bytes) => BoxBody::new(bytes),
+            OpenedFile::Local { file, len } => BoxBody::new(SizedStream::new(
+                len,
+                tokio_util::io::ReaderStream::new(file),
+            )),
+        },
+    )
+}
+
+/// Decodes `data:[<mime type>][;base64],<data>`
+fn parse_data_url(url: &str) -> Option<(&str, anyhow::Result<Vec<u8>>)> {
+    let (metadata, data) = url.strip_prefix("data:")?.split_once(',')?;
+    Some(if let Some(mime) = metadata.strip_suffix(";base64") {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .context("Invalid base64 data in the data URL");
+        (mime, bytes)
+    } else {
+        let bytes = percent_encoding::percent_decode_str(data).collect();
+        (metadata, Ok(bytes))
+    })
+}
+
+fn guess_mime_type(path: &str) -> String {
+    mime_guess::from_path(path)
+        .first_or_octet_stream()
+        .to_string()
+}
+
+fn content_disposition(inline: bool, filename: Option<&str>) -> ContentDisposition {
+    let mut parameters = Vec::new();
+    if let Some(filename) = filename {
+        if filename.is_ascii() {
+            parameters.push(DispositionParam::Filename(filename.to_owned()));
+        } else {
+            // Old browsers only understand the ASCII version of the file name
+            let ascii = filename.replace(|c: char| !c.is_ascii(), "_");
+            parameters.push(DispositionParam::Filename(ascii));
+            parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+                charset: Charset::Ext("UTF-8".to_owned()),
+                language_tag: None,
+                value: filename.as_bytes().to_vec(),
+            }));
+        }
+    }
+    ContentDisposition {
+        disposition: if inline {
+            DispositionType::Inline
+        } else {
+            DispositionType::Attachment
+        },
+        parameters,
+    }
+}
+
+#[test]
+fn test_parse_data_url() {
+    let (mime, bytes) = parse_data_url("data:image/png;base64,iVBORw==").unwrap();
+    assert_eq!(mime, "image/png");
+    assert_eq!(bytes.unwrap(), b"\x89PNG");
+    let (mime, bytes) = parse_data_url("data:,Hello%20World").unwrap();
+    assert_eq!(mime, "");
+    assert_eq!(bytes.unwrap(), b"Hello World");
+    assert!(parse_data_url("Hello").is_none());
+    assert_eq!(
+        content_disposition(false, Some("résumé.pdf")).to_string(),
+        "attachment; filename=\"r_sum_.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf"
+    );
+    assert_eq!(content_disposition(true, None).to_string(), "inline");
+}
@@ -17,6 +17,11 @@ impl std::fmt::Display for ErrorWithStatus {
 }
 impl std::error::Error for ErrorWithStatus {}
 
+pub(crate) fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<ErrorWithStatus>()
+        .is_some_and(|e| e.status == StatusCode::NOT_FOUND)
+}
+
 impl ResponseError for ErrorWithStatus {
     fn status_code(&self) -> StatusCode {
         self.status
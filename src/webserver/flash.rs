@@ -0,0 +1,66 @@
+//! One-shot messages set by the `flash` component before a redirect,
+//! and displayed on the next page the user visits.
+//! They are stored in a cookie, signed so that users cannot forge messages.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+
+pub const FLASH_COOKIE: &str = "sqlpage_flash";
+
+/// Browsers ignore cookies larger than 4KB
+const MAX_COOKIE_SIZE: usize = 4000;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Encodes the messages as a signed cookie value
+pub fn encode(messages: &[Value], key: &[u8]) -> anyhow::Result<String> {
+    let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(messages)?);
+    let signature = URL_SAFE_NO_PAD.encode(signer(key, &payload).finalize().into_bytes());
+    let cookie = format!("{payload}.{signature}");
+    if cookie.len() > MAX_COOKIE_SIZE {
+        anyhow::bail!(
+            "The flash messages are too long to be stored in a cookie ({} bytes, the maximum is {MAX_COOKIE_SIZE})",
+            cookie.len()
+        );
+    }
+    Ok(cookie)
+}
+
+/// Decodes the messages stored in a cookie. Returns no message if the signature is invalid.
+#[must_use]
+pub fn decode(cookie: &str, key: &[u8]) -> Vec<Value> {
+    let verified = cookie.split_once('.').and_then(|(payload, signature)| {
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        signer(key, payload).verify_slice(&signature).ok()?;
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    });
+    if verified.is_none() {
+        log::debug!("Ignoring a flash message cookie with an invalid signature");
+    }
+    verified.unwrap_or_default()
+}
+
+fn signer(key: &[u8], payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+#[test]
+fn test_flash_cookie() {
+    let messages = vec![serde_json::json!({"color": "success", "title": "Saved"})];
+    let cookie = encode(&messages, b"key").unwrap();
+    assert_eq!(decode(&cookie, b"key"), messages);
+    assert_eq!(decode(&cookie, b"other key"), Vec::<Value>::new());
+    let forged = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(r#"[{"title":"Forged"}]"#),
+        cookie.split_once('.').unwrap().1
+    );
+    assert_eq!(decode(&forged, b"key"), Vec::<Value>::new());
+    let too_long = vec![Value::String("x".repeat(MAX_COOKIE_SIZE))];
+    assert!(encode(&too_long, b"key").is_err());
+}
@@ -0,0 +1,317 @@
+//! Server-side validation of form submissions with the `validate` component,
+//! and display of the errors in the `form` component the user is sent back to.
+
+use super::http::SingleOrVec;
+use super::http_request_info::UploadedFiles;
+use super::request_variables::ParamMap;
+use anyhow::Context as _;
+use serde_json::{Map, Value};
+
+/// The errors of an invalid form submission, and the values that were submitted
+#[derive(Debug, Default, Clone)]
+pub struct FormValidation {
+    pub errors: Map<String, Value>,
+    pub values: Map<String, Value>,
+}
+
+/// Checks the submitted values against field definitions that have the same properties
+/// as the fields of the `form` component (`name`, `type`, `required`, `min`, `max`, ...).
+/// Returns an error message for each invalid field, keyed by field name.
+pub fn validate_fields(
+    fields: &[Value],
+    variables: &ParamMap,
+    files: &UploadedFiles,
+) -> anyhow::Result<Map<String, Value>> {
+    let mut errors = Map::new();
+    for field in fields {
+        let Some(name) = field.get("name").and_then(Value::as_str) else {
+            anyhow::bail!(
+                "Each field of the validate component must have a name. Invalid field: {field}"
+            );
+        };
+        let has_file = files.contains_key(name);
+        let name = field_key(name);
+        if let Some(message) = field_error(field, variables.get(name), has_file)
+            .with_context(|| format!("Unable to validate the {name:?} field"))?
+        {
+            let message = field
+                .get("error_message")
+                .and_then(Value::as_str)
+                .map_or(message, str::to_owned);
+            errors.insert(name.to_owned(), Value::String(message));
+        }
+    }
+    Ok(errors)
+}
+
+/// Request variables ending with `[]` are stored without the brackets
+fn field_key(name: &str) -> &str {
+    name.strip_suffix("[]").unwrap_or(name)
+}
+
+fn field_error(
+    field: &Value,
+    submitted: Option<&SingleOrVec>,
+    has_file: bool,
+) -> anyhow::Result<Option<String>> {
+    let values: Vec<&str> = match submitted {
+        Some(SingleOrVec::Single(s)) => vec![s.as_str()],
+        Some(SingleOrVec::Vec(v)) => v.iter().map(String::as_str).collect(),
+        Some(SingleOrVec::Json(_)) | None => Vec::new(),
+    };
+    let values: Vec<&str> = values.into_iter().filter(|v| !v.is_empty()).collect();
+    if values.is_empty() && !has_file {
+        return Ok(is_true(field.get("required")).then(|| "This field is required.".to_owned()));
+    }
+    let field_type = field.get("type").and_then(Value::as_str).unwrap_or("text");
+    let pattern = property_str(field, "pattern")
+        .map(|pattern| compile_pattern(&pattern))
+        .transpose()?;
+    for value in values {
+        if let Some(message) = value_error(field, field_type, pattern.as_ref(), value)? {
+            return Ok(Some(message));
+        }
+    }
+    Ok(None)
+}
+
+/// Like in HTML, the pattern must match the whole value.
+/// HTML patterns are javascript regular expressions: the few features that the regex crate
+/// does not support, such as lookarounds and backreferences, are rejected with an error.
+fn compile_pattern(pattern: &str) -> anyhow::Result<regex::Regex> {
+    regex::Regex::new(&format!("^(?:{pattern})$")).with_context(|| {
+        format!(
+            "Invalid pattern: {pattern}. \
+            Lookarounds and backreferences are not supported in server-side validation."
+        )
+    })
+}
+
+fn value_error(
+    field: &Value,
+    field_type: &str,
+    pattern: Option<&regex::Regex>,
+    value: &str,
+) -> anyhow::Result<Option<String>> {
+    let type_error = match field_type {
+        "number" | "range" if value.parse::<f64>().is_err() => Some("Please enter a number."),
+        "email" if !is_email(value) => Some("Please enter an email address."),
+        "url" if !is_url(value) => Some("Please enter a URL."),
+        "date" if chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").is_err() => {
+            Some("Please enter a valid date.")
+        }
+        _ => None,
+    };
+    if let Some(message) = type_error {
+        return Ok(Some(message.to_owned()));
+    }
+    if let Some(min) = property_str(field, "min") {
+        if compare(field_type, value, &min).is_lt() {
+            return Ok(Some(format!(
+                "The value must be greater than or equal to {min}."
+            )));
+        }
+    }
+    if let Some(max) = property_str(field, "max") {
+        if compare(field_type, value, &max).is_gt() {
+            return Ok(Some(format!(
+                "The value must be less than or equal to {max}."
+            )));
+        }
+    }
+    let length = value.chars().count();
+    if let Some(minlength) = property_str(field, "minlength") {
+        if length
+            < minlength
+                .parse::<usize>()
+                .context("minlength must be a number")?
+        {
+            return Ok(Some(format!("Please use at least {minlength} characters.")));
+        }
+    }
+    if let Some(maxlength) = property_str(field, "maxlength") {
+        if length
+            > maxlength
+                .parse::<usize>()
+                .context("maxlength must be a number")?
+        {
+            return Ok(Some(format!("Please use at most {maxlength} characters.")));
+        }
+    }
+    if pattern.is_some_and(|pattern| !pattern.is_match(value)) {
+        return Ok(Some("Please match the requested format.".to_owned()));
+    }
+    Ok(None)
+}
+
+/// Numbers are compared as numbers, and dates and times as text, since they are in ISO format
+fn compare(field_type: &str, value: &str, bound: &str) -> std::cmp::Ordering {
+    match (field_type, value.parse::<f64>(), bound.parse::<f64>()) {
+        ("number" | "range", Ok(value), Ok(bound)) => value.total_cmp(&bound),
+        _ => value.cmp(bound),
+    }
+}
+
+fn is_email(value: &str) -> bool {
+    value.split_once('@').is_some_and(|(user, domain)| {
+        !user.is_empty()
+            && !domain.is_empty()
+            && !domain.contains('@')
+            && !value.contains(char::is_whitespace)
+    })
+}
+
+fn is_url(value: &str) -> bool {
+    value
+        .parse::<actix_web::http::Uri>()
+        .is_ok_and(|uri| uri.scheme().is_some())
+}
+
+fn property_str(field: &Value, name: &str) -> Option<String> {
+    match field.get(name)? {
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn is_true(value: Option<&Value>) -> bool {
+    match value {
+        Some(Value::Bool(b)) => *b,
+        Some(Value::Number(n)) => n.as_f64() != Some(0.),
+        Some(Value::String(s)) => !matches!(s.as_str(), "" | "0" | "false"),
+        _ => false,
+    }
+}
+
+impl FormValidation {
+    /// Fills a field of the `form` component with the value the user submitted,
+    /// and adds a `validation_error` property when the value was invalid
+    pub fn apply_to_field(&self, field: &mut Map<String, Value>) {
+        let Some(name) = field.get("name").and_then(Value::as_str) else {
+            return;
+        };
+        let name = field_key(name).to_owned();
+        if let Some(error) = self.errors.get(&name) {
+            field.insert("validation_error".into(), error.clone());
+        }
+        let field_type = field.get("type").and_then(Value::as_str).unwrap_or("text");
+        let submitted = self.values.get(&name);
+        match field_type {
+            // Passwords are never sent back to the browser
+            "password" | "file" => {}
+            "checkbox" | "radio" => {
+                let value = field.get("value").and_then(Value::as_str).unwrap_or("on");
+                let checked = match submitted {
+                    Some(Value::String(s)) => s == value,
+                    Some(Value::Array(a)) => a.iter().any(|v| v.as_str() == Some(value)),
+                    _ => false,
+                };
+                field.insert("checked".into(), Value::Bool(checked));
+            }
+            _ => {
+                if let Some(value @ Value::String(_)) = submitted {
+                    field.insert("value".into(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webserver::request_variables::param_map;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_fields() {
+        let fields = [
+            json!({"name": "name", "required": true}),
+            json!({"name": "age", "type": "number", "min": 18, "max": "120"}),
+            json!({"name": "email", "type": "email"}),
+            json!({"name": "zip", "pattern": "[0-9]{5}", "error_message": "Invalid zip code"}),
+            json!({"name": "bio", "type": "textarea", "maxlength": 5}),
+            json!({"name": "tags[]", "required": 1}),
+        ];
+        let submitted = |pairs: &[(&str, &str)]| {
+            param_map(
+                pairs
+                    .iter()
+                    .map(|(k, v)| ((*k).to_owned(), (*v).to_owned())),
+            )
+        };
+        let valid = submitted(&[
+            ("name", "Ophir"),
+            ("age", "30"),
+            ("email", "a@b.c"),
+            ("zip", "75001"),
+            ("bio", "héhé"),
+            ("tags[]", "x"),
+        ]);
+        let files = UploadedFiles::default();
+        assert_eq!(
+            validate_fields(&fields, &valid, &files).unwrap(),
+            Map::new()
+        );
+        let invalid = submitted(&[
+            ("name", ""),
+            ("age", "9"),
+            ("email", "a b@c"),
+            ("zip", "750011"),
+            ("bio", "too long"),
+        ]);
+        assert_eq!(
+            Value::Object(validate_fields(&fields, &invalid, &files).unwrap()),
+            json!({
+                "name": "This field is required.",
+                "age": "The value must be greater than or equal to 18.",
+                "email": "Please enter an email address.",
+                "zip": "Invalid zip code",
+                "bio": "Please use at most 5 characters.",
+                "tags": "This field is required.",
+            })
+        );
+        let not_a_number = submitted(&[("name", "x"), ("age", "x"), ("tags", "y")]);
+        assert_eq!(
+            Value::Object(validate_fields(&fields, &not_a_number, &files).unwrap()),
+            json!({"age": "Please enter a number."})
+        );
+        let lookahead = [json!({"name": "name", "pattern": "(?=.*[0-9]).+"})];
+        let err = validate_fields(&lookahead, &valid, &files).unwrap_err();
+        assert!(format!("{err:#}").contains("Lookarounds"), "{err:#}");
+    }
+
+    #[test]
+    fn test_apply_to_field() {
+        let validation = FormValidation {
+            errors: json!({"email": "Please enter an email address."})
+                .as_object()
+                .unwrap()
+                .clone(),
+            values: json!({"email": "x", "password": "secret", "colors": ["red"]})
+                .as_object()
+                .unwrap()
+                .clone(),
+        };
+        let apply = |field: Value| {
+            let mut field = field.as_object().unwrap().clone();
+            validation.apply_to_field(&mut field);
+            Value::Object(field)
+        };
+        assert_eq!(
+            apply(json!({"name": "email", "value": "default"})),
+            json!({"name": "email", "value": "x", "validation_error": "Please enter an email address."})
+        );
+        assert_eq!(
+            apply(json!({"name": "password", "type": "password"})),
+            json!({"name": "password", "type": "password"})
+        );
+        assert_eq!(
+            apply(
+                json!({"name": "colors[]", "type": "checkbox", "value": "blue", "checked": true})
+            ),
+            json!({"name": "colors[]", "type": "checkbox", "value": "blue", "checked": false})
+        );
+    }
+}
@@ -1,26 +1,29 @@
 use crate::render::{HeaderContext, PageContext, RenderContext};
 use crate::webserver::content_security_policy::ContentSecurityPolicy;
-use crate::webserver::database::execute_queries::stop_at_first_error;
+use crate::webserver::database::execute_queries::{discard_connection, stop_at_first_error};
 use crate::webserver::database::{execute_queries::stream_query_results_with_conn, DbItem};
+use crate::webserver::flash;
+use crate::webserver::form_validation::FormValidation;
 use crate::webserver::http_request_info::extract_request_info;
+use crate::webserver::response_cache::{PendingCachedResponse, ResponseCache};
 use crate::webserver::ErrorWithStatus;
 use crate::{app_config, AppConfig, AppState, ParsedSqlFile};
 use actix_web::dev::{fn_service, ServiceFactory, ServiceRequest};
 use actix_web::error::ErrorInternalServerError;
-use actix_web::http::header::{ContentType, Header, HttpDate, IfModifiedSince, LastModified};
 use actix_web::http::{header, StatusCode, Uri};
 use actix_web::web::PayloadConfig;
 use actix_web::{
-    dev::ServiceResponse, middleware, middleware::Logger, web, web::Bytes, App, HttpResponse,
-    HttpServer,
+    dev::ServiceResponse, middleware, middleware::Logger, web, web::Bytes, App, HttpRequest,
+    HttpResponse, HttpServer,
 };
 use actix_web::{HttpResponseBuilder, ResponseError};
 
+use super::error_with_status::is_not_found;
 use super::https::make_auto_rustls_config;
 use super::static_content;
+use super::static_files::serve_static_file;
 use actix_web::body::MessageBody;
 use anyhow::{bail, Context};
-use chrono::{DateTime, Utc};
 use futures_util::stream::Stream;
 use futures_util::StreamExt;
 use std::borrow::Cow;
@@ -29,7 +32,6 @@ use std::mem;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::SystemTime;
 use tokio::sync::mpsc;
 
 /// If the sending queue exceeds this number of outgoing messages, an error will be thrown
@@ -39,12 +41,25 @@ use tokio::sync::mpsc;
 pub struct ResponseWriter {
     buffer: Vec<u8>,
     response_bytes: mpsc::Sender<actix_web::Result<Bytes>>,
+    /// A copy of everything sent to the client, kept when the page is going to be cached
+    captured: Option<Vec<u8>>,
 }
 
 #[derive(Clone)]
 pub struct RequestContext {
     pub is_embedded: bool,
     pub content_security_policy: ContentSecurityPolicy,
+    /// The path under which the response can be cached by the `cache` component.
+    /// None when the response cannot be cached, for instance because the request is a POST.
+    pub cache_path: Option<String>,
+    pub if_none_match: Option<String>,
+    /// The errors and values of a form submission rejected by the `validate` component
+    pub form_validation: Option<FormValidation>,
+    /// The messages set by the `flash` component on the previous page, that the shell displays
+    pub flash_messages: Vec<serde_json::Value>,
+    /// Set when the page reads its flash messages with `sqlpage.flash_messages()`.
+    /// The shell does not display them again, and they are removed after the page.
+    pub flash_messages_read: bool,
 }
 
 impl ResponseWriter {
@@ -52,8 +67,26 @@ impl ResponseWriter {
         Self {
             response_bytes,
             buffer: Vec::new(),
+            captured: None,
         }
     }
+
+    fn capture(&mut self) {
+        self.captured = Some(Vec::new());
+    }
+
+    /// Takes the bytes that were sent to the client since [`Self::capture`] was called
+    fn take_captured(&mut self) -> Option<Vec<u8>> {
+        self.captured.take()
+    }
+
+    fn take_buffer(&mut self) -> Bytes {
+        let bytes = Bytes::from(mem::take(&mut self.buffer));
+        if let Some(captured) = &mut self.captured {
+            captured.extend_from_slice(&bytes);
+        }
+        bytes
+    }
     async fn close_with_error(&mut self, mut msg: String) {
         if !self.response_bytes.is_closed() {
             if let Err(e) = self.async_flush().await {
@@ -77,8 +110,9 @@ impl ResponseWriter {
             "Async flushing data to client: {}",
             String::from_utf8_lossy(&self.buffer)
         );
+        let bytes = self.take_buffer();
         self.response_bytes
-            .send(Ok(mem::take(&mut self.buffer).into()))
+            .send(Ok(bytes))
             .await
             .map_err(|err| {
                 use std::io::{Error, ErrorKind};
@@ -105,8 +139,9 @@ impl Write for ResponseWriter {
             "Flushing data to client: {}",
             String::from_utf8_lossy(&self.buffer)
         );
+        let bytes = self.take_buffer();
         self.response_bytes
-            .try_send(Ok(mem::take(&mut self.buffer).into()))
+            .try_send(Ok(bytes))
             .map_err(|e|
                 std::io::Error::new(
                     std::io::ErrorKind::WouldBlock,
@@ -124,25 +159,46 @@ impl Drop for ResponseWriter {
     }
 }
 
+/// Renders the database results to the client.
+/// Returns false if rendering stopped before all the SQL statements were executed.
+/// When `cache` is set, the page is stored in the response cache once it is completely rendered without errors.
 async fn stream_response(
     stream: impl Stream<Item = DbItem>,
     mut renderer: RenderContext<ResponseWriter>,
-) {
+    cache: Option<(&ResponseCache, PendingCachedResponse)>,
+) -> bool {
+    let mut has_error = false;
     let mut stream = Box::pin(stream);
 
     if let Err(e) = &renderer.writer.async_flush().await {
         log::error!("Unable to flush initial data to client: {e}");
-        return;
+        return false;
     }
 
-    while let Some(item) = stream.next().await {
+    loop {
+        let item = tokio::select! {
+            item = stream.next() => item,
+            () = renderer.writer.response_bytes.closed() => {
+                log::info!("The client disconnected. Stopping the execution of the SQL file.");
+                return false;
+            }
+        };
+        let Some(item) = item else { break };
         log::trace!("Received item from database: {item:?}");
         let render_result = match item {
             DbItem::FinishedQuery => renderer.finish_query().await,
             DbItem::Row(row) => renderer.handle_row(&row).await,
-            DbItem::Error(e) => renderer.handle_error(&e).await,
+            DbItem::ComponentVariables(variables) => {
+                renderer.set_component_variables(variables);
+                Ok(())
+            }
+            DbItem::Error(e) => {
+                has_error = true;
+                renderer.handle_error(&e).await
+            }
         };
         if let Err(e) = render_result {
+            has_error = true;
             if let Err(nested_err) = renderer.handle_error(&e).await {
                 renderer
                     .close()
@@ -154,7 +210,7 @@ async fn stream_response(
                     \nRoot error: {e}\n
                     \nNested error: {nested_err}"
                 );
-                return;
+                return false;
             }
         }
         if let Err(e) = &renderer.writer.async_flush().await {
@@ -162,14 +218,21 @@ async fn stream_response(
                 "Stopping rendering early because we were unable to flush data to client: {e:#}"
             );
             // If we cannot write to the client anymore, there is nothing we can do, so we just stop rendering
-            return;
+            return false;
         }
     }
-    if let Err(e) = &renderer.close().await.async_flush().await {
+    let mut writer = renderer.close().await;
+    if let Err(e) = &writer.async_flush().await {
         log::error!("Unable to flush data to client after rendering the page end: {e}");
-        return;
+        return true;
+    }
+    if let (Some((response_cache, pending)), Some(body), false) =
+        (cache, writer.take_captured(), has_error)
+    {
+        response_cache.insert(pending, body);
     }
     log::debug!("Successfully finished rendering the page");
+    true
 }
 
 async fn build_response_header_and_stream<S: Stream<Item = DbItem>>(
@@ -189,6 +252,12 @@ async fn build_response_header_and_stream<S: Stream<Item = DbItem>>(
                 log::debug!("finished query");
                 continue;
             }
+            DbItem::ComponentVariables(_) => {
+                log::debug!(
+                    "Ignoring the component variables of a query that did not display anything"
+                );
+                continue;
+            }
             DbItem::Error(source_err)
                 if matches!(
                     source_err.downcast_ref(),
@@ -205,14 +274,24 @@ async fn build_response_header_and_stream<S: Stream<Item = DbItem>>(
             }
             PageContext::Body {
                 mut http_response,
-                renderer,
+                mut renderer,
+                cache,
             } => {
                 let body_stream = tokio_stream::wrappers::ReceiverStream::new(receiver);
                 let http_response = http_response.streaming(body_stream);
+                let cache = cache.map(|settings| {
+                    renderer.writer.capture();
+                    PendingCachedResponse {
+                        settings,
+                        status: http_response.status(),
+                        headers: http_response.headers().clone(),
+                    }
+                });
                 return Ok(ResponseWithWriter::RenderStream {
                     http_response,
                     renderer,
                     database_entries_stream: stream,
+                    cache,
                 });
             }
             PageContext::Close(http_response) => {
@@ -230,6 +309,7 @@ enum ResponseWithWriter<S> {
         http_response: HttpResponse,
         renderer: RenderContext<ResponseWriter>,
         database_entries_stream: Pin<Box<S>>,
+        cache: Option<PendingCachedResponse>,
     },
     FinishedResponse {
         http_response: HttpResponse,
@@ -253,44 +333,88 @@ async fn render_sql(
 
     let (resp_send, resp_recv) = tokio::sync::oneshot::channel::<HttpResponse>();
     actix_web::rt::spawn(async move {
+        let flash_cookie = req_param.cookies.get(flash::FLASH_COOKIE);
+        // Pages that read their flash messages with sqlpage.flash_messages() display them themselves
+        let flash_messages_read = flash_cookie.is_some() && sql_file.reads_flash_messages();
         let request_context = RequestContext {
             is_embedded: req_param.get_variables.contains_key("_sqlpage_embed"),
             content_security_policy: ContentSecurityPolicy::default(),
+            cache_path: (req_param.method == actix_web::http::Method::GET)
+                .then(|| req_param.path.clone()),
+            if_none_match: req_param
+                .headers
+                .get("if-none-match")
+                .map(|v| v.as_json_str().into_owned()),
+            form_validation: None,
+            flash_messages: flash_cookie
+                .filter(|_| !flash_messages_read)
+                .map(|cookie| flash::decode(&cookie.as_json_str(), &app_state.cookie_key))
+                .unwrap_or_default(),
+            flash_messages_read,
         };
         let mut conn = None;
         let database_entries_stream =
             stream_query_results_with_conn(&sql_file, &mut req_param, &mut conn);
         let database_entries_stream = stop_at_first_error(database_entries_stream);
-        let response_with_writer = build_response_header_and_stream(
-            Arc::clone(&app_state),
+        let all_statements_executed = Box::pin(send_response(
+            app_state,
             database_entries_stream,
             request_context,
-        )
+            resp_send,
+        ))
         .await;
-        match response_with_writer {
-            Ok(ResponseWithWriter::RenderStream {
-                http_response,
-                renderer,
-                database_entries_stream,
-            }) => {
-                resp_send
-                    .send(http_response)
-                    .unwrap_or_else(|e| log::error!("could not send headers {e:?}"));
-                stream_response(database_entries_stream, renderer).await;
-            }
-            Ok(ResponseWithWriter::FinishedResponse { http_response }) => {
-                resp_send
-                    .send(http_response)
-                    .unwrap_or_else(|e| log::error!("could not send headers {e:?}"));
-            }
-            Err(err) => {
-                send_anyhow_error(&err, resp_send, app_state.config.environment);
-            }
+        if !all_statements_executed {
+            discard_connection(&mut conn);
         }
     });
     resp_recv.await.map_err(ErrorInternalServerError)
 }
 
+/// Sends the response headers, then streams the response body.
+/// Returns false if the execution of the SQL file stopped early because the client disconnected.
+async fn send_response(
+    app_state: Arc<AppState>,
+    database_entries_stream: impl Stream<Item = DbItem>,
+    request_context: RequestContext,
+    mut resp_send: tokio::sync::oneshot::Sender<HttpResponse>,
+) -> bool {
+    let response_with_writer = tokio::select! {
+        response = build_response_header_and_stream(
+            Arc::clone(&app_state),
+            database_entries_stream,
+            request_context,
+        ) => response,
+        () = resp_send.closed() => {
+            log::info!("The client disconnected before the response headers were sent. Stopping the execution of the SQL file.");
+            return false;
+        }
+    };
+    match response_with_writer {
+        Ok(ResponseWithWriter::RenderStream {
+            http_response,
+            renderer,
+            database_entries_stream,
+            cache,
+        }) => {
+            resp_send
+                .send(http_response)
+                .unwrap_or_else(|e| log::error!("could not send headers {e:?}"));
+            let cache = cache.map(|pending| (&app_state.response_cache, pending));
+            stream_response(database_entries_stream, renderer, cache).await
+        }
+        Ok(ResponseWithWriter::FinishedResponse { http_response }) => {
+            resp_send
+                .send(http_response)
+                .unwrap_or_else(|e| log::error!("could not send headers {e:?}"));
+            true
+        }
+        Err(err) => {
+            send_anyhow_error(&err, resp_send, app_state.config.environment);
+            true
+        }
+    }
+}
+
 fn send_anyhow_error(
     e: &anyhow::Error,
     resp_send: tokio::sync::oneshot::Sender<HttpResponse>,
@@ -331,6 +455,8 @@ fn send_anyhow_error(
 pub enum SingleOrVec {
     Single(String),
     Vec(Vec<String>),
+    /// A value set with `SET` that is not a string, and keeps its type
+    Json(serde_json::Value),
 }
 
 impl SingleOrVec {
@@ -348,6 +474,21 @@ impl SingleOrVec {
         match self {
             SingleOrVec::Single(x) => vec![mem::take(x)],
             SingleOrVec::Vec(v) => mem::take(v),
+            SingleOrVec::Json(v) => json_list_values(v)
+                .into_iter()
+                .map(Cow::into_owned)
+                .collect(),
+        }
+    }
+
+    /// The values to use when the variable is expanded into a list,
+    /// such as in `x IN ($ids)`
+    #[must_use]
+    pub fn values(&self) -> Vec<Cow<'_, str>> {
+        match self {
+            SingleOrVec::Single(x) => vec![Cow::Borrowed(x)],
+            SingleOrVec::Vec(v) => v.iter().map(|x| Cow::Borrowed(x.as_str())).collect(),
+            SingleOrVec::Json(v) => json_list_values(v),
         }
     }
 
@@ -356,10 +497,30 @@ impl SingleOrVec {
         match self {
             SingleOrVec::Single(x) => Cow::Borrowed(x),
             SingleOrVec::Vec(v) => Cow::Owned(serde_json::to_string(v).unwrap()),
+            SingleOrVec::Json(v) => json_as_str(v),
         }
     }
 }
 
+fn json_as_str(value: &serde_json::Value) -> Cow<'_, str> {
+    match value {
+        serde_json::Value::String(s) => Cow::Borrowed(s),
+        other => Cow::Owned(other.to_string()),
+    }
+}
+
+/// The elements of a JSON array, or the value itself if it is not an array. Nulls are skipped.
+fn json_list_values(value: &serde_json::Value) -> Vec<Cow<'_, str>> {
+    match value {
+        serde_json::Value::Array(values) => values
+            .iter()
+            .filter(|v| !v.is_null())
+            .map(json_as_str)
+            .collect(),
+        other => vec![json_as_str(other)],
+    }
+}
+
 /// Resolves the path in a query to the path to a local SQL file if there is one that matches
 fn path_to_sql_file(path: &str) -> Option<PathBuf> {
     let mut path = PathBuf::from(path);
@@ -400,37 +561,23 @@ fn anyhow_err_to_actix(e: anyhow::Error) -> actix_web::Error {
 async fn serve_file(
     path: &str,
     state: &AppState,
-    if_modified_since: Option<IfModifiedSince>,
+    request: &HttpRequest,
 ) -> actix_web::Result<HttpResponse> {
     let path = path.strip_prefix(&state.config.site_prefix).unwrap_or(path);
-    if let Some(IfModifiedSince(date)) = if_modified_since {
-        let since = DateTime::<Utc>::from(SystemTime::from(date));
-        let modified = state
-            .file_system
-            .modified_since(state, path.as_ref(), since, false)
-            .await
-            .with_context(|| format!("Unable to get modification time of file {path:?}"))
-            .map_err(anyhow_err_to_actix)?;
-        if !modified {
-            return Ok(HttpResponse::NotModified().finish());
-        }
+    match serve_static_file(state, path, request).await {
+        // Files that are neither in the web root nor in the database may be in the bucket
+        Err(e) if is_not_found(&e) => match state.file_system.object_storage() {
+            Some(storage) if path.starts_with(&state.config.s3_serve_prefix) => storage
+                .serve(&state.config, path, request.headers())
+                .await
+                .with_context(|| format!("Unable to serve file {path:?} from the bucket"))
+                .map_err(anyhow_err_to_actix),
+            _ => Err(anyhow_err_to_actix(e)),
+        },
+        result => result
+            .with_context(|| format!("Unable to serve file {path:?}"))
+            .map_err(anyhow_err_to_actix),
     }
-    state
-        .file_system
-        .read_file(state, path.as_ref(), false)
-        .await
-        .with_context(|| format!("Unable to read file {path:?}"))
-        .map_err(anyhow_err_to_actix)
-        .map(|b| {
-            HttpResponse::Ok()
-                .insert_header(
-                    mime_guess::from_path(path)
-                        .first()
-                        .map_or_else(ContentType::octet_stream, ContentType),
-                )
-                .insert_header(LastModified(HttpDate::from(SystemTime::now())))
-                .body(b)
-        })
 }
 
 /// Fallback handler for when a file could not be served
@@ -509,9 +656,7 @@ pub async fn main_handler(
         log::debug!("Serving file: {:?}", path);
         let app_state = service_request.extract::<web::Data<AppState>>().await?;
         let path = req_path(&service_request);
-        let if_modified_since = IfModifiedSince::parse(&service_request).ok();
-
-        serve_file(&path, &app_state, if_modified_since).await
+        serve_file(&path, &app_state, service_request.request()).await
     };
 
     // On 404/NOT_FOUND error, fall back to `404.sql` handler if it exists
@@ -684,6 +829,9 @@ pub async fn run_server(config: &AppConfig, state: AppState) -> anyhow::Result<(
 
     // We are done, we can close the database connection
     final_state.db.close().await?;
+    for database in final_state.databases.values() {
+        database.close().await?;
+    }
     Ok(())
 }
 
@@ -24,20 +24,85 @@ use tokio_stream::StreamExt;
 use super::request_variables::param_map;
 use super::request_variables::ParamMap;
 
+/// The files uploaded in each form field, in the order in which they were sent.
+/// A field with the `multiple` attribute can contain several files:
+/// `get` and indexing return the last one, and `get_all` returns all of them.
+#[derive(Debug, Default)]
+pub struct UploadedFiles(HashMap<String, Vec<TempFile>>);
+
+impl UploadedFiles {
+    /// The last file uploaded in the given field
+    #[must_use]
+    pub fn get(&self, field_name: &str) -> Option<&TempFile> {
+        self.0.get(field_name)?.last()
+    }
+
+    /// All the files uploaded in the given field
+    #[must_use]
+    pub fn get_all(&self, field_name: &str) -> &[TempFile] {
+        self.0.get(field_name).map_or(&[], Vec::as_slice)
+    }
+
+    #[must_use]
+    pub fn contains_key(&self, field_name: &str) -> bool {
+        self.0.contains_key(field_name)
+    }
+
+    /// The number of fields that contain files
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// All the uploaded files, of all the fields
+    pub fn files(&self) -> impl Iterator<Item = &TempFile> {
+        self.0.values().flatten()
+    }
+}
+
+impl FromIterator<(String, TempFile)> for UploadedFiles {
+    fn from_iter<I: IntoIterator<Item = (String, TempFile)>>(files: I) -> Self {
+        let mut map: HashMap<String, Vec<TempFile>> = HashMap::new();
+        for (field_name, file) in files {
+            map.entry(field_name).or_default().push(file);
+        }
+        Self(map)
+    }
+}
+
+impl std::ops::Index<&str> for UploadedFiles {
+    type Output = TempFile;
+
+    fn index(&self, field_name: &str) -> &TempFile {
+        self.get(field_name)
+            .unwrap_or_else(|| panic!("no file uploaded in {field_name:?}"))
+    }
+}
+
 #[derive(Debug)]
 pub struct RequestInfo {
     pub method: actix_web::http::Method,
     pub path: String,
+    /// The query string of the URL, without the variables that the page `SET`
+    pub query_string: String,
     pub protocol: String,
     pub get_variables: ParamMap,
     pub post_variables: ParamMap,
-    pub uploaded_files: Rc<HashMap<String, TempFile>>,
+    pub uploaded_files: Rc<UploadedFiles>,
     pub headers: ParamMap,
     pub client_ip: Option<IpAddr>,
     pub cookies: ParamMap,
     pub basic_auth: Option<Basic>,
     pub app_state: Arc<AppState>,
     pub clone_depth: u8,
+    /// Set when the form submitted with the request was rejected by a `validate` component,
+    /// and the request was forwarded back to the form
+    pub validation_failed: bool,
 }
 
 impl RequestInfo {
@@ -46,6 +111,7 @@ impl RequestInfo {
         Self {
             method: self.method.clone(),
             path: self.path.clone(),
+            query_string: self.query_string.clone(),
             protocol: self.protocol.clone(),
             get_variables: ParamMap::new(),
             post_variables: ParamMap::new(),
@@ -56,6 +122,7 @@ impl RequestInfo {
             basic_auth: self.basic_auth.clone(),
             app_state: self.app_state.clone(),
             clone_depth: self.clone_depth + 1,
+            validation_failed: self.validation_failed,
         }
     }
 }
@@ -102,16 +169,18 @@ pub(crate) async fn extract_request_info(
     Ok(RequestInfo {
         method,
         path: req.path().to_string(),
+        query_string: req.query_string().to_string(),
         headers: param_map(headers),
         get_variables: param_map(get_variables),
         post_variables: param_map(post_variables),
-        uploaded_files: Rc::new(HashMap::from_iter(uploaded_files)),
+        uploaded_files: Rc::new(UploadedFiles::from_iter(uploaded_files)),
         client_ip,
         cookies: param_map(cookies),
         basic_auth,
         app_state,
         protocol,
         clone_depth: 0,
+        validation_failed: false,
     })
 }
 
@@ -342,4 +411,42 @@ mod test {
         assert_eq!(std::fs::read(&my_upload.file).unwrap(), b"Hello World");
         assert_eq!(request_info.get_variables.len(), 0);
     }
+
+    #[actix_web::test]
+    async fn test_extract_multipart_multiple_files() {
+        let config =
+            serde_json::from_str::<AppConfig>(r#"{"listen_on": "localhost:1234"}"#).unwrap();
+        let mut service_request = TestRequest::get()
+            .insert_header(("content-type", "multipart/form-data;boundary=xxx"))
+            .set_payload(
+                "--xxx\r\n\
+                Content-Disposition: form-data; name=\"photos[]\"; filename=\"a.png\"\r\n\
+                Content-Type: image/png\r\n\
+                \r\n\
+                A\r\n\
+                --xxx\r\n\
+                Content-Disposition: form-data; name=\"photos[]\"; filename=\"b.png\"\r\n\
+                Content-Type: image/png\r\n\
+                \r\n\
+                B\r\n\
+                --xxx--\r\n",
+            )
+            .to_srv_request();
+        let app_data = Arc::new(AppState::init(&config).await.unwrap());
+        let request_info = extract_request_info(&mut service_request, app_data)
+            .await
+            .unwrap();
+        assert_eq!(request_info.uploaded_files.len(), 1);
+        assert_eq!(
+            request_info.uploaded_files["photos[]"].file_name.as_deref(),
+            Some("b.png")
+        );
+        let photo_names: Vec<_> = request_info
+            .uploaded_files
+            .get_all("photos[]")
+            .iter()
+            .map(|f| f.file_name.as_deref().unwrap())
+            .collect();
+        assert_eq!(photo_names, ["a.png", "b.png"]);
+    }
 }
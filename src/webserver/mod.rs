@@ -1,10 +1,16 @@
+mod compression;
 mod content_security_policy;
 pub mod database;
+pub mod download;
 pub mod error_with_status;
+pub mod flash;
+pub mod form_validation;
 pub mod http;
+pub(crate) mod http_client;
 pub mod http_request_info;
 mod https;
 pub mod request_variables;
+pub mod response_cache;
 
 pub use database::Database;
 pub use error_with_status::ErrorWithStatus;
@@ -12,3 +18,4 @@ pub use error_with_status::ErrorWithStatus;
 pub use database::make_placeholder;
 pub use database::migrations::apply;
 mod static_content;
+mod static_files;
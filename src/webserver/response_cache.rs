@@ -0,0 +1,372 @@
+//! Rendered pages stored in memory by the `cache` component,
+//! and served without executing the rest of the SQL file until they expire.
+
+use super::content_security_policy::ContentSecurityPolicy;
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::HttpResponse;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub struct ResponseCache {
+    entries: Mutex<Entries>,
+    max_entries: usize,
+    /// Maximum total size of the cached bodies, in bytes
+    max_size: usize,
+}
+
+#[derive(Default)]
+struct Entries {
+    responses: HashMap<String, Entry>,
+    /// Total size of the cached bodies, in bytes
+    size: usize,
+    /// Incremented on each access, to find the least recently used responses
+    clock: u64,
+}
+
+struct Entry {
+    response: Arc<CachedResponse>,
+    last_used: u64,
+}
+
+/// How a page is cached, as set by the `cache` component
+pub struct CacheSettings {
+    pub key: String,
+    pub max_age: Duration,
+    /// Whether shared caches, such as proxies and CDNs, may store the page and send it to other users.
+    /// Otherwise, only the browser of the user may store it.
+    pub public: bool,
+    /// Identifies the version of the page that is being rendered, and sent to the client before it is rendered
+    pub etag: String,
+    /// The nonce of the content security policy of the page that is being rendered
+    pub nonce: u64,
+}
+
+impl CacheSettings {
+    /// The value of the `Cache-Control` header of the page, when it expires in `max_age`
+    #[must_use]
+    pub fn cache_control(public: bool, max_age: Duration) -> String {
+        let visibility = if public { "public" } else { "private" };
+        format!("{visibility}, max-age={}", max_age.as_secs())
+    }
+
+    /// A new weak `ETag`: the cached versions of a page only differ from each other by their nonce
+    #[must_use]
+    pub fn new_etag() -> String {
+        format!("W/\"{:x}\"", rand::random::<u64>())
+    }
+}
+
+/// A response that is being rendered, and will be stored in the cache when it is complete
+pub struct PendingCachedResponse {
+    pub settings: CacheSettings,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+}
+
+pub struct CachedResponse {
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    /// The body, split where the content security policy nonce appears,
+    /// so that each visitor gets a new nonce
+    body_parts: Vec<Bytes>,
+    /// Whether the page was sent with the default content security policy, that contains the nonce
+    has_nonce_csp: bool,
+    size: usize,
+    etag: String,
+    public: bool,
+    expires_at: Instant,
+}
+
+impl ResponseCache {
+    #[must_use]
+    pub fn new(max_entries: usize, max_size: usize) -> Self {
+        Self {
+            entries: Mutex::default(),
+            max_entries,
+            max_size,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<CachedResponse>> {
+        let mut entries = self.entries.lock().expect("poisoned response cache");
+        entries.clock += 1;
+        let clock = entries.clock;
+        match entries.responses.get_mut(key) {
+            Some(entry) if entry.response.expires_at > Instant::now() => {
+                entry.last_used = clock;
+                Some(Arc::clone(&entry.response))
+            }
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, pending: PendingCachedResponse, body: Vec<u8>) {
+        let now = Instant::now();
+        let nonce = pending.settings.nonce.to_string();
+        let is_nonce_csp = |name: &HeaderName, value: &HeaderValue| {
+            name == header::CONTENT_SECURITY_POLICY
+                && value.to_str().is_ok_and(|v| v.contains(&nonce))
+        };
+        let has_nonce_csp = pending
+            .headers
+            .iter()
+            .any(|(name, value)| is_nonce_csp(name, value));
+        let entry = CachedResponse {
+            status: pending.status,
+            // Cookies are specific to a user, and must never be sent to other users.
+            // The content security policy is sent again with a new nonce.
+            headers: pending
+                .headers
+                .into_iter()
+                .filter(|(name, value)| name != header::SET_COOKIE && !is_nonce_csp(name, value))
+                .collect(),
+            size: body.len(),
+            body_parts: split_at(&Bytes::from(body), nonce.as_bytes()),
+            has_nonce_csp,
+            etag: pending.settings.etag,
+            public: pending.settings.public,
+            expires_at: now + pending.settings.max_age,
+        };
+        let key = pending.settings.key;
+        log::debug!("Storing the response to {key:?} in the cache");
+        let mut entries = self.entries.lock().expect("poisoned response cache");
+        entries.remove(&key);
+        entries.clock += 1;
+        entries.size += entry.size;
+        let last_used = entries.clock;
+        entries.responses.insert(
+            key,
+            Entry {
+                response: Arc::new(entry),
+                last_used,
+            },
+        );
+        if entries.responses.len() > self.max_entries || entries.size > self.max_size {
+            entries.evict(now, self.max_entries, self.max_size);
+        }
+    }
+
+    /// Removes the cached responses of a path, or all of them.
+    /// Returns the number of removed responses.
+    pub fn purge(&self, path: Option<&str>) -> usize {
+        let mut entries = self.entries.lock().expect("poisoned response cache");
+        let count = entries.responses.len();
+        entries.retain(|key, _| path.is_some_and(|path| key_path(key) != path));
+        count - entries.responses.len()
+    }
+}
+
+impl Entries {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.responses.remove(key) {
+            self.size -= entry.response.size;
+        }
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&str, &CachedResponse) -> bool) {
+        let mut removed_size = 0;
+        self.responses.retain(|key, entry| {
+            let kept = keep(key, &entry.response);
+            if !kept {
+                removed_size += entry.response.size;
+            }
+            kept
+        });
+        self.size -= removed_size;
+    }
+
+    /// Removes the expired responses, then the least recently used ones,
+    /// until the cache is 10% under its limits
+    fn evict(&mut self, now: Instant, max_entries: usize, max_size: usize) {
+        self.retain(|_, response| response.expires_at > now);
+        let (target_entries, target_size) =
+            (max_entries - max_entries / 10, max_size - max_size / 10);
+        let mut by_last_use: Vec<(u64, String)> = self
+            .responses
+            .iter()
+            .map(|(key, entry)| (entry.last_used, key.clone()))
+            .collect();
+        by_last_use.sort_unstable();
+        for (_, key) in by_last_use {
+            if self.responses.len() <= target_entries && self.size <= target_size {
+                break;
+            }
+            log::debug!("Evicting {key:?} from the full response cache");
+            self.remove(&key);
+        }
+    }
+}
+
+/// The responses of a page are stored separately for each value of `vary`
+#[must_use]
+pub fn cache_key(path: &str, vary: &serde_json::Value) -> String {
+    format!("{path}\n{vary}")
+}
+
+fn key_path(key: &str) -> &str {
+    key.split_once('\n').map_or(key, |(path, _)| path)
+}
+
+/// Splits `body` at each occurrence of `separator`
+fn split_at(body: &Bytes, separator: &[u8]) -> Vec<Bytes> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i + separator.len() <= body.len() {
+        if body[i..].starts_with(separator) {
+            parts.push(body.slice(start..i));
+            i += separator.len();
+            start = i;
+        } else {
+            i += 1;
+        }
+    }
+    parts.push(body.slice(start..));
+    parts
+}
+
+impl CachedResponse {
+    /// Answers with an empty `304 Not Modified` when the client already has this version.
+    /// Otherwise, sends the page with the nonce of the content security policy of the current request.
+    #[must_use]
+    pub fn response(
+        &self,
+        if_none_match: Option<&str>,
+        csp: &ContentSecurityPolicy,
+    ) -> HttpResponse {
+        let remaining = self.expires_at.saturating_duration_since(Instant::now());
+        let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+        let not_modified = if_none_match.is_some_and(|tags| {
+            tags.split(',')
+                .any(|tag| weak(tag) == weak(&self.etag) || tag.trim() == "*")
+        });
+        let mut response = HttpResponse::build(if not_modified {
+            StatusCode::NOT_MODIFIED
+        } else {
+            self.status
+        });
+        for (name, value) in &self.headers {
+            response.append_header((name.clone(), value.clone()));
+        }
+        response
+            .insert_header((header::ETAG, self.etag.as_str()))
+            .insert_header((
+                header::CACHE_CONTROL,
+                CacheSettings::cache_control(self.public, remaining),
+            ));
+        if not_modified {
+            // The client keeps the content security policy that matches the nonce in its copy of the page
+            return response.finish();
+        }
+        if self.has_nonce_csp {
+            response.insert_header(csp);
+        }
+        let nonce = csp.nonce.to_string();
+        let mut body = BytesMut::with_capacity(self.size);
+        for (i, part) in self.body_parts.iter().enumerate() {
+            if i > 0 {
+                body.extend_from_slice(nonce.as_bytes());
+            }
+            body.extend_from_slice(part);
+        }
+        response.body(body.freeze())
+    }
+}
+
+#[cfg(test)]
+fn pending(key: String) -> PendingCachedResponse {
+    PendingCachedResponse {
+        settings: CacheSettings {
+            key,
+            max_age: Duration::from_secs(60),
+            public: false,
+            etag: CacheSettings::new_etag(),
+            nonce: 123,
+        },
+        status: StatusCode::OK,
+        headers: HeaderMap::new(),
+    }
+}
+
+#[test]
+fn test_response_cache() {
+    let cache = ResponseCache::new(100, 1024);
+    let fr = cache_key("/a.sql", &serde_json::json!("fr"));
+    let en = cache_key("/a.sql", &serde_json::json!("en"));
+    cache.insert(pending(fr.clone()), b"bonjour".to_vec());
+    cache.insert(pending(en.clone()), b"hello".to_vec());
+    cache.insert(
+        pending(cache_key("/b.sql", &serde_json::Value::Null)),
+        vec![],
+    );
+    let cached = cache.get(&fr).unwrap();
+    assert_eq!(cached.body_parts, vec![Bytes::from("bonjour")]);
+    let csp = ContentSecurityPolicy::default();
+    let not_modified = cached.response(Some(&cached.etag), &csp);
+    assert_eq!(not_modified.status(), StatusCode::NOT_MODIFIED);
+    let cache_control = not_modified.headers().get(header::CACHE_CONTROL).unwrap();
+    assert!(cache_control.to_str().unwrap().starts_with("private, "));
+    assert_eq!(
+        cached.response(Some("\"x\""), &csp).status(),
+        StatusCode::OK
+    );
+    assert_eq!(cache.purge(Some("/a.sql")), 2);
+    assert!(cache.get(&en).is_none());
+    assert_eq!(cache.purge(None), 1);
+}
+
+#[test]
+fn test_response_cache_eviction() {
+    let cache = ResponseCache::new(10, 100);
+    for i in 0..10 {
+        cache.insert(pending(format!("/{i}.sql")), vec![b'x'; 5]);
+    }
+    assert!(cache.get("/0.sql").is_some());
+    // Too many responses: the least recently used ones are evicted
+    cache.insert(pending("/10.sql".into()), vec![b'x'; 5]);
+    assert!(cache.get("/0.sql").is_some());
+    assert!(cache.get("/1.sql").is_none());
+    assert!(cache.get("/10.sql").is_some());
+    // Too large
+    cache.insert(pending("/big.sql".into()), vec![b'x'; 80]);
+    let entries = cache.entries.lock().unwrap();
+    assert!(entries.size <= 90, "{}", entries.size);
+    assert!(entries.responses.contains_key("/big.sql"));
+}
+
+#[actix_web::test]
+async fn test_response_cache_renews_the_nonce() {
+    let cache = ResponseCache::new(100, 1024);
+    let mut pending = pending("/a.sql".into());
+    pending.headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("script-src 'self' 'nonce-123'"),
+    );
+    cache.insert(pending, b"<script nonce=\"123\"></script>".to_vec());
+    let cached = cache.get("/a.sql").unwrap();
+    let csp = ContentSecurityPolicy { nonce: 456 };
+    let response = cached.response(None, &csp);
+    assert_eq!(
+        response
+            .headers()
+            .get(header::CONTENT_SECURITY_POLICY)
+            .unwrap(),
+        "script-src 'self' 'nonce-456'"
+    );
+    let body = actix_web::body::to_bytes(response.into_body())
+        .await
+        .unwrap();
+    assert_eq!(body, "<script nonce=\"456\"></script>");
+    let not_modified = cached.response(Some(&cached.etag), &csp);
+    assert!(not_modified
+        .headers()
+        .get(header::CONTENT_SECURITY_POLICY)
+        .is_none());
+}
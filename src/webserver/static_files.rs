@@ -0,0 +1,239 @@
+//! Serves the files of the web root that are not SQL files: images, videos, downloads, ...
+//! Local files are streamed from the disk. Responses carry the modification time and an `ETag`
+//! of the file, so that browsers can revalidate their cache, and support range requests,
+//! so that videos can be seeked and large downloads resumed.
+
+use super::compression::{accepted_encodings, add_vary_accept_encoding};
+use crate::filesystem::OpenedFile;
+use crate::AppState;
+use actix_web::body::SizedStream;
+use actix_web::http::header::{
+    self, ByteRangeSpec, ContentEncoding, ContentRange, ContentRangeSpec, ContentType, ETag,
+    EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch, IfRange, LastModified, Range,
+};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+pub(crate) async fn serve_static_file(
+    app_state: &AppState,
+    path: &str,
+    request: &HttpRequest,
+) -> anyhow::Result<HttpResponse> {
+    let file_system = &app_state.file_system;
+    let precompressed = open_precompressed(app_state, path, request).await?;
+    let (file, last_modified, encoding) =
+        if let Some((file, last_modified, encoding)) = precompressed {
+            (file, last_modified, Some(encoding))
+        } else if let Some((file, last_modified)) = file_system
+            .open_local_file_with_date(app_state, Path::new(path))
+            .await?
+        {
+            (file, last_modified, None)
+        } else {
+            // Files stored in the database are only downloaded when the client does not have them already
+            if is_conditional(request) {
+                let (len, last_modified) = file_system
+                    .database_file_metadata(app_state, Path::new(path))
+                    .await?;
+                let (etag, last_modified) = validators(len, last_modified);
+                if !is_modified(request, &etag, last_modified) {
+                    return Ok(not_modified(etag, last_modified, None));
+                }
+            }
+            let (file, last_modified) = file_system
+                .open_database_file_with_date(app_state, Path::new(path))
+                .await?;
+            (file, last_modified, None)
+        };
+    let (etag, last_modified) = validators(file.len(), last_modified);
+    if !is_modified(request, &etag, last_modified) {
+        return Ok(not_modified(etag, last_modified, encoding));
+    }
+    let mut response = response_with_validators(etag.clone(), last_modified, encoding);
+    response.insert_header(
+        mime_guess::from_path(path)
+            .first()
+            .map_or_else(ContentType::octet_stream, ContentType),
+    );
+
+    let len = file.len();
+    match requested_range(request, &etag, last_modified) {
+        None => send(response, file, 0, len).await,
+        Some(range) => match range.to_satisfiable_range(len) {
+            Some((start, end)) => {
+                log::debug!("Sending bytes {start}-{end} of {path:?}");
+                response
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                        range: Some((start, end)),
+                        instance_length: Some(len),
+                    }));
+                if encoding.is_none() {
+                    // compressing a part of the file would make the range meaningless
+                    response.insert_header(ContentEncoding::Identity);
+                }
+                send(response, file, start, end + 1 - start).await
+            }
+            None => Ok(response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(len),
+                }))
+                .finish()),
+        },
+    }
+    .map(vary_accept_encoding)
+}
+
+/// Opens the `.br` or `.gz` version of the file, if the client accepts it and it exists,
+/// so that large assets don't have to be compressed again on every request.
+/// Precompressed files are only looked for on the local disk:
+/// looking for them in the database would cost a query per missing file on every request.
+async fn open_precompressed(
+    app_state: &AppState,
+    path: &str,
+    request: &HttpRequest,
+) -> anyhow::Result<Option<(OpenedFile, Option<DateTime<Utc>>, ContentEncoding)>> {
+    let extension = Path::new(path).extension().unwrap_or_default();
+    if extension.eq_ignore_ascii_case("br") || extension.eq_ignore_ascii_case("gz") {
+        return Ok(None);
+    }
+    for encoding in accepted_encodings(request, &app_state.config) {
+        let extension = if encoding == ContentEncoding::Brotli {
+            "br"
+        } else {
+            "gz"
+        };
+        let compressed_path = format!("{path}.{extension}");
+        if let Some((file, last_modified)) = app_state
+            .file_system
+            .open_local_file_with_date(app_state, Path::new(&compressed_path))
+            .await?
+        {
+            log::debug!("Serving the precompressed {compressed_path:?}");
+            return Ok(Some((file, last_modified, encoding)));
+        }
+    }
+    Ok(None)
+}
+
+fn vary_accept_encoding(mut response: HttpResponse) -> HttpResponse {
+    add_vary_accept_encoding(response.head_mut());
+    response
+}
+
+/// The `ETag` and `Last-Modified` values of a file.
+/// Files are identified by their size and modification time, so that they don't have to be read.
+/// The `ETag` is weak, because the same file can be sent as it is, precompressed, or compressed on the fly,
+/// and the bytes of these responses differ.
+fn validators(len: u64, last_modified: Option<DateTime<Utc>>) -> (EntityTag, Option<HttpDate>) {
+    let modified = last_modified.map_or(0, |date| {
+        date.timestamp_nanos_opt()
+            .unwrap_or_else(|| date.timestamp())
+    });
+    let etag = EntityTag::new_weak(format!("{len:x}-{modified:x}"));
+    // HTTP dates have a precision of one second
+    let last_modified = last_modified.map(|date| HttpDate::from(SystemTime::from(date)));
+    (etag, last_modified)
+}
+
+fn response_with_validators(
+    etag: EntityTag,
+    last_modified: Option<HttpDate>,
+    encoding: Option<ContentEncoding>,
+) -> HttpResponseBuilder {
+    let mut response = HttpResponse::build(StatusCode::OK);
+    response
+        .insert_header(ETag(etag))
+        .insert_header((header::ACCEPT_RANGES, "bytes"));
+    if let Some(date) = last_modified {
+        response.insert_header(LastModified(date));
+    }
+    if let Some(encoding) = encoding {
+        response.insert_header(encoding);
+    }
+    response
+}
+
+fn not_modified(
+    etag: EntityTag,
+    last_modified: Option<HttpDate>,
+    encoding: Option<ContentEncoding>,
+) -> HttpResponse {
+    let mut response = response_with_validators(etag, last_modified, encoding);
+    vary_accept_encoding(response.status(StatusCode::NOT_MODIFIED).finish())
+}
+
+fn is_conditional(request: &HttpRequest) -> bool {
+    let headers = request.headers();
+    headers.contains_key(header::IF_NONE_MATCH) || headers.contains_key(header::IF_MODIFIED_SINCE)
+}
+
+/// `If-None-Match` takes precedence over `If-Modified-Since`
+fn is_modified(request: &HttpRequest, etag: &EntityTag, last_modified: Option<HttpDate>) -> bool {
+    if request.headers().contains_key(header::IF_NONE_MATCH) {
+        return match IfNoneMatch::parse(request) {
+            Ok(IfNoneMatch::Any) => false,
+            Ok(IfNoneMatch::Items(tags)) => !tags.iter().any(|tag| tag.weak_eq(etag)),
+            Err(_) => true,
+        };
+    }
+    match (IfModifiedSince::parse(request), last_modified) {
+        (Ok(IfModifiedSince(since)), Some(modified)) => {
+            SystemTime::from(modified) > SystemTime::from(since)
+        }
+        _ => true,
+    }
+}
+
+/// Only single ranges are supported. Requests for several ranges get the entire file.
+fn requested_range(
+    request: &HttpRequest,
+    etag: &EntityTag,
+    last_modified: Option<HttpDate>,
+) -> Option<ByteRangeSpec> {
+    let Ok(Range::Bytes(mut ranges)) = Range::parse(request) else {
+        return None;
+    };
+    // If-Range asks for the entire file if it changed since the client got the first part
+    let unchanged = match IfRange::parse(request) {
+        Ok(IfRange::EntityTag(tag)) => tag.strong_eq(etag),
+        Ok(IfRange::Date(date)) => last_modified == Some(date),
+        Err(_) => request.headers().get(header::IF_RANGE).is_none(),
+    };
+    if ranges.len() == 1 && unchanged {
+        ranges.pop()
+    } else {
+        None
+    }
+}
+
+async fn send(
+    mut response: HttpResponseBuilder,
+    file: OpenedFile,
+    start: u64,
+    len: u64,
+) -> anyhow::Result<HttpResponse> {
+    Ok(match file {
+        OpenedFile::InMemory(mut contents) => {
+            let start = usize::try_from(start)?;
+            let end = start + usize::try_from(len)?;
+            contents.truncate(end);
+            contents.drain(..start);
+            response.body(contents)
+        }
+        OpenedFile::Local { mut file, .. } => {
+            file.seek(std::io::SeekFrom::Start(start))
+                .await
+                .with_context(|| format!("Unable to seek to byte {start}"))?;
+            let stream = tokio_util::io::ReaderStream::new(file.take(len));
+            response.body(SizedStream::new(len, stream))
+        }
+    })
+}
@@ -0,0 +1,2 @@
+select 'cache' as component, 60 as max_age, $region as vary;
+select 'text' as component, sqlpage.random_string(20) as contents;
@@ -0,0 +1,5 @@
+-- Used by test_download in tests/index.rs
+select 'download' as component, 'report.csv' as filename, 'a,b' as contents where $source = 'text';
+select 'download' as component, 'tests/it_works.txt' as file, true as inline where $source = 'file';
+select 'download' as component, 'tests/download.sql' as file where $source = 'sql';
+select 'download' as component, 'tests/../sqlpage/sqlpage.json' as file where $source = 'parent';
@@ -0,0 +1,4 @@
+-- Used by test_flash_messages in tests/index.rs
+select 'flash' as component, 'Saved' as title, 'success' as color where $save is not null;
+select 'redirect' as component, 'flash.sql' as link where $save is not null;
+select 'text' as component, 'The shell displays the flash messages' as contents;
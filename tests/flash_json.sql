@@ -0,0 +1,2 @@
+-- Used by test_flash_messages in tests/index.rs
+select 'json' as component, coalesce(sqlpage.flash_messages(), 'none') as contents;
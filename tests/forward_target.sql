@@ -0,0 +1,7 @@
+-- The target of tests/sql_test_files/it_works_forward.sql and of test_forward in tests/index.rs
+select 'status_code' as component, 404 as status where $not_found = 'yes';
+select 'text' as component,
+    CASE
+        WHEN $x = '1' THEN 'It ' || $target
+        ELSE 'error: the request variables were not kept'
+    END as contents;
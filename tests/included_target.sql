@@ -0,0 +1,2 @@
+-- Included by tests/sql_test_files/it_works_include.sql
+set target = CASE WHEN $greeting = 'It' THEN 'works !' ELSE 'error: the including file variables are not visible' END;
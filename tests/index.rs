@@ -3,7 +3,7 @@ use std::{collections::HashMap, path::PathBuf};
 use actix_web::{
     body::MessageBody,
     dev::{fn_service, ServerHandle, ServiceRequest, ServiceResponse},
-    http::{self, header::ContentType, StatusCode},
+    http::{self, header, header::ContentType, StatusCode},
     test::{self, TestRequest},
     HttpResponse,
 };
@@ -346,6 +346,29 @@ async fn test_upload_file_data_url() -> actix_web::Result<()> {
     Ok(())
 }
 
+#[actix_web::test]
+async fn test_uploaded_file_bytes() -> actix_web::Result<()> {
+    let mut payload = b"--1234567890\r\n\
+        Content-Disposition: form-data; name=\"my_file\"; filename=\"image.bin\"\r\n\
+        Content-Type: application/octet-stream\r\n\
+        \r\n"
+        .to_vec();
+    // bytes that are not valid UTF-8, and would be mangled by a conversion to text
+    payload.extend_from_slice(&[0, 159, 146, 150, 255]);
+    payload.extend_from_slice(b"\r\n--1234567890--\r\n");
+    let req = get_request_to("/tests/upload_file_bytes_test.sql")
+        .await?
+        .insert_header(("content-type", "multipart/form-data; boundary=1234567890"))
+        .set_payload(payload)
+        .to_srv_request();
+    let resp = main_handler(req).await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = test::read_body(resp).await;
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    assert_eq!(body_str, "data:application/octet-stream;base64,AJ+Slv8=");
+    Ok(())
+}
+
 #[actix_web::test]
 async fn test_uploaded_file_name() -> actix_web::Result<()> {
     let req = get_request_to("/tests/uploaded_file_name_test.sql")
@@ -368,6 +391,141 @@ async fn test_uploaded_file_name() -> actix_web::Result<()> {
     Ok(())
 }
 
+const TWO_PHOTOS_PAYLOAD: &str = "--1234567890\r\n\
+    Content-Disposition: form-data; name=\"photos[]\"; filename=\"cat.png\"\r\n\
+    Content-Type: image/png\r\n\
+    \r\n\
+    cat\r\n\
+    --1234567890\r\n\
+    Content-Disposition: form-data; name=\"photos[]\"; filename=\"dog.png\"\r\n\
+    Content-Type: image/png\r\n\
+    \r\n\
+    dog\r\n\
+    --1234567890--\r\n";
+
+#[actix_web::test]
+async fn test_uploaded_file_name_multiple() -> actix_web::Result<()> {
+    let req = get_request_to("/tests/uploaded_file_name_last_test.sql")
+        .await?
+        .insert_header(("content-type", "multipart/form-data; boundary=1234567890"))
+        .set_payload(TWO_PHOTOS_PAYLOAD)
+        .to_srv_request();
+    let resp = main_handler(req).await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = test::read_body(resp).await;
+    assert_eq!(body, "dog.png");
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_uploaded_files() -> actix_web::Result<()> {
+    let req = get_request_to("/tests/uploaded_files_test.sql")
+        .await?
+        .insert_header(("content-type", "multipart/form-data; boundary=1234567890"))
+        .set_payload(TWO_PHOTOS_PAYLOAD)
+        .to_srv_request();
+    let resp = main_handler(req).await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = test::read_body(resp).await;
+    let files: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+    let names: Vec<_> = files.iter().map(|f| &f["name"]).collect();
+    assert_eq!(names, ["cat.png", "dog.png"]);
+    assert!(files.iter().all(|f| f["mime_type"] == "image/png"));
+    assert!(files.iter().all(|f| f["path"].is_string()), "{files:?}");
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_persist_uploaded_files_checks_all_extensions() -> actix_web::Result<()> {
+    let folder = std::path::Path::new("target/test_persisted_photos");
+    let _ = std::fs::remove_dir_all(folder);
+    let req = get_request_to("/tests/persist_uploaded_files_test.sql")
+        .await?
+        .insert_header(("content-type", "multipart/form-data; boundary=1234567890"))
+        .set_payload(
+            "--1234567890\r\n\
+            Content-Disposition: form-data; name=\"photos[]\"; filename=\"cat.png\"\r\n\
+            Content-Type: image/png\r\n\
+            \r\n\
+            cat\r\n\
+            --1234567890\r\n\
+            Content-Disposition: form-data; name=\"photos[]\"; filename=\"virus.exe\"\r\n\
+            Content-Type: application/octet-stream\r\n\
+            \r\n\
+            virus\r\n\
+            --1234567890--\r\n",
+        )
+        .to_srv_request();
+    let resp = main_handler(req).await?;
+    let body = test::read_body(resp).await;
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body_str.contains("exe is not allowed"), "{body_str}");
+    // The valid file is not saved either
+    assert!(!folder.exists(), "{folder:?} should not have been created");
+    Ok(())
+}
+
+/// Runs against the MinIO server of docker-compose.yml, only when `S3_ENDPOINT` is set:
+/// `docker compose --profile minio up -d && S3_ENDPOINT=http://localhost:9000 cargo test s3`
+#[actix_web::test]
+async fn test_persist_uploaded_file_to_s3() -> actix_web::Result<()> {
+    let Ok(endpoint) = std::env::var("S3_ENDPOINT") else {
+        return Ok(());
+    };
+    let mut config = test_config();
+    config.upload_storage = sqlpage::app_config::UploadStorage::S3;
+    config.s3_endpoint = Some(endpoint);
+    config.s3_bucket = Some("sqlpage".into());
+    config.s3_access_key_id = Some("root".into());
+    config.s3_secret_access_key = Some("Password123!".into());
+    let app_data = make_app_data_from_config(config).await;
+    let req = test::TestRequest::post()
+        .uri("/tests/persist_uploaded_file_s3_test.sql")
+        .app_data(app_data.clone())
+        .insert_header(("content-type", "multipart/form-data; boundary=1234567890"))
+        .set_payload(
+            "--1234567890\r\n\
+            Content-Disposition: form-data; name=\"my_file\"; filename=\"hello.txt\"\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            Hello, bucket!\r\n\
+            --1234567890--\r\n",
+        )
+        .to_srv_request();
+    let resp = main_handler(req).await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let path = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(path.starts_with("/uploads/s3_test/"), "{path}");
+    assert!(
+        !std::path::Path::new(&path[1..]).exists(),
+        "{path} should only be in the bucket"
+    );
+
+    let get = |headers: Vec<(header::HeaderName, &'static str)>| {
+        let mut req = test::TestRequest::get()
+            .uri(&path.replace(' ', "%20"))
+            .app_data(app_data.clone());
+        for header in headers {
+            req = req.insert_header(header);
+        }
+        main_handler(req.to_srv_request())
+    };
+    let resp = get(vec![]).await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await, "Hello, bucket!");
+    let resp = get(vec![(header::RANGE, "bytes=7-12")]).await?;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(test::read_body(resp).await, "bucket");
+
+    let missing = test::TestRequest::get()
+        .uri("/uploads/s3_test/missing.txt")
+        .app_data(app_data.clone())
+        .to_srv_request();
+    let resp = main_handler(missing).await.unwrap_err().error_response();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    Ok(())
+}
+
 #[actix_web::test]
 async fn test_csv_upload() -> actix_web::Result<()> {
     let req = get_request_to("/tests/upload_csv_test.sql")
@@ -421,6 +579,173 @@ async fn test_static_files() {
     assert_eq!(&body, &b"It works !"[..]);
 }
 
+#[actix_web::test]
+async fn test_static_files_caching_and_ranges() {
+    let get = |headers: &[(header::HeaderName, String)]| {
+        let headers = headers.to_vec();
+        async move {
+            let mut req = get_request_to("/tests/it_works.txt").await.unwrap();
+            for header in headers {
+                req = req.insert_header(header);
+            }
+            main_handler(req.to_srv_request()).await.unwrap()
+        }
+    };
+    let resp = get(&[]).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+    let etag = resp.headers().get(header::ETAG).unwrap().to_str().unwrap();
+    let etag = etag.to_owned();
+    assert!(etag.starts_with("W/"), "{etag}");
+    let last_modified = resp.headers().get(header::LAST_MODIFIED).unwrap();
+    let last_modified = last_modified.to_str().unwrap().to_owned();
+
+    let resp = get(&[(header::IF_NONE_MATCH, etag.clone())]).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    let resp = get(&[(header::IF_NONE_MATCH, "\"other\"".into())]).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = get(&[(header::IF_MODIFIED_SINCE, last_modified)]).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    let resp = get(&[(header::RANGE, "bytes=3-7".into())]).await;
+    assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        resp.headers().get(header::CONTENT_RANGE).unwrap(),
+        "bytes 3-7/10"
+    );
+    assert_eq!(test::read_body(resp).await, "works");
+    let resp = get(&[(header::RANGE, "bytes=-1".into())]).await;
+    assert_eq!(test::read_body(resp).await, "!");
+    let resp = get(&[(header::RANGE, "bytes=20-".into())]).await;
+    assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        resp.headers().get(header::CONTENT_RANGE).unwrap(),
+        "bytes */10"
+    );
+    // the file changed since the client got its first part: send all of it
+    let resp = get(&[
+        (header::RANGE, "bytes=3-7".into()),
+        (header::IF_RANGE, "\"other\"".into()),
+    ])
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await, "It works !");
+    // weak entity tags cannot be used to resume a download
+    let resp = get(&[
+        (header::RANGE, "bytes=3-7".into()),
+        (header::IF_RANGE, etag),
+    ])
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_precompressed_static_files() {
+    let get = |accept_encoding: &'static str| async move {
+        let req = get_request_to("/tests/precompressed.txt")
+            .await
+            .unwrap()
+            .insert_header((header::ACCEPT_ENCODING, accept_encoding));
+        main_handler(req.to_srv_request()).await.unwrap()
+    };
+    let resp = get("gzip, deflate, br").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers().get(header::CONTENT_ENCODING).unwrap(),
+        "gzip"
+    );
+    assert_eq!(
+        resp.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/plain"
+    );
+    assert_eq!(resp.headers().get(header::VARY).unwrap(), "accept-encoding");
+    let expected = std::fs::read("tests/precompressed.txt.gz").unwrap();
+    assert_eq!(test::read_body(resp).await, expected);
+
+    let resp = get("identity").await;
+    assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+    assert_eq!(test::read_body(resp).await, "Precompressed assets work !");
+}
+
+#[actix_web::test]
+async fn test_watch_files() {
+    let web_root = std::env::temp_dir().join("sqlpage_test_watch_files");
+    std::fs::create_dir_all(&web_root).unwrap();
+    let page = web_root.join("page.sql");
+    let write_page = |version: &str| {
+        let sql = format!("select 'text' as component, '{version}' as contents;");
+        std::fs::write(&page, sql).unwrap();
+    };
+    write_page("version 1");
+    let mut config = test_config();
+    config.web_root = web_root.clone();
+    config.environment = sqlpage::app_config::DevOrProd::Production;
+    config.watch_files = true;
+    let app_data = make_app_data_from_config(config).await;
+    let get_page = || async {
+        let resp = req_path_with_app_data("/page.sql", app_data.clone())
+            .await
+            .unwrap();
+        String::from_utf8(test::read_body(resp).await.to_vec()).unwrap()
+    };
+    assert!(get_page().await.contains("version 1"));
+
+    write_page("version 2");
+    let mut body = String::new();
+    for _ in 0..50 {
+        body = get_page().await;
+        if body.contains("version 2") {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    std::fs::remove_dir_all(&web_root).unwrap();
+    assert!(body.contains("version 2"), "{body}");
+}
+
+#[actix_web::test]
+async fn test_precompile_files() {
+    use sqlpage::app_config::Precompilation;
+    let web_root = std::env::temp_dir().join("sqlpage_test_precompile_files");
+    std::fs::create_dir_all(web_root.join("sqlpage")).unwrap();
+    std::fs::write(web_root.join("page.sql"), "select 'text' as component;").unwrap();
+    std::fs::write(web_root.join("broken.sql"), "selct 'text' as component;").unwrap();
+    // files in sqlpage/ cannot be requested, and are not precompiled
+    std::fs::write(web_root.join("sqlpage").join("ignored.sql"), "not sql").unwrap();
+    let mut config = test_config();
+    config.web_root = web_root.clone();
+    config.configuration_directory = web_root.join("sqlpage");
+    config.environment = sqlpage::app_config::DevOrProd::Production;
+
+    config.precompile_files = Precompilation::Abort;
+    let err = AppState::init(&config).await.unwrap_err();
+    let message = format!("{err:#}");
+
+    config.precompile_files = Precompilation::Warn;
+    let app_data = actix_web::web::Data::new(AppState::init(&config).await.unwrap());
+    std::fs::remove_dir_all(&web_root).unwrap();
+    assert!(message.contains("1 files contain errors"), "{message}");
+    assert!(message.contains("broken.sql"), "{message}");
+    // the page was parsed at startup, and is served from the cache
+    let resp = req_path_with_app_data("/page.sql", app_data).await.unwrap();
+    assert_eq!(resp.status(), http::StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_pagination_links() {
+    let resp = req_path("/tests/paginated_table.sql?q=x&page=7")
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), http::StatusCode::OK);
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    // the page past the end shows the last page
+    assert!(body.contains("Page 2 of 2 (3 rows)"), "{body}");
+    assert!(body.contains("align-middle\">3<"), "{body}");
+    assert!(body.contains("?q&#x3D;x&amp;page&#x3D;1"), "{body}");
+    // the variables set by the page are not in the links
+    assert!(!body.contains("secret_token"), "{body}");
+}
+
 #[actix_web::test]
 async fn test_with_site_prefix() {
     let mut config = test_config();
@@ -443,6 +768,421 @@ async fn test_with_site_prefix() {
     );
 }
 
+#[actix_web::test]
+async fn test_max_query_duration() {
+    let mut config = test_config();
+    config.max_query_duration_seconds = Some(0.05);
+    let app_data = make_app_data_from_config(config).await;
+    if app_data.db.to_string().contains("Mssql") {
+        // slow_query.sql uses WITH RECURSIVE, which SQL Server does not support.
+        // SQL Server also stops recursive queries after 100 levels, so the query would fail before timing out.
+        return;
+    }
+    let resp = req_path_with_app_data("/tests/slow_query.sql", app_data.clone())
+        .await
+        .unwrap();
+    let body = test::read_body(resp).await;
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    assert!(
+        body_str.contains("max_query_duration_seconds"),
+        "{body_str}\nexpected the query to be cancelled"
+    );
+    if app_data.db.to_string().contains("Postgres") {
+        // The statement is also cancelled on the server
+        for _ in 0..20 {
+            let resp = req_path_with_app_data("/tests/running_slow_queries.sql", app_data.clone())
+                .await
+                .unwrap();
+            let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+            if body.contains("running: 0") {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("The slow query is still running on the server");
+    }
+}
+
+#[actix_web::test]
+async fn test_list_parameters() {
+    let app_data = make_app_data().await;
+    let resp = req_path_with_app_data(
+        "/tests/list_parameters.sql?ids[]=Apple&ids[]=Cherry",
+        app_data,
+    )
+    .await
+    .unwrap();
+    let body = test::read_body(resp).await;
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    assert!(
+        body_str.contains("Apple") && body_str.contains("Cherry") && !body_str.contains("Banana"),
+        "{body_str}\nexpected only the rows in the list"
+    );
+}
+
+#[actix_web::test]
+async fn test_forward() {
+    let resp = req_path("/tests/sql_test_files/it_works_forward.sql?x=1&not_found=yes")
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let body = test::read_body(resp).await;
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body_str.contains("It works !"), "{body_str}");
+}
+
+#[actix_web::test]
+async fn test_validate_component() -> actix_web::Result<()> {
+    let post = |form: &'static [(&'static str, &'static str)]| async move {
+        let req = get_request_to("/tests/validate_form.sql")
+            .await?
+            .method(http::Method::POST)
+            .set_form(HashMap::<&str, &str>::from_iter(form.iter().copied()))
+            .to_srv_request();
+        let resp = main_handler(req).await?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = test::read_body(resp).await;
+        Ok::<_, actix_web::Error>(String::from_utf8(body.to_vec()).unwrap())
+    };
+    let valid = post(&[("email", "a@example.com"), ("age", "30")]).await?;
+    assert!(valid.contains("Saved a@example.com"), "{valid}");
+    assert!(!valid.contains("is-invalid"), "{valid}");
+
+    let invalid = post(&[("email", "not an email"), ("age", "12")]).await?;
+    assert!(!invalid.contains("Saved"), "{invalid}");
+    assert!(
+        invalid.contains("Please enter an email address."),
+        "{invalid}"
+    );
+    assert!(
+        invalid.contains("The value must be greater than or equal to 18."),
+        "{invalid}"
+    );
+    assert!(invalid.contains(r#"value="not an email""#), "{invalid}");
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_flash_messages() {
+    let app_data = make_app_data().await;
+    let resp = req_path_with_app_data("/tests/flash.sql?save=1", app_data.clone())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    let cookie = resp
+        .response()
+        .cookies()
+        .find(|c| c.name() == "sqlpage_flash")
+        .expect("the flash component sets a cookie")
+        .into_owned();
+
+    let req = test::TestRequest::get()
+        .uri("/tests/flash.sql")
+        .cookie(cookie.clone())
+        .app_data(app_data.clone())
+        .to_srv_request();
+    let resp = main_handler(req).await.unwrap();
+    let removed = resp
+        .response()
+        .cookies()
+        .find(|c| c.name() == "sqlpage_flash")
+        .expect("the displayed messages are removed");
+    assert_eq!(removed.value(), "");
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(
+        body.contains(r#"<h4 class="alert-title">Saved</h4>"#),
+        "{body}"
+    );
+    assert!(body.contains("alert-success"), "{body}");
+
+    // Pages that display flash messages are specific to the user, and never cached
+    let req = test::TestRequest::get()
+        .uri("/tests/cached_page.sql?region=flash")
+        .cookie(cookie.clone())
+        .app_data(app_data.clone())
+        .to_srv_request();
+    let resp = main_handler(req).await.unwrap();
+    assert_eq!(
+        resp.headers().get(header::CACHE_CONTROL).unwrap(),
+        "no-store"
+    );
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("Saved"), "{body}");
+    let resp = req_path_with_app_data("/tests/cached_page.sql?region=flash", app_data.clone())
+        .await
+        .unwrap();
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(!body.contains("Saved"), "{body}");
+
+    // Pages that read the messages with sqlpage.flash_messages() display them themselves, once
+    let req = test::TestRequest::get()
+        .uri("/tests/flash_json.sql")
+        .cookie(cookie.clone())
+        .app_data(app_data.clone())
+        .to_srv_request();
+    let resp = main_handler(req).await.unwrap();
+    let removed = resp
+        .response()
+        .cookies()
+        .find(|c| c.name() == "sqlpage_flash")
+        .expect("the messages that were read are removed");
+    assert_eq!(removed.value(), "");
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert_eq!(body.matches("Saved").count(), 1, "{body}");
+
+    let mut forged = cookie;
+    forged.set_value(forged.value().replace('.', "x."));
+    let req = test::TestRequest::get()
+        .uri("/tests/flash_json.sql")
+        .cookie(forged.clone())
+        .app_data(app_data.clone())
+        .to_srv_request();
+    let body = test::read_body(main_handler(req).await.unwrap()).await;
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("[]"), "{body}");
+    let req = test::TestRequest::get()
+        .uri("/tests/flash.sql")
+        .cookie(forged)
+        .app_data(app_data)
+        .to_srv_request();
+    let body = test::read_body(main_handler(req).await.unwrap()).await;
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(!body.contains("alert-title"), "{body}");
+}
+
+#[actix_web::test]
+async fn test_download() {
+    let resp = req_path("/tests/download.sql?source=text").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        resp.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/csv"
+    );
+    assert_eq!(
+        resp.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+        "attachment; filename=\"report.csv\""
+    );
+    assert_eq!(test::read_body(resp).await, "a,b");
+
+    let resp = req_path("/tests/download.sql?source=file").await.unwrap();
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(
+        body.contains("can only send files from the uploads directory"),
+        "{body}"
+    );
+
+    let mut config = test_config();
+    config.uploads_directory = "tests".to_string();
+    let app_data = make_app_data_from_config(config).await;
+    let download = |source: &str| {
+        req_path_with_app_data(
+            format!("/tests/download.sql?source={source}"),
+            app_data.clone(),
+        )
+    };
+    let resp = download("file").await.unwrap();
+    assert_eq!(
+        resp.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/plain"
+    );
+    assert_eq!(
+        resp.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+        "inline"
+    );
+    let expected = std::fs::read("tests/it_works.txt").unwrap();
+    assert_eq!(test::read_body(resp).await, expected);
+
+    let resp = download("sql").await.unwrap();
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(
+        body.contains("cannot send the source of SQL files"),
+        "{body}"
+    );
+
+    let resp = download("parent").await.unwrap();
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(
+        body.contains("can only send files from the tests directory"),
+        "{body}"
+    );
+}
+
+#[actix_web::test]
+async fn test_cache_component() {
+    let app_data = make_app_data().await;
+    let get = |path: &'static str| {
+        let app_data = app_data.clone();
+        async move {
+            let resp = req_path_with_app_data(path, app_data).await.unwrap();
+            let etag = resp.headers().get(header::ETAG).cloned();
+            let csp = resp.headers().get(header::CONTENT_SECURITY_POLICY).cloned();
+            let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+            if let Some(nonce) = body.split("nonce=\"").nth(1) {
+                let nonce = nonce.split('"').next().unwrap();
+                let csp = csp.expect("the page has a content security policy");
+                assert!(csp.to_str().unwrap().contains(nonce), "{csp:?}");
+            }
+            (body, etag)
+        }
+    };
+    let (first, first_etag) = get("/tests/cached_page.sql?region=eu").await;
+    let (second, etag) = get("/tests/cached_page.sql?region=eu").await;
+    assert_eq!(
+        first_etag, etag,
+        "the rendered page has the ETag of its cached version"
+    );
+    assert_eq!(
+        without_nonces(&first),
+        without_nonces(&second),
+        "the second response comes from the cache"
+    );
+    assert_ne!(first, second, "each response has its own nonce");
+    let (other_region, _) = get("/tests/cached_page.sql?region=us").await;
+    assert_ne!(
+        without_nonces(&first),
+        without_nonces(&other_region),
+        "each value of vary is cached separately"
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/tests/cached_page.sql?region=eu")
+        .insert_header((
+            header::IF_NONE_MATCH,
+            etag.expect("cached responses have an ETag"),
+        ))
+        .app_data(app_data.clone())
+        .to_srv_request();
+    let resp = main_handler(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    let (purged, _) = get("/tests/purge_cache.sql").await;
+    assert!(purged.contains("purged 2"), "{purged}");
+    let (after_purge, _) = get("/tests/cached_page.sql?region=eu").await;
+    assert_ne!(without_nonces(&first), without_nonces(&after_purge));
+}
+
+fn without_nonces(body: &str) -> String {
+    body.split("nonce=\"")
+        .enumerate()
+        .map(|(i, part)| {
+            if i == 0 {
+                part
+            } else {
+                part.split_once('"').unwrap().1
+            }
+        })
+        .collect()
+}
+
+#[actix_web::test]
+async fn test_read_replica() {
+    use sqlx::{Connection, Executor};
+    let dir = std::env::temp_dir().join("sqlpage_test_read_replica");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let url = |name: &str| format!("sqlite://{}?mode=rwc", dir.join(name).display());
+    for name in ["primary", "replica"] {
+        let mut conn = sqlx::AnyConnection::connect(&url(name)).await.unwrap();
+        conn.execute(&*format!(
+            "CREATE TABLE whoami (name TEXT); INSERT INTO whoami VALUES ('{name}');
+             CREATE TABLE visits (page TEXT);"
+        ))
+        .await
+        .unwrap();
+    }
+    let mut config = test_config();
+    config.database_url = url("primary");
+    config.database_replica_urls = vec![url("replica")];
+    let app_data = make_app_data_from_config(config).await;
+    let resp = req_path_with_app_data("/tests/read_replica.sql", app_data.clone())
+        .await
+        .unwrap();
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains("first read from replica"), "{body}");
+    // Once the page has written to the primary, it keeps reading from it, to see its own writes
+    assert!(body.contains("read after write from primary"), "{body}");
+    app_data.db.close().await.unwrap();
+
+    let mut visits = Vec::new();
+    for name in ["primary", "replica"] {
+        let mut conn = sqlx::AnyConnection::connect(&url(name)).await.unwrap();
+        let (count,): (i64,) = sqlx::query_as("SELECT count(*) FROM visits")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        visits.push(count);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(visits, [1, 0], "the write should only go to the primary");
+}
+
+#[actix_web::test]
+async fn test_named_database() {
+    let mut config = test_config();
+    config
+        .databases
+        .insert("other".to_string(), "sqlite::memory:".to_string());
+    let app_data = make_app_data_from_config(config).await;
+    let resp = req_path_with_app_data("/tests/named_database.sql", app_data)
+        .await
+        .unwrap();
+    let body = test::read_body(resp).await;
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body_str.contains("It works ! SQLite"), "{body_str}");
+
+    let resp = req_path("/tests/named_database.sql").await.unwrap();
+    let body = test::read_body(resp).await;
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body_str.contains("no such database"), "{body_str}");
+}
+
+#[actix_web::test]
+async fn test_named_database_directory() {
+    let mut config = test_config();
+    config
+        .databases
+        .insert("other".to_string(), "sqlite::memory:".to_string());
+    config.database_directories.insert(
+        "tests/named_database_directory".to_string(),
+        "other".to_string(),
+    );
+    let app_data = make_app_data_from_config(config).await;
+    let resp = req_path_with_app_data("/tests/named_database_directory/version.sql", app_data)
+        .await
+        .unwrap();
+    let body = test::read_body(resp).await;
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body_str.contains("It works ! SQLite"), "{body_str}");
+}
+
+#[actix_web::test]
+async fn test_run_sql_named_database() {
+    let mut config = test_config();
+    config
+        .databases
+        .insert("other".to_string(), "sqlite::memory:".to_string());
+    let app_data = make_app_data_from_config(config).await;
+    let resp = req_path_with_app_data(
+        "/tests/run_sql_named_database.sql?database=other",
+        app_data.clone(),
+    )
+    .await
+    .unwrap();
+    let body = test::read_body(resp).await;
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body_str.contains("It works ! SQLite"), "{body_str}");
+
+    let resp = req_path_with_app_data(
+        "/tests/run_sql_named_database.sql?database=missing",
+        app_data,
+    )
+    .await
+    .unwrap();
+    let body = test::read_body(resp).await;
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body_str.contains("no such database"), "{body_str}");
+}
+
 async fn make_app_data_for_official_website() -> actix_web::web::Data<AppState> {
     init_log();
     let config_path = std::path::Path::new("examples/official-site/sqlpage");
@@ -0,0 +1,8 @@
+select 'list' as component;
+select x as title
+from (
+    select 'Apple' as x
+    union all select 'Banana'
+    union all select 'Cherry'
+) as t
+where x in ($ids);
@@ -0,0 +1,2 @@
+-- @database other
+select 'text' as component, 'It works ! SQLite ' || sqlite_version() as contents;
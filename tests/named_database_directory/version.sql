@@ -0,0 +1 @@
+select 'text' as component, 'It works ! SQLite ' || sqlite_version() as contents;
@@ -0,0 +1,4 @@
+set token = 'secret_token';
+select 'table' as component;
+-- @paginate 2
+select n from (select 1 as n union all select 2 union all select 3) as numbers order by n;
@@ -0,0 +1,3 @@
+-- Used by test_persist_uploaded_file_to_s3 in tests/index.rs
+select 'shell-empty' as component,
+    sqlpage.persist_uploaded_file('my_file', 'uploads/s3_test', 'txt') as html;
@@ -0,0 +1,3 @@
+-- Used by test_persist_uploaded_files_checks_all_extensions in tests/index.rs
+select 'text' as component,
+    sqlpage.persist_uploaded_files('photos[]', 'target/test_persisted_photos', 'png') as contents;
@@ -0,0 +1 @@
+select 'text' as component, 'purged ' || sqlpage.purge_cache('/tests/cached_page.sql') as contents;
@@ -0,0 +1,5 @@
+-- Used by test_read_replica in tests/index.rs. The primary and the replica both have a `whoami` table,
+-- containing their own name, and a `visits` table.
+select 'text' as component, 'first read from ' || name as contents from whoami;
+insert into visits (page) values ('read_replica.sql');
+select 'text' as component, 'read after write from ' || name as contents from whoami;
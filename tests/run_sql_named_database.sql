@@ -0,0 +1 @@
+select 'dynamic' as component, sqlpage.run_sql('tests/named_database_directory/version.sql', null, $database) as properties;
@@ -0,0 +1,4 @@
+-- Used by test_max_query_duration in tests/index.rs, on PostgreSQL
+select 'text' as component, 'running: ' || count(*) as contents
+from pg_stat_activity
+where state = 'active' and query like '%n < 100000000%' and pid <> pg_backend_pid();
@@ -0,0 +1,6 @@
+with recursive t(n) as (
+    select 1
+    union all
+    select n + 1 from t where n < 100000000
+)
+select 'text' as component, count(*) as contents from t;
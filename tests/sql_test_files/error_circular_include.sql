@@ -0,0 +1,2 @@
+-- This file includes itself
+-- @include './tests/sql_test_files/error_circular_include.sql'
@@ -0,0 +1,2 @@
+set answer = 'maybe';
+select 'text' as component, CAST($answer AS BOOLEAN) as contents;
@@ -0,0 +1 @@
+select 'text' as component, sqlpage.url_encode(sqlpage.uploaded_file_bytes('my_file')) as contents;
@@ -0,0 +1,4 @@
+-- A variable with a single value is an array literal, that ANY($x::int[]) casts to an array
+set ids = '{1,2,3}';
+select 'text' as component,
+    case when 2 = any($ids::int[]) and 4 <> all($ids::int[]) then 'It works !' else 'error' end as contents;
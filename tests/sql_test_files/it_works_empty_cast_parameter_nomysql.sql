@@ -0,0 +1,4 @@
+-- Empty values are bound as NULL. SQLite used to cast them to 0.
+set empty = '';
+select 'text' as component,
+    case when CAST($empty AS INTEGER) IS NULL then 'It works !' else 'error' end as contents;
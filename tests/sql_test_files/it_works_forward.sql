@@ -0,0 +1,2 @@
+select 'forward' as component, 'tests/forward_target.sql' as path, '{"target": "works !"}' as variables;
+select 'text' as component, 'error: the statements after forward were executed' as contents;
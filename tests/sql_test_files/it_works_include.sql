@@ -0,0 +1,3 @@
+set greeting = 'It';
+-- @include 'tests/included_target.sql'
+select 'text' as component, $greeting || ' ' || $target as contents;
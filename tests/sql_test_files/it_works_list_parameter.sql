@@ -0,0 +1,2 @@
+select 'text' as component,
+    case when '1' in ($x) and '2' not in ($x) then 'It works !' else 'error' end as contents;
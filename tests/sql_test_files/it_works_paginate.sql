@@ -0,0 +1,6 @@
+set page = 2;
+select 'text' as component;
+-- @paginate 2
+select case n when 3 then 'It works !' else 'error: wrong page' end as contents
+from (select 1 as n union all select 2 union all select 3) as numbers
+order by n;
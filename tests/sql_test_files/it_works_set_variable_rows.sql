@@ -0,0 +1,9 @@
+set (greeting, target) = (select 'It', 'works !');
+set numbers[] = (select 1 as n union all select 2);
+set users[] = (select 1 as id, 'Alice' as name);
+select 'text' as component,
+    CASE
+        WHEN $numbers = '[1,2]' AND $users = '[{"id":1,"name":"Alice"}]'
+        THEN $greeting || ' ' || $target
+        ELSE 'error: numbers=' || $numbers || ', users=' || $users
+    END as contents;
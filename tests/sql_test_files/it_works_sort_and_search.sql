@@ -0,0 +1,11 @@
+set sort = '-n';
+select 'text' as component;
+-- @sort_and_search
+-- @paginate 1
+select n, case n when 4 then 'It works !' else 'error: wrong order' end as contents
+from (select 1 as n union all select 2 union all select 4 union all select 3) as numbers;
+
+set search = 'B';
+-- @sort_and_search
+select letter, case letter when 'b' then ' ' else 'error: wrong search' end as contents
+from (select 'a' as letter union all select 'b') as letters;
@@ -0,0 +1,11 @@
+-- On SQLite, pure sqlpage functions can be used anywhere in an expression, and are evaluated on each row.
+with t(name) as (values ('A B'))
+select 'text' as component,
+    case
+        when lower(sqlpage.url_encode(name)) = 'a%20b'
+            and length(sqlpage.random_string(2 + 3)) = 5
+            and sqlpage.hash_password(name) like '$argon2%'
+        then 'It works !'
+        else 'It failed ! Got ' || sqlpage.url_encode(name)
+    end as contents
+from t;
@@ -0,0 +1,14 @@
+select 'text' as component,
+    case
+        when 'abc123' REGEXP '^[a-z]+\d+$'
+            and not ('abc' REGEXP '\d')
+            and regexp_replace('2024-10-01', '(\d+)-(\d+)-(\d+)', '$3/$2/$1') = '01/10/2024'
+            and regexp_capture('order #42', '#(\d+)') = '42'
+            and regexp_capture('order #42', '#(?<id>\d+)', 'id') = '42'
+            and regexp_capture('no number', '\d+') is null
+            and length(normalize('e' || char(769))) = 1
+            and unaccent('Élève') = 'Eleve'
+            and 'ÉLÈVE' = 'eleve' collate noaccent
+        then 'It works !'
+        else 'It failed !'
+    end as contents;
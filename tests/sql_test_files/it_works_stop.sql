@@ -0,0 +1,5 @@
+-- The tests are run with ?x=1, so this does not stop the page
+select 'stop' as component where $x is null;
+select 'text' as component, 'It works !' as contents;
+select 'stop' as component;
+select 'text' as component, 'error: the stop component did not stop the page' as contents;
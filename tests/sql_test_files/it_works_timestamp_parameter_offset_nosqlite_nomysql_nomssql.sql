@@ -0,0 +1,8 @@
+-- Timestamps are parsed by the database, with the offsets it supports and without converting them to UTC
+set t = '2024-10-01 15:30:00+02';
+set naive = '2024-10-01 15:30:00';
+select 'text' as component,
+    case when CAST($t AS TIMESTAMP) = TIMESTAMP '2024-10-01 15:30:00'
+        and CAST($t AS TIMESTAMPTZ) = TIMESTAMPTZ '2024-10-01 13:30:00+00'
+        and $naive::timestamptz = TIMESTAMPTZ '2024-10-01 15:30:00'
+        then 'It works !' else 'error' end as contents;
@@ -0,0 +1,3 @@
+set one = '1';
+select 'text' as component,
+    case when CAST($one AS INTEGER) + CAST($x AS INTEGER) = 2 then 'It works !' else 'error' end as contents;
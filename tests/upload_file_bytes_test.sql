@@ -0,0 +1,3 @@
+-- send the contents of the uploaded file to the database as binary data, and display them as a data URL
+select 'shell-empty' as component,
+    sqlpage.uploaded_file_bytes('my_file') as html;
@@ -0,0 +1,3 @@
+-- Used by test_uploaded_file_name_multiple in tests/index.rs: displays the name of the last file uploaded in the photos[] field
+select 'shell-empty' as component,
+    sqlpage.uploaded_file_name('photos[]') as html;
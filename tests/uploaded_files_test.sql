@@ -0,0 +1,3 @@
+-- Used by test_uploaded_files in tests/index.rs: describes all the files uploaded in the photos[] field
+select 'shell-empty' as component,
+    sqlpage.uploaded_files('photos[]') as html;
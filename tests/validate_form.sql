@@ -0,0 +1,10 @@
+-- Used by test_validate_component in tests/index.rs
+select 'validate' as component, 'tests/validate_form.sql' as form;
+select 'email' as name, 'email' as type, true as required;
+select 'age' as name, 'number' as type, 18 as min;
+
+select 'text' as component, 'Saved ' || :email as contents where :email is not null;
+
+select 'form' as component;
+select 'email' as name, 'email' as type, true as required;
+select 'age' as name, 'number' as type, 18 as min;